@@ -0,0 +1,366 @@
+use crate::models::Event;
+use camino::{Utf8Path, Utf8PathBuf};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use tailforward_cfg::config::{Archive as Settings, Compression, Fsync, Rotation};
+
+/// File events are appended to until it's rotated
+pub const CURRENT: &str = "events.jsonl";
+
+/// Line of the archive, one per event
+#[derive(Debug, Serialize)]
+struct Line<'a> {
+    received: DateTime<Utc>,
+    endpoint: &'a str,
+    key_id: &'a str,
+    event: &'a Event,
+}
+
+/// Verified events appended to JSON Lines files in a directory
+#[derive(Debug)]
+pub struct Archive {
+    directory: Utf8PathBuf,
+    current: Mutex<Current>,
+}
+
+/// File being appended to
+#[derive(Debug)]
+struct Current {
+    file: File,
+    /// When the file was started, or last written to if it was there on startup
+    since: DateTime<Utc>,
+    size: u64,
+    /// Set if the file was rotated but a new one couldn't be started, so that
+    /// the next events don't go to the rotated file
+    stale: bool,
+}
+
+impl Archive {
+    /// Opens the current file, creating it and the directory if missing
+    ///
+    /// # Errors
+    /// If the directory or file can't be created
+    pub fn open(directory: &Utf8Path) -> io::Result<Self> {
+        fs::create_dir_all(directory)?;
+        let current = Current::open(&directory.join(CURRENT))?;
+        Ok(Self {
+            directory: directory.to_owned(),
+            current: Mutex::new(current),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Current> {
+        self.current.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Appends events, rotating the current file first if it's due, and returns
+    /// the file rotated, still to be compressed
+    ///
+    /// # Errors
+    /// If the events can't be written or the file rotated
+    pub fn append(
+        &self,
+        settings: &Settings,
+        received: DateTime<Utc>,
+        endpoint: &str,
+        key_id: &str,
+        events: &[Event],
+    ) -> io::Result<Option<Utf8PathBuf>> {
+        let mut lines = Vec::new();
+        for event in events {
+            let line = Line {
+                received,
+                endpoint,
+                key_id,
+                event,
+            };
+            serde_json::to_writer(&mut lines, &line)?;
+            lines.push(b'\n');
+        }
+
+        let mut current = self.lock();
+        if current.stale {
+            *current = Current::open(&self.directory.join(CURRENT))?;
+        }
+        let due = current.size > 0
+            && match settings.rotation {
+                Rotation::Daily => current.since.date_naive() != received.date_naive(),
+                Rotation::Size => current.size + lines.len() as u64 > settings.max_size,
+            };
+        let rotated = if due {
+            Some(self.rotate(&mut current, settings, received)?)
+        } else {
+            None
+        };
+        current.file.write_all(&lines)?;
+        current.size += lines.len() as u64;
+        if settings.fsync == Fsync::Always {
+            current.file.sync_data()?;
+        }
+        drop(current);
+        Ok(rotated)
+    }
+
+    /// Renames the current file after what it covers and starts a new one
+    fn rotate(
+        &self,
+        current: &mut Current,
+        settings: &Settings,
+        now: DateTime<Utc>,
+    ) -> io::Result<Utf8PathBuf> {
+        if settings.fsync != Fsync::Never {
+            current.file.sync_all()?;
+        }
+        let stem = match settings.rotation {
+            Rotation::Daily => format!("events-{}", current.since.format("%Y-%m-%d")),
+            Rotation::Size => format!("events-{}", now.format("%Y-%m-%dT%H-%M-%S%.3fZ")),
+        };
+        let rotated = self.unused(&stem);
+        let path = self.directory.join(CURRENT);
+        fs::rename(&path, &rotated)?;
+        match Current::open(&path) {
+            Ok(new) => *current = new,
+            Err(error) => {
+                current.stale = true;
+                return Err(error);
+            }
+        }
+        current.since = now;
+        Ok(rotated)
+    }
+
+    /// First of `<stem>.jsonl`, `<stem>.1.jsonl`… not taken, compressed or not
+    fn unused(&self, stem: &str) -> Utf8PathBuf {
+        let mut path = self.directory.join(format!("{stem}.jsonl"));
+        let mut n = 0;
+        while ["", ".gz", ".zst"]
+            .iter()
+            .any(|extension| Utf8Path::new(&format!("{path}{extension}")).exists())
+        {
+            n += 1;
+            path = self.directory.join(format!("{stem}.{n}.jsonl"));
+        }
+        path
+    }
+}
+
+impl Current {
+    fn open(path: &Utf8Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+        let since = metadata
+            .modified()
+            .map_or_else(|_| Utc::now(), DateTime::<Utc>::from);
+        Ok(Self {
+            file,
+            since,
+            size: metadata.len(),
+            stale: false,
+        })
+    }
+}
+
+/// Compresses a rotated file next to it, then removes it, returning the
+/// compressed file; the file is kept if it can't be compressed
+///
+/// # Errors
+/// If the file can't be read or the compressed one written
+pub fn compress(
+    path: &Utf8Path,
+    compression: Compression,
+    fsync: Fsync,
+) -> io::Result<Option<Utf8PathBuf>> {
+    let extension = match compression {
+        Compression::None => return Ok(None),
+        Compression::Gzip => "gz",
+        Compression::Zstd => "zst",
+    };
+    let compressed = Utf8PathBuf::from(format!("{path}.{extension}"));
+    let result = write_compressed(path, &compressed, compression, fsync);
+    if result.is_err() {
+        let _ = fs::remove_file(&compressed);
+    }
+    result?;
+    fs::remove_file(path)?;
+    Ok(Some(compressed))
+}
+
+fn write_compressed(
+    path: &Utf8Path,
+    compressed: &Utf8Path,
+    compression: Compression,
+    fsync: Fsync,
+) -> io::Result<()> {
+    let mut input = BufReader::new(File::open(path)?);
+    let output = BufWriter::new(File::create_new(compressed)?);
+    let output = match compression {
+        Compression::None => output,
+        Compression::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?
+        }
+        Compression::Zstd => {
+            let mut encoder = zstd::Encoder::new(output, 0)?;
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?
+        }
+    };
+    let file = output
+        .into_inner()
+        .map_err(io::IntoInnerError::into_error)?;
+    if fsync != Fsync::Never {
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use test_case::test_case;
+
+    fn directory(name: &str) -> Utf8PathBuf {
+        let path = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("tailforward-archive-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        path
+    }
+
+    fn event(message: &str) -> Event {
+        Event {
+            timestamp: Utc::now(),
+            version: 1,
+            r#type: "test".to_owned(),
+            tailnet: "example.com".to_owned(),
+            message: message.to_owned(),
+            data: None,
+        }
+    }
+
+    fn lines(path: &Utf8Path) -> Vec<serde_json::Value> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn rotates_daily() {
+        let directory = directory("daily");
+        let archive = Archive::open(&directory).unwrap();
+        let settings = Settings::default();
+        let today = Utc::now();
+        let tomorrow = today + chrono::Duration::days(1);
+
+        let first = archive
+            .append(&settings, today, "", "k1", &[event("one"), event("two")])
+            .unwrap();
+        let second = archive
+            .append(&settings, tomorrow, "", "k1", &[event("three")])
+            .unwrap();
+
+        assert_eq!(first, None);
+        let rotated = second.unwrap();
+        let name = format!("events-{}.jsonl", today.format("%Y-%m-%d"));
+        assert_eq!(rotated.file_name(), Some(name.as_str()));
+        let rotated = lines(&rotated);
+        assert_eq!(rotated.len(), 2);
+        assert_eq!(rotated[0]["event"]["message"], "one");
+        assert_eq!(rotated[0]["key_id"], "k1");
+        assert_eq!(
+            lines(&directory.join(CURRENT))[0]["event"]["message"],
+            "three"
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let directory = directory("size");
+        let archive = Archive::open(&directory).unwrap();
+        let settings = Settings {
+            rotation: Rotation::Size,
+            max_size: 300,
+            ..Settings::default()
+        };
+        let now = Utc::now();
+
+        let rotated: Vec<_> = (0..6)
+            .filter_map(|n| {
+                archive
+                    .append(&settings, now, "", "k1", &[event(&n.to_string())])
+                    .unwrap()
+            })
+            .collect();
+
+        assert!(!rotated.is_empty());
+        for path in rotated.iter().chain([&directory.join(CURRENT)]) {
+            assert!(fs::metadata(path).unwrap().len() <= 300);
+        }
+        let total: usize = rotated.iter().map(|path| lines(path).len()).sum();
+        assert_eq!(total + lines(&directory.join(CURRENT)).len(), 6);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn starts_new_file_after_failed_rotation() {
+        let directory = directory("failed");
+        let archive = Archive::open(&directory).unwrap();
+        let settings = Settings::default();
+        archive
+            .append(&settings, Utc::now(), "", "k1", &[event("one")])
+            .unwrap();
+        // What `rotate` leaves behind when the new file can't be opened
+        let rotated = directory.join("events-rotated.jsonl");
+        fs::rename(directory.join(CURRENT), &rotated).unwrap();
+        archive.lock().stale = true;
+
+        archive
+            .append(&settings, Utc::now(), "", "k1", &[event("two")])
+            .unwrap();
+
+        assert_eq!(lines(&rotated).len(), 1);
+        assert_eq!(
+            lines(&directory.join(CURRENT))[0]["event"]["message"],
+            "two"
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test_case(Compression::Gzip; "when gzip")]
+    #[test_case(Compression::Zstd; "when zstd")]
+    fn compresses(compression: Compression) {
+        let directory = directory(&format!("{compression:?}"));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("events-2026-01-02.jsonl");
+        fs::write(&path, "{\"a\":1}\n").unwrap();
+
+        let compressed = compress(&path, compression, Fsync::Rotation)
+            .unwrap()
+            .unwrap();
+
+        assert!(!path.exists());
+        let file = File::open(&compressed).unwrap();
+        let mut text = String::new();
+        match compression {
+            Compression::None => unreachable!(),
+            Compression::Gzip => flate2::read::GzDecoder::new(file)
+                .read_to_string(&mut text)
+                .unwrap(),
+            Compression::Zstd => zstd::Decoder::new(file)
+                .unwrap()
+                .read_to_string(&mut text)
+                .unwrap(),
+        };
+        assert_eq!(text, "{\"a\":1}\n");
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,506 @@
+use crate::config::{self, config_file_path, new_config_with, Application, Endpoint};
+use crate::export::{export, Exporter, Format as ExportFormat};
+use crate::history::{Query, Store};
+use crate::models::event::TYPES;
+use crate::models::tailscale_header::{Signature, Version};
+use crate::models::{Event, Header};
+use crate::secret;
+use crate::services::dispatch::dispatch;
+use crate::services::post_webhook::sign;
+use crate::State;
+use camino::{Utf8Path, Utf8PathBuf};
+use chrono::{DateTime, Duration, Utc};
+use clap::{builder::PossibleValuesParser, Args, Parser, Subcommand};
+use color_eyre::{
+    eyre::{eyre, WrapErr},
+    Result,
+};
+use reqwest::header::CONTENT_TYPE;
+use secrecy::SecretString;
+use serde_json::Value;
+use std::fmt::Write;
+use std::{env, fs::File, io};
+use tailforward_cfg::{config::Format, Problem, SecretSource};
+
+/// Event type of the notifications sent by `send-test`
+pub const TEST_EVENT_TYPE: &str = "test";
+
+/// Forwards Tailscale webhooks to Telegram
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Configuration file, `$CONFIGURATION_DIRECTORY/tailforward` by default
+    #[arg(long, global = true)]
+    pub config: Option<String>,
+    /// Overrides a configuration key, e.g. `--set telegram.chat_id=-123`
+    #[arg(
+        long = "set",
+        value_name = "KEY=VALUE",
+        value_parser = parse_override,
+        global = true
+    )]
+    pub overrides: Vec<(String, String)>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Serve Tailscale webhooks, the default
+    Serve,
+    /// Validate the configuration and read every secret it refers to
+    CheckConfig,
+    /// Print the example configuration
+    PrintDefaultConfig,
+    /// Print the JSON Schema of the configuration file
+    PrintSchema,
+    /// Send a test notification through the sinks of an endpoint
+    SendTest {
+        /// Name of the tailnet in `tailnets`, the default endpoint if omitted
+        #[arg(long)]
+        tailnet: Option<String>,
+        /// Text of the notification
+        #[arg(long, default_value = "Test notification from tailforward")]
+        message: String,
+    },
+    /// Print a curl command that posts a signed webhook
+    Sign {
+        #[command(flatten)]
+        webhook: Webhook,
+        /// Webhook URL the command posts to
+        #[arg(long, default_value = "http://localhost:33010/tailscale-webhook")]
+        url: String,
+    },
+    /// Post a signed webhook to a running instance
+    Fire {
+        #[command(flatten)]
+        webhook: Webhook,
+        /// Webhook URL, e.g. `http://localhost:33010/tailscale-webhook`
+        url: String,
+    },
+    /// Write the events kept in the history, oldest first
+    Export {
+        #[command(flatten)]
+        filters: Filters,
+        /// Format of the output
+        #[arg(long, value_enum, default_value_t = ExportFormat::Jsonl)]
+        format: ExportFormat,
+        /// File to write to instead of standard output
+        #[arg(long)]
+        output: Option<Utf8PathBuf>,
+    },
+}
+
+/// Which stored events to export
+#[derive(Debug, Args)]
+pub struct Filters {
+    /// Only events received at or after, e.g. `2024-01-31T00:00:00Z`
+    #[arg(long)]
+    pub since: Option<DateTime<Utc>>,
+    /// Only events received before
+    #[arg(long)]
+    pub until: Option<DateTime<Utc>>,
+    /// Only events of this type
+    #[arg(long = "type", value_parser = PossibleValuesParser::new(TYPES))]
+    pub r#type: Option<String>,
+    /// Only events of this tailnet
+    #[arg(long)]
+    pub tailnet: Option<String>,
+    /// Only events with this text in the message or data, ignoring ASCII case
+    #[arg(long)]
+    pub q: Option<String>,
+    /// Only events whose last delivery failed
+    #[arg(long)]
+    pub failed: bool,
+}
+
+impl From<&Filters> for Query {
+    fn from(filters: &Filters) -> Self {
+        Self {
+            r#type: filters.r#type.clone(),
+            tailnet: filters.tailnet.clone(),
+            since: filters.since,
+            until: filters.until,
+            q: filters.q.clone(),
+            failed: filters.failed,
+            ..Self::default()
+        }
+    }
+}
+
+/// Events to send and how to sign them
+#[derive(Debug, Args)]
+pub struct Webhook {
+    /// JSON file with an event or a list of events
+    #[arg(long, conflicts_with = "sample")]
+    pub event: Option<Utf8PathBuf>,
+    /// Type of the built-in sample event, `test` if no file is given either
+    #[arg(long, value_parser = PossibleValuesParser::new(TYPES))]
+    pub sample: Option<String>,
+    /// Tailnet whose webhook secret signs the events, the default endpoint if omitted
+    #[arg(long)]
+    pub tailnet: Option<String>,
+    /// Webhook secret, e.g. `env:WEBHOOK_SECRET`, instead of the one in the configuration
+    #[arg(long)]
+    pub secret: Option<SecretSource>,
+    /// Sign as if sent this many seconds ago, anything over 300 is rejected as stale
+    #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+    pub age: i64,
+    /// Send a signature that doesn't match the body
+    #[arg(long)]
+    pub wrong_signature: bool,
+}
+
+impl Cli {
+    #[must_use]
+    pub fn config_file(&self) -> String {
+        self.config.clone().unwrap_or_else(config_file_path)
+    }
+
+    /// Reads the configuration file with the overrides applied, along with its secrets
+    ///
+    /// # Errors
+    /// If the configuration is invalid or a secret can't be read
+    pub fn settings(&self) -> Result<Application> {
+        new_config_with(&self.config_file(), &self.overrides)
+    }
+}
+
+/// Writes the events kept in the history to `output`, standard output if unset,
+/// returning how many there were
+///
+/// # Errors
+/// If the configuration is invalid, or the history can't be read or the output
+/// written to
+pub fn export_history(
+    cli: &Cli,
+    filters: &Filters,
+    format: ExportFormat,
+    output: Option<&Utf8Path>,
+) -> Result<u64> {
+    // Only the path of the history is needed, not the secrets
+    let base = config::load(&cli.config_file(), env::vars(), &cli.overrides)?;
+    let path = &base.history.path;
+    if !path.exists() {
+        return Err(eyre!("There is no event history at {path}"));
+    }
+    let store =
+        Store::open(path).wrap_err_with(|| format!("Can't open the event history {path}"))?;
+    let query = Query::from(filters);
+    let exporter = Exporter::new(format, query.r#type.as_deref());
+    match output {
+        Some(output) => {
+            let mut file = io::BufWriter::new(
+                File::create(output).wrap_err_with(|| format!("Can't create {output}"))?,
+            );
+            export(&store, &query, &exporter, &mut file)
+        }
+        None => export(&store, &query, &exporter, &mut io::stdout().lock()),
+    }
+}
+
+fn parse_override(pair: &str) -> Result<(String, String), String> {
+    pair.split_once('=')
+        .filter(|(key, _)| !key.is_empty())
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got {pair:?}"))
+}
+
+/// Summary of the endpoints of a configuration that passed validation, and of
+/// the environment variables that were ignored
+#[must_use]
+pub fn check_config(settings: &Application, ignored: &[Problem]) -> String {
+    let mut summary = "Configuration is valid".to_owned();
+    for endpoint in settings.endpoints() {
+        let name = if endpoint.name.is_empty() {
+            "default"
+        } else {
+            &endpoint.name
+        };
+        let _ = write!(
+            summary,
+            "\n{name}: chat {}, tailnet {}, Tailscale API {}",
+            endpoint.telegram.chat_id.unwrap_or_default(),
+            endpoint.tailnet.as_deref().unwrap_or("any"),
+            if endpoint.api.is_some() {
+                "enabled"
+            } else {
+                "disabled"
+            },
+        );
+    }
+    for problem in ignored {
+        let _ = write!(summary, "\nIgnored environment variable {problem}");
+    }
+    summary
+}
+
+/// Example configuration as TOML
+///
+/// # Errors
+/// If the configuration can't be serialized
+pub fn default_config() -> Result<String> {
+    Ok(toml::to_string(&tailforward_cfg::Config::example())?)
+}
+
+/// JSON Schema of the configuration file
+///
+/// # Errors
+/// If the schema can't be serialized
+pub fn config_schema() -> Result<String> {
+    Ok(serde_json::to_string_pretty(
+        &tailforward_cfg::validate::schema(),
+    )?)
+}
+
+/// Pushes a synthetic event through the sinks of the endpoint serving `tailnet`
+///
+/// # Errors
+/// If the tailnet isn't configured or a sink fails
+pub async fn send_test(state: &State, tailnet: Option<&str>, message: &str) -> Result<()> {
+    let settings = state.settings();
+    let name = tailnet.unwrap_or_default();
+    let endpoint = settings
+        .endpoint(name)
+        .ok_or_else(|| eyre!("Tailnet {name} is not configured"))?;
+    dispatch(
+        state,
+        endpoint,
+        vec![test_event(endpoint, message)],
+        Vec::new(),
+    )
+    .await
+}
+
+impl Webhook {
+    /// Signs the events, returning the `Tailscale-Webhook-Signature` header and the body
+    ///
+    /// # Errors
+    /// If the secret or the events can't be read
+    pub fn sign(&self, cli: &Cli) -> Result<(Header, String)> {
+        let (secret, tailnet) = if let Some(source) = &self.secret {
+            (secret::load("Webhook", source, &Format::Plain, None)?, None)
+        } else {
+            let settings = cli.settings()?;
+            let name = self.tailnet.as_deref().unwrap_or_default();
+            let endpoint = settings
+                .endpoint(name)
+                .ok_or_else(|| eyre!("Tailnet {name} is not configured"))?;
+            (endpoint.tailscale_secret.clone(), endpoint.tailnet.clone())
+        };
+        let tailnet = tailnet.as_deref().unwrap_or("example.com");
+
+        let events = if let Some(path) = &self.event {
+            read_events(path)?
+        } else {
+            let r#type = self.sample.as_deref().unwrap_or("test");
+            vec![Event::sample(r#type, tailnet)
+                .ok_or_else(|| eyre!("No sample event of type {type}"))?]
+        };
+        let body = serde_json::to_string(&events)?;
+        Ok((self.header(&body, &secret)?, body))
+    }
+
+    fn header(&self, body: &str, secret: &SecretString) -> Result<Header> {
+        let timestamp = Utc::now() - Duration::seconds(self.age);
+        let mut value = sign(timestamp, body, secret)?;
+        if self.wrong_signature {
+            // Flipping a bit keeps the signature well-formed
+            value = hex::encode(
+                hex::decode(&value)?
+                    .iter()
+                    .map(|byte| byte ^ 1)
+                    .collect::<Vec<_>>(),
+            );
+        }
+        Ok(Header {
+            timestamp,
+            signature: Signature {
+                version: Version::V1,
+                value,
+            },
+        })
+    }
+}
+
+fn read_events(path: &Utf8Path) -> Result<Vec<Event>> {
+    let contents =
+        std::fs::read_to_string(path).wrap_err_with(|| format!("Can't read events from {path}"))?;
+    let events = match serde_json::from_str(&contents)? {
+        events @ Value::Array(_) => serde_json::from_value(events)?,
+        event => vec![serde_json::from_value(event)?],
+    };
+    Ok(events)
+}
+
+/// Shell command posting a signed webhook to `url`
+#[must_use]
+pub fn curl(url: &str, header: &Header, body: &str) -> String {
+    format!(
+        "curl -X POST {} -H {} -H 'Content-Type: application/json' --data {}",
+        quote(url),
+        quote(&format!("Tailscale-Webhook-Signature: {header}")),
+        quote(body),
+    )
+}
+
+fn quote(argument: &str) -> String {
+    format!("'{}'", argument.replace('\'', r"'\''"))
+}
+
+/// Posts a signed webhook, returning the response of a successful request
+///
+/// # Errors
+/// If the request fails or the server rejects the webhook
+pub async fn fire(url: &str, header: &Header, body: String) -> Result<String> {
+    let response = reqwest::Client::new()
+        .post(url)
+        .header("Tailscale-Webhook-Signature", header.to_string())
+        .header(CONTENT_TYPE, "application/json")
+        .body(body)
+        .send()
+        .await?;
+    let status = response.status();
+    let text = response.text().await?;
+    if status.is_success() {
+        Ok(format!("{status} {text}"))
+    } else {
+        Err(eyre!("{url} responded with {status}: {text}"))
+    }
+}
+
+fn test_event(endpoint: &Endpoint, message: &str) -> Event {
+    Event {
+        timestamp: Utc::now(),
+        version: 1,
+        r#type: TEST_EVENT_TYPE.to_owned(),
+        tailnet: endpoint
+            .tailnet
+            .clone()
+            .unwrap_or_else(|| "example.com".to_owned()),
+        message: message.to_owned(),
+        data: None,
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+    use test_case::test_case;
+
+    #[test]
+    fn verifies_cli() {
+        Cli::command().debug_assert();
+    }
+
+    #[test_case(&["tailforward"] => matches None; "when no subcommand")]
+    #[test_case(&["tailforward", "check-config"] => matches Some(Command::CheckConfig); "when check config")]
+    #[test_case(&["tailforward", "send-test", "--tailnet", "corp"] => matches Some(Command::SendTest { tailnet: Some(_), .. }); "when send test")]
+    fn parses_subcommand(args: &[&str]) -> Option<Command> {
+        Cli::try_parse_from(args).unwrap().command
+    }
+
+    #[test]
+    fn parses_global_options() {
+        let cli = Cli::try_parse_from([
+            "tailforward",
+            "check-config",
+            "--config",
+            "/tmp/tailforward.toml",
+            "--set",
+            "telegram.chat_id=-1",
+        ])
+        .unwrap();
+
+        assert_eq!(cli.config_file(), "/tmp/tailforward.toml");
+        assert_eq!(
+            cli.overrides,
+            vec![("telegram.chat_id".to_owned(), "-1".to_owned())]
+        );
+    }
+
+    #[test_case("key=value" => Ok(("key".to_owned(), "value".to_owned())); "when pair")]
+    #[test_case("key=a=b" => Ok(("key".to_owned(), "a=b".to_owned())); "when value contains equals")]
+    #[test_case("key" => matches Err(_); "when no equals")]
+    #[test_case("=value" => matches Err(_); "when no key")]
+    fn parses_override(pair: &str) -> Result<(String, String), String> {
+        parse_override(pair)
+    }
+
+    #[test]
+    fn default_config_parses() {
+        let config: tailforward_cfg::Config = toml::from_str(&default_config().unwrap()).unwrap();
+        assert_eq!(config.telegram.chat_id, Some(-123));
+    }
+
+    #[test]
+    fn examples_are_up_to_date() {
+        assert_eq!(
+            include_str!("../examples/config.toml"),
+            default_config().unwrap()
+        );
+        assert_eq!(
+            include_str!("../examples/config.schema.json").trim_end(),
+            config_schema().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn send_test_fails_on_unknown_tailnet() {
+        let settings = crate::config::new_config_with_secrets(
+            "tail".to_owned().into(),
+            "tele".to_owned().into(),
+        )
+        .unwrap();
+        let state = State::new(settings);
+
+        let error = send_test(&state, Some("corp"), "test").await.unwrap_err();
+
+        assert_eq!(error.to_string(), "Tailnet corp is not configured");
+    }
+
+    fn webhook(args: &[&str]) -> Webhook {
+        let args = ["tailforward", "sign", "--secret", "literal:123"]
+            .iter()
+            .chain(args);
+        match Cli::try_parse_from(args).unwrap().command {
+            Some(Command::Sign { webhook, .. }) => webhook,
+            command => panic!("{command:?}"),
+        }
+    }
+
+    #[test_case(&[] => matches Ok(_); "when fresh")]
+    #[test_case(&["--sample", "nodeCreated"] => matches Ok(_); "when sample")]
+    #[test_case(&["--wrong-signature"] => matches Err(_); "when wrong signature")]
+    fn signs_like_tailscale(args: &[&str]) -> Result<Vec<Event>> {
+        let cli = Cli::try_parse_from(["tailforward"]).unwrap();
+        let (header, body) = webhook(args).sign(&cli).unwrap();
+        let header: Header = header.to_string().parse()?;
+        crate::services::post_webhook::post_webhook(header, &body, &"123".to_owned().into())
+    }
+
+    #[test]
+    fn signs_stale_webhooks() {
+        let cli = Cli::try_parse_from(["tailforward"]).unwrap();
+        let (header, _) = webhook(&["--age", "600"]).sign(&cli).unwrap();
+        assert!(header.to_string().parse::<Header>().is_err());
+    }
+
+    #[test]
+    fn quotes_curl_arguments() {
+        let header = Header {
+            timestamp: chrono::DateTime::from_timestamp(1, 0).unwrap(),
+            signature: Signature {
+                version: Version::V1,
+                value: "ab".to_owned(),
+            },
+        };
+        assert_eq!(
+            curl("http://localhost", &header, "[{\"message\": \"it's\"}]"),
+            "curl -X POST 'http://localhost' -H 'Tailscale-Webhook-Signature: t=1,v1=ab' \
+             -H 'Content-Type: application/json' --data '[{\"message\": \"it'\\''s\"}]'"
+        );
+    }
+}
@@ -1,71 +1,306 @@
-use color_eyre::{eyre::eyre, Result};
-use config::{Config, File, FileFormat};
-use secrecy::SecretString;
-use std::{env, fs::read_to_string};
-use tap::{Pipe, Tap};
+use crate::secret;
+use crate::services::tailscale_api;
+use camino::Utf8PathBuf;
+use color_eyre::{eyre::eyre, Report, Result};
+use config::{Config, ConfigError, File, FileFormat, Map, Source, Value};
+use secrecy::{ExposeSecret, SecretString};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, env};
+use tailforward_cfg::config::{Auth, Format, Listener, Service, Tailscale, TailscaleApi, Telegram};
+use tailforward_cfg::validate::unknown_keys;
+use tailforward_cfg::{ListenAddress, Problem, SecretSource};
 use tracing::{debug, info};
 
+/// Prefix of environment variables overriding the configuration file
+pub const ENV_PREFIX: &str = "TAILFORWARD_";
+
 #[tracing::instrument]
 pub fn new_config() -> Result<Application> {
+    new_config_with(&config_file_path(), &[])
+}
+
+/// Reads the configuration from `file`, environment variables and `overrides`
+/// (`key=value` pairs from the command line, e.g. `telegram.chat_id=-123`), then
+/// reads the secrets it refers to
+#[tracing::instrument]
+pub fn new_config_with(file: &str, overrides: &[(String, String)]) -> Result<Application> {
+    from_base(load(file, env::vars(), overrides)?)
+}
+
+/// Path of the configuration file, `$CONFIGURATION_DIRECTORY/tailforward`
+#[must_use]
+pub fn config_file_path() -> String {
     let config_dir_path = env::var("CONFIGURATION_DIRECTORY").unwrap_or_else(|error| {
         info!("CONFIGURATION_DIRECTORY is not specified, using defaults");
         debug!(?error);
         "/etc/tailforward".to_string()
     });
-    let config_file_path = config_dir_path + "/tailforward";
+    config_dir_path + "/tailforward"
+}
 
-    let s = Config::builder()
-        .add_source(File::new(&config_file_path, FileFormat::Toml))
-        .build()?;
+/// Layers the configuration sources, each one taking precedence over the previous:
+/// defaults < file < environment < overrides
+#[tracing::instrument(skip(env))]
+pub fn load(
+    file: &str,
+    env: impl IntoIterator<Item = (String, String)>,
+    overrides: &[(String, String)],
+) -> Result<tailforward_cfg::Config> {
+    let mut builder = Config::builder()
+        .add_source(File::new(file, FileFormat::Toml))
+        .add_source(Environment {
+            vars: env.into_iter().collect(),
+        });
+    for (key, value) in overrides {
+        builder = builder.set_override(key.as_str(), value.as_str())?;
+    }
 
-    // You can deserialize (and thus freeze) the entire configuration as
-    let base: tailforward_cfg::Config = s.try_deserialize()?;
+    let config = builder.build()?;
+    // Catch typos before serde reports only the first unknown field
+    let document: serde_json::Value = config.clone().try_deserialize()?;
+    let unknown = unknown_keys(&document);
+    if !unknown.is_empty() {
+        return Err(invalid(&unknown));
+    }
+    Ok(config.try_deserialize()?)
+}
+
+fn invalid(problems: &[Problem]) -> Report {
+    let list: Vec<_> = problems.iter().map(ToString::to_string).collect();
+    eyre!("Invalid configuration:\n  - {}", list.join("\n  - "))
+}
+
+/// Prefixed environment variables that match no configuration key, which are
+/// left out of the configuration, other than those `secrets` are read from
+pub fn ignored_vars(
+    env: impl IntoIterator<Item = (String, String)>,
+    secrets: &[SecretSource],
+) -> Vec<Problem> {
+    let (_, mut ignored) = Environment {
+        vars: env.into_iter().collect(),
+    }
+    .keys();
+    ignored.retain(|problem| !secrets.contains(&SecretSource::Env(problem.path.clone())));
+    ignored
+}
+
+/// `TAILFORWARD_`-prefixed environment variables, `__` separating nested keys and
+/// numbers indexing into lists, e.g. `TAILFORWARD_TAILNETS__0__TELEGRAM__CHAT_ID`
+#[derive(Clone, Debug)]
+struct Environment {
+    vars: Vec<(String, String)>,
+}
+
+impl Environment {
+    /// Configuration keys and values of the variables, and the problems of the
+    /// variables whose key isn't in the schema
+    fn keys(&self) -> (Vec<(String, String)>, Vec<Problem>) {
+        let mut keys = Vec::new();
+        let mut ignored = Vec::new();
+        for (name, value) in &self.vars {
+            let Some(key) = name.strip_prefix(ENV_PREFIX).and_then(env_key) else {
+                continue;
+            };
+            match unknown_key(&key, value) {
+                Some(problem) => ignored.push(Problem {
+                    path: name.clone(),
+                    message: problem.message,
+                }),
+                None => keys.push((key, value.clone())),
+            }
+        }
+        (keys, ignored)
+    }
+}
+
+/// Why `key` isn't in the schema, if it isn't
+fn unknown_key(key: &str, value: &str) -> Option<Problem> {
+    let document: serde_json::Value = Config::builder()
+        .set_override(key, value)
+        .ok()?
+        .build()
+        .ok()?
+        .try_deserialize()
+        .ok()?;
+    unknown_keys(&document).into_iter().next()
+}
+
+impl Source for Environment {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
 
-    if base.telegram.chat_id.is_none() {
-        return Err(eyre!("Chat id is not specified"));
+    fn collect(&self) -> Result<Map<String, Value>, ConfigError> {
+        let origin = "the environment".to_owned();
+        Ok(self
+            .keys()
+            .0
+            .into_iter()
+            .map(|(key, value)| (key, Value::new(Some(&origin), value)))
+            .collect())
+    }
+}
+
+/// Turns `TAILNETS__0__NAME` into `tailnets[0].name`
+fn env_key(name: &str) -> Option<String> {
+    let mut key = String::new();
+    for segment in name.split("__") {
+        if segment.is_empty() {
+            return None;
+        }
+        if segment.bytes().all(|byte| byte.is_ascii_digit()) {
+            if key.is_empty() {
+                return None;
+            }
+            key = format!("{key}[{segment}]");
+        } else {
+            if !key.is_empty() {
+                key.push('.');
+            }
+            key.push_str(&segment.to_lowercase());
+        }
+    }
+    Some(key)
+}
+
+/// Validates the configuration and reads the secrets it refers to
+#[tracing::instrument]
+pub fn from_base(base: tailforward_cfg::Config) -> Result<Application> {
+    base.validate().map_err(|problems| invalid(&problems))?;
+    if let Some(filter) = &base.log.filter {
+        tracing_subscriber::EnvFilter::builder()
+            .parse(filter)
+            .map_err(|error| {
+                invalid(&[Problem {
+                    path: "log.filter".to_owned(),
+                    message: error.to_string(),
+                }])
+            })?;
+    }
+
+    if let Some(tls) = &base.tls {
+        crate::tls::server_config(tls)?;
+    }
+
+    let endpoint = Endpoint {
+        name: String::new(),
+        tailnet: base.tailnet.clone(),
+        tailscale_secret: read_tailscale_secret(&base.tailscale)?,
+        telegram_secret: read_telegram_secret(&base.telegram)?,
+        tailscale: base.tailscale.clone(),
+        telegram: base.telegram.clone(),
+        api: new_api_client(&base.tailscale)?,
     };
 
-    let tailscale_secret_path = &base
-        .tailscale
-        .secret_file
+    let mut tailnets = BTreeMap::new();
+    for tailnet in &base.tailnets {
+        let endpoint = Endpoint {
+            name: tailnet.name.clone(),
+            tailnet: tailnet.tailnet.clone(),
+            tailscale_secret: read_tailscale_secret(&tailnet.tailscale)?,
+            telegram_secret: read_telegram_secret(&tailnet.telegram)?,
+            tailscale: tailnet.tailscale.clone(),
+            telegram: tailnet.telegram.clone(),
+            api: new_api_client(&tailnet.tailscale)?,
+        };
+        tailnets.insert(tailnet.name.clone(), endpoint);
+        info!(name = tailnet.name, "Configured tailnet");
+    }
+
+    let api_token = base
+        .api
+        .token
         .as_ref()
-        .ok_or(eyre!("Must specify path for Tailscale secret"))?;
-    debug!(?tailscale_secret_path, "Reading Tailscale secret");
-    let tailscale_secret: SecretString = read_to_string(tailscale_secret_path)?
-        .trim()
-        .to_owned()
-        .tap_dbg(|tailscale_secret| debug!(?tailscale_secret))
-        .into();
-    info!(?tailscale_secret_path, "Read Tailscale secret");
-
-    let telegram_secret_path = &base
-        .telegram
-        .secret_file
+        .map(|source| secret::load("API token", source, &Format::Plain, None))
+        .transpose()?;
+
+    let dashboard_auth = base
+        .dashboard
+        .auth
         .as_ref()
-        .ok_or(eyre!("Must specify path for Telegram secret"))?;
-    debug!(?telegram_secret_path, "Reading Telegram secret");
-    let telegram_secret: SecretString = read_to_string(telegram_secret_path)?
-        .trim()
-        .to_owned()
-        .pipe_as_mut(|str| match &base.telegram.file_format {
-            tailforward_cfg::config::Format::Alertmanager => {
-                debug!("alertmanager match");
-                str.split('=').collect::<Vec<_>>()[1]
-            }
-            tailforward_cfg::config::Format::Plain => {
-                debug!("plain match");
-                str
-            }
-        })
-        .to_string()
-        .tap_dbg(|telegram_secret| debug!(?telegram_secret))
-        .into();
-    info!(?telegram_secret_path, "Read Telegram secret");
+        .map(read_credentials)
+        .transpose()?;
 
     Ok(Application {
         base,
-        tailscale_secret,
-        telegram_secret,
+        endpoint,
+        tailnets,
+        api_token,
+        dashboard_auth,
+    })
+}
+
+#[tracing::instrument]
+fn read_tailscale_secret(tailscale: &Tailscale) -> Result<SecretString> {
+    let source = secret::source(
+        "Tailscale",
+        tailscale.secret.as_ref(),
+        tailscale.secret_file.as_ref(),
+    )?;
+    debug!(?source, "Reading Tailscale secret");
+    let tailscale_secret = secret::load(
+        "Tailscale",
+        &source,
+        &tailscale.file_format,
+        tailscale.secret_key.as_deref(),
+    )?;
+    Ok(tailscale_secret)
+}
+
+#[tracing::instrument]
+fn new_api_client(tailscale: &Tailscale) -> Result<Option<tailscale_api::Client>> {
+    tailscale
+        .api
+        .as_ref()
+        .map(|api| Ok(tailscale_api::Client::new(api, read_api_secret(api)?)))
+        .transpose()
+}
+
+#[tracing::instrument]
+fn read_api_secret(api: &TailscaleApi) -> Result<SecretString> {
+    let source = secret::source(
+        "Tailscale API",
+        api.secret.as_ref(),
+        api.secret_file.as_ref(),
+    )?;
+    Ok(secret::load(
+        "Tailscale API",
+        &source,
+        &api.file_format,
+        api.secret_key.as_deref(),
+    )?)
+}
+
+#[tracing::instrument]
+fn read_telegram_secret(telegram: &Telegram) -> Result<SecretString> {
+    let source = secret::source(
+        "Telegram",
+        telegram.secret.as_ref(),
+        telegram.secret_file.as_ref(),
+    )?;
+    debug!(?source, "Reading Telegram secret");
+    let telegram_secret = secret::load(
+        "Telegram",
+        &source,
+        &telegram.file_format,
+        telegram.secret_key.as_deref(),
+    )?;
+    Ok(telegram_secret)
+}
+
+#[tracing::instrument]
+fn read_credentials(auth: &Auth) -> Result<Credentials> {
+    Ok(match auth {
+        Auth::Basic { username, password } => Credentials::Basic {
+            username: username.clone(),
+            password: secret::load("Dashboard password", password, &Format::Plain, None)?,
+        },
+        Auth::Bearer { token } => Credentials::Bearer(secret::load(
+            "Dashboard token",
+            token,
+            &Format::Plain,
+            None,
+        )?),
     })
 }
 
@@ -76,17 +311,365 @@ pub fn new_config_with_secrets(
 ) -> Result<Application> {
     let s = Config::builder().build()?;
 
-    let base = s.try_deserialize()?;
+    let base: tailforward_cfg::Config = s.try_deserialize()?;
     Ok(Application {
+        endpoint: Endpoint {
+            name: String::new(),
+            tailnet: None,
+            tailscale_secret,
+            telegram_secret,
+            tailscale: base.tailscale.clone(),
+            telegram: base.telegram.clone(),
+            api: None,
+        },
         base,
-        tailscale_secret,
-        telegram_secret,
+        tailnets: BTreeMap::new(),
+        api_token: None,
+        dashboard_auth: None,
     })
 }
 
 #[derive(Clone, Debug)]
 pub struct Application {
     pub base: tailforward_cfg::Config,
+    /// Served on `/tailscale-webhook`
+    pub endpoint: Endpoint,
+    /// Served on `/tailscale-webhook/<name>`, keyed by name
+    pub tailnets: BTreeMap<String, Endpoint>,
+    /// Bearer token of `/api`, refusing every request if unset
+    pub api_token: Option<SecretString>,
+    /// Credentials of `/dashboard`, refusing every request if unset
+    pub dashboard_auth: Option<Credentials>,
+}
+
+/// `dashboard.auth` with its secret read
+#[derive(Clone, Debug)]
+pub enum Credentials {
+    Basic {
+        username: String,
+        password: SecretString,
+    },
+    Bearer(SecretString),
+}
+
+/// Everything needed to verify and forward webhooks of a single tailnet
+#[derive(Clone, Debug)]
+pub struct Endpoint {
+    /// Name of the tailnet in `tailnets`, empty for the default endpoint
+    pub name: String,
+    pub tailnet: Option<String>,
     pub tailscale_secret: SecretString,
     pub telegram_secret: SecretString,
+    pub tailscale: Tailscale,
+    pub telegram: Telegram,
+    pub api: Option<tailscale_api::Client>,
+}
+
+impl Endpoint {
+    /// Fingerprint of the webhook secret, to tell which key verified an event
+    /// without revealing it
+    #[must_use]
+    pub fn key_id(&self) -> String {
+        let digest = Sha256::digest(self.tailscale_secret.expose_secret().as_bytes());
+        hex::encode(&digest[..8])
+    }
+
+    /// Short reference to the endpoint that fits in Telegram callback data
+    /// whatever the length of its name, empty for the default endpoint
+    #[must_use]
+    pub fn reference(&self) -> String {
+        if self.name.is_empty() {
+            return String::new();
+        }
+        let digest = Sha256::digest(self.name.as_bytes());
+        hex::encode(&digest[..4])
+    }
+}
+
+impl Application {
+    /// The default endpoint followed by every configured tailnet
+    pub fn endpoints(&self) -> impl Iterator<Item = &Endpoint> {
+        std::iter::once(&self.endpoint).chain(self.tailnets.values())
+    }
+
+    /// Where every secret referenced by the configuration is read from
+    #[must_use]
+    pub fn secret_sources(&self) -> Vec<SecretSource> {
+        let base = &self.base;
+        let sections = std::iter::once((&base.tailscale, &base.telegram)).chain(
+            base.tailnets
+                .iter()
+                .map(|tailnet| (&tailnet.tailscale, &tailnet.telegram)),
+        );
+        let mut sources = Vec::new();
+        for (tailscale, telegram) in sections {
+            let settings = [
+                (tailscale.secret.as_ref(), tailscale.secret_file.as_ref()),
+                (telegram.secret.as_ref(), telegram.secret_file.as_ref()),
+            ]
+            .into_iter()
+            .chain(
+                tailscale
+                    .api
+                    .as_ref()
+                    .map(|api| (api.secret.as_ref(), api.secret_file.as_ref())),
+            );
+            for (secret, secret_file) in settings {
+                let source = secret
+                    .cloned()
+                    .or_else(|| secret_file.cloned().map(SecretSource::File));
+                if let Some(source) = source.filter(|source| !sources.contains(source)) {
+                    sources.push(source);
+                }
+            }
+        }
+        let credentials = base.dashboard.auth.as_ref().map(|auth| match auth {
+            Auth::Basic { password, .. } => password,
+            Auth::Bearer { token } => token,
+        });
+        for source in base.api.token.iter().chain(credentials) {
+            if !sources.contains(source) {
+                sources.push(source.clone());
+            }
+        }
+        sources
+    }
+
+    /// Secret files referenced by the configuration, to watch for changes
+    #[must_use]
+    pub fn secret_files(&self) -> Vec<Utf8PathBuf> {
+        let mut files: Vec<_> = self
+            .secret_sources()
+            .into_iter()
+            .filter_map(|source| match source {
+                SecretSource::File(path) => Some(path),
+                _ => None,
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    /// Sockets to serve on: `listeners`, or else `address` along with `metrics.address`
+    #[must_use]
+    pub fn listeners(&self) -> Vec<Listener> {
+        let base = &self.base;
+        if !base.listeners.is_empty() {
+            return base.listeners.clone();
+        }
+        let Some(metrics) = base.metrics.address.filter(|_| base.metrics.enabled) else {
+            return vec![Listener {
+                address: ListenAddress::Tcp(base.address),
+                ..Listener::default()
+            }];
+        };
+        vec![
+            Listener {
+                address: ListenAddress::Tcp(base.address),
+                services: vec![Service::Webhooks, Service::Health],
+                ..Listener::default()
+            },
+            Listener {
+                address: ListenAddress::Tcp(metrics),
+                services: vec![Service::Metrics],
+                tls: false,
+                ..Listener::default()
+            },
+        ]
+    }
+
+    /// Looks an endpoint up by tailnet name, empty name being the default endpoint
+    #[must_use]
+    pub fn endpoint(&self, name: &str) -> Option<&Endpoint> {
+        if name.is_empty() {
+            Some(&self.endpoint)
+        } else {
+            self.tailnets.get(name)
+        }
+    }
+
+    /// Looks an endpoint up by its [`Endpoint::reference`]
+    #[must_use]
+    pub fn endpoint_by_reference(&self, reference: &str) -> Option<&Endpoint> {
+        self.endpoints()
+            .find(|endpoint| endpoint.reference() == reference)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use test_case::test_case;
+
+    fn config_file(name: &str, contents: &str) -> String {
+        let path = env::temp_dir().join(format!("tailforward-{name}-{}.toml", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_owned()
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| ((*name).to_owned(), (*value).to_owned()))
+            .collect()
+    }
+
+    #[test_case("DEBUG" => Some("debug".to_owned()); "when top level")]
+    #[test_case("TELEGRAM__CHAT_ID" => Some("telegram.chat_id".to_owned()); "when nested")]
+    #[test_case("TAILNETS__0__NAME" => Some("tailnets[0].name".to_owned()); "when list of tables")]
+    #[test_case("TELEGRAM__ADMINS__1" => Some("telegram.admins[1]".to_owned()); "when list")]
+    #[test_case("0__NAME" => None; "when index first")]
+    #[test_case("TELEGRAM____CHAT_ID" => None; "when empty segment")]
+    fn env_keys(name: &str) -> Option<String> {
+        env_key(name)
+    }
+
+    #[test]
+    fn uses_defaults() {
+        let file = config_file("defaults", "");
+        let config = load(&file, vec![], &[]).unwrap();
+        fs::remove_file(&file).unwrap();
+        assert!(!config.debug);
+        assert_eq!(config.address.port(), 33010);
+    }
+
+    #[test_case("" => Some(String::new()); "when default")]
+    #[test_case("a-tailnet-with-a-name-far-longer-than-callback-data-allows" => Some("a-tailnet-with-a-name-far-longer-than-callback-data-allows".to_owned()); "when long name")]
+    #[test_case("unknown" => None; "when not configured")]
+    fn finds_endpoint_by_reference(name: &str) -> Option<String> {
+        let mut config =
+            new_config_with_secrets("tail".to_owned().into(), "tele".to_owned().into()).unwrap();
+        let long = "a-tailnet-with-a-name-far-longer-than-callback-data-allows";
+        let endpoint = Endpoint {
+            name: long.to_owned(),
+            ..config.endpoint.clone()
+        };
+        config.tailnets.insert(long.to_owned(), endpoint);
+        let reference = Endpoint {
+            name: name.to_owned(),
+            ..config.endpoint.clone()
+        }
+        .reference();
+
+        assert!(reference.len() <= 8);
+        config
+            .endpoint_by_reference(&reference)
+            .map(|endpoint| endpoint.name.clone())
+    }
+
+    #[test]
+    fn reads_dashboard_auth() {
+        let file = config_file(
+            "dashboard",
+            "[dashboard]\nauth = { Basic = { username = \"admin\", password = \"env:PASSWORD\" } }\n",
+        );
+        let config = load(&file, vec![], &[]).unwrap();
+        fs::remove_file(&file).unwrap();
+        assert_eq!(
+            config.dashboard.auth,
+            Some(Auth::Basic {
+                username: "admin".to_owned(),
+                password: SecretSource::Env("PASSWORD".to_owned()),
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let file = config_file("unknown", "debugg = true\n[telegram]\nchat_idd = -1\n");
+        let error = load(&file, vec![], &[]).unwrap_err().to_string();
+        fs::remove_file(&file).unwrap();
+        assert_eq!(
+            error,
+            "Invalid configuration:\n  \
+             - debugg: unknown key, did you mean `debug`?\n  \
+             - telegram.chat_idd: unknown key, did you mean `chat_id`?"
+        );
+    }
+
+    #[test]
+    fn ignores_env_vars_of_unknown_keys() {
+        let file = config_file("unrelated", "[telegram]\nchat_id = -1\n");
+        let env = vars(&[
+            ("TAILFORWARD_FOO", "bar"),
+            ("TAILFORWARD_DEBUGG", "true"),
+            ("TAILFORWARD_TELEGRAM__CHAT_ID", "-2"),
+        ]);
+        let config = load(&file, env.clone(), &[]).unwrap();
+        fs::remove_file(&file).unwrap();
+        assert_eq!(config.telegram.chat_id, Some(-2));
+        let secrets = [SecretSource::Env("TAILFORWARD_FOO".to_owned())];
+        let ignored: Vec<_> = ignored_vars(env.clone(), &[])
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            ignored,
+            [
+                "TAILFORWARD_FOO: unknown key",
+                "TAILFORWARD_DEBUGG: unknown key, did you mean `debug`?"
+            ]
+        );
+        assert_eq!(ignored_vars(env, &secrets).len(), 1);
+    }
+
+    #[test]
+    fn rejects_invalid_log_filter() {
+        let file = config_file(
+            "log",
+            "[tailscale]\nsecret = \"literal:tail\"\n\
+             [telegram]\nsecret = \"literal:tele\"\nchat_id = -1\n\
+             [log]\nfilter = \"tailforward=loud\"\n",
+        );
+        let error = from_base(load(&file, vec![], &[]).unwrap())
+            .unwrap_err()
+            .to_string();
+        fs::remove_file(&file).unwrap();
+        assert!(error.starts_with("Invalid configuration:\n  - log.filter: "));
+    }
+
+    #[test]
+    fn file_overrides_defaults() {
+        let file = config_file("file", "debug = true\n[telegram]\nchat_id = -1\n");
+        let config = load(&file, vec![], &[]).unwrap();
+        fs::remove_file(&file).unwrap();
+        assert!(config.debug);
+        assert_eq!(config.telegram.chat_id, Some(-1));
+    }
+
+    #[test]
+    fn env_overrides_file() {
+        let file = config_file(
+            "env",
+            "debug = false\n[telegram]\nchat_id = -1\n[[tailnets]]\nname = \"corp\"\n",
+        );
+        let env = vars(&[
+            ("TAILFORWARD_DEBUG", "true"),
+            ("TAILFORWARD_TELEGRAM__CHAT_ID", "-2"),
+            ("TAILFORWARD_TELEGRAM__ADMINS__0", "42"),
+            ("TAILFORWARD_TAILNETS__0__TAILNET", "corp.example.com"),
+            ("CHAT_ID", "-3"),
+        ]);
+        let config = load(&file, env, &[]).unwrap();
+        fs::remove_file(&file).unwrap();
+        assert!(config.debug);
+        assert_eq!(config.telegram.chat_id, Some(-2));
+        assert_eq!(config.telegram.admins, vec![42]);
+        assert_eq!(config.tailnets[0].name, "corp");
+        assert_eq!(
+            config.tailnets[0].tailnet.as_deref(),
+            Some("corp.example.com")
+        );
+    }
+
+    #[test]
+    fn overrides_override_env() {
+        let file = config_file("overrides", "[telegram]\nchat_id = -1\n");
+        let env = vars(&[("TAILFORWARD_TELEGRAM__CHAT_ID", "-2")]);
+        let overrides = [("telegram.chat_id".to_owned(), "-3".to_owned())];
+        let config = load(&file, env, &overrides).unwrap();
+        fs::remove_file(&file).unwrap();
+        assert_eq!(config.telegram.chat_id, Some(-3));
+    }
 }
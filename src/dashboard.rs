@@ -0,0 +1,220 @@
+use crate::history::{Outcome, Record};
+use crate::redact::{redact, REDACTED};
+use crate::services::health::{Readiness, Status};
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::Result;
+use std::fmt::Write;
+use tailforward_cfg::Config;
+
+const STYLE: &str = "
+body { font-family: system-ui, sans-serif; margin: 2rem auto; max-width: 70rem; padding: 0 1rem; color: #222; }
+table { border-collapse: collapse; width: 100%; margin-bottom: 2rem; }
+th, td { border-bottom: 1px solid #ddd; padding: .4rem; text-align: left; vertical-align: top; }
+pre { background: #f5f5f5; padding: 1rem; overflow-x: auto; }
+.ok, .delivered { color: #1a7f37; } .degraded, .muted { color: #9a6700; } .unavailable, .failed { color: #cf222e; }
+.flash { background: #ddf4ff; padding: .6rem; }
+";
+
+/// Everything shown on `/dashboard`
+#[derive(Debug)]
+pub struct View {
+    pub readiness: Readiness,
+    pub recent: Vec<Record>,
+    pub dead_letters: Vec<Record>,
+    pub config: String,
+    pub flash: Option<String>,
+}
+
+/// Escapes text for HTML element contents and quoted attributes
+#[must_use]
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn time(time: Option<DateTime<Utc>>) -> String {
+    time.map_or_else(
+        || "never".to_owned(),
+        |time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+    )
+}
+
+const fn status(status: Status) -> &'static str {
+    match status {
+        Status::Ok => "ok",
+        Status::Degraded => "degraded",
+        Status::Unavailable => "unavailable",
+    }
+}
+
+const fn outcome(outcome: Outcome) -> &'static str {
+    match outcome {
+        Outcome::Delivered => "delivered",
+        Outcome::Failed => "failed",
+        Outcome::Muted => "muted",
+    }
+}
+
+/// Configuration as TOML, without literal secrets, telemetry headers or any
+/// secret read at runtime
+///
+/// # Errors
+/// If the configuration can't be serialized
+pub fn masked_config(config: &Config) -> Result<String> {
+    let mut document = toml::Value::try_from(config)?;
+    mask(&mut document);
+    if let Some(headers) = document
+        .get_mut("telemetry")
+        .and_then(|telemetry| telemetry.get_mut("headers"))
+        .and_then(toml::Value::as_table_mut)
+    {
+        for (_, value) in headers.iter_mut() {
+            *value = toml::Value::String(REDACTED.to_owned());
+        }
+    }
+    Ok(redact(&toml::to_string(&document)?))
+}
+
+fn mask(value: &mut toml::Value) {
+    match value {
+        toml::Value::String(text) if text.starts_with("literal:") => {
+            *text = format!("literal:{REDACTED}");
+        }
+        toml::Value::Array(items) => items.iter_mut().for_each(mask),
+        toml::Value::Table(table) => table.iter_mut().for_each(|(_, value)| mask(value)),
+        _ => {}
+    }
+}
+
+fn events(html: &mut String, records: &[Record], replay: bool) {
+    html.push_str(
+        "<table><tr><th>ID</th><th>Received</th><th>Type</th><th>Tailnet</th>\
+         <th>Message</th><th>Delivery</th>",
+    );
+    if replay {
+        html.push_str("<th></th>");
+    }
+    html.push_str("</tr>");
+    for record in records {
+        let delivery = record.deliveries.last().map_or_else(
+            || "<td>pending</td>".to_owned(),
+            |delivery| {
+                let error = delivery.error.as_deref().map_or_else(String::new, |error| {
+                    format!("<br><small>{}</small>", escape(&redact(error)))
+                });
+                format!(
+                    "<td class=\"{0}\">{0} to {1} at {2}{error}</td>",
+                    outcome(delivery.outcome),
+                    escape(&delivery.sink),
+                    time(Some(delivery.at)),
+                )
+            },
+        );
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>{delivery}",
+            record.id,
+            time(Some(record.received)),
+            escape(&record.event.r#type),
+            escape(&record.event.tailnet),
+            escape(&record.event.message),
+        );
+        if replay {
+            let _ = write!(
+                html,
+                "<td><form method=\"post\" action=\"/dashboard/replay/{}\">\
+                 <button>Replay</button></form></td>",
+                record.id
+            );
+        }
+        html.push_str("</tr>");
+    }
+    html.push_str("</table>");
+}
+
+/// Renders the dashboard page
+#[must_use]
+pub fn render(view: &View) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html><html lang=\"en\"><head><meta charset=\"utf-8\">\
+         <title>tailforward</title><style>{STYLE}</style></head><body>\
+         <h1>tailforward <span class=\"{0}\">{0}</span></h1>",
+        status(view.readiness.status)
+    );
+    if let Some(flash) = &view.flash {
+        let _ = write!(html, "<p class=\"flash\">{}</p>", escape(flash));
+    }
+
+    html.push_str("<h2>Sinks</h2><table><tr><th>Sink</th><th>Status</th><th>Last success</th><th>Last failure</th></tr>");
+    for (name, sink) in &view.readiness.sinks {
+        let error = sink.error.as_deref().map_or_else(String::new, |error| {
+            format!("<br><small>{}</small>", escape(&redact(error)))
+        });
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td class=\"{1}\">{1}</td><td>{2}</td><td>{3}{error}</td></tr>",
+            escape(name),
+            status(sink.status),
+            time(sink.last_success),
+            time(sink.last_failure),
+        );
+    }
+    html.push_str("</table>");
+
+    let _ = write!(
+        html,
+        "<h2>Dead letters</h2><p>Events whose last delivery failed: {}</p>",
+        view.dead_letters.len()
+    );
+    events(&mut html, &view.dead_letters, true);
+    html.push_str("<h2>Recent events</h2>");
+    events(&mut html, &view.recent, false);
+    let _ = write!(
+        html,
+        "<h2>Configuration</h2><pre>{}</pre></body></html>",
+        escape(&view.config)
+    );
+    html
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use tailforward_cfg::SecretSource;
+    use test_case::test_case;
+
+    #[test_case("<script>alert('x')</script>" => "&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;"; "when markup")]
+    #[test_case("a & \"b\"" => "a &amp; &quot;b&quot;"; "when attribute")]
+    fn escapes(text: &str) -> String {
+        escape(text)
+    }
+
+    #[test]
+    fn masks_secrets() {
+        let mut config = Config::example();
+        config.telegram.secret_file = None;
+        config.telegram.secret = Some(SecretSource::Literal("123:abc".to_owned()));
+        config
+            .telemetry
+            .headers
+            .insert("authorization".to_owned(), "Bearer xyz".to_owned());
+
+        let masked = masked_config(&config).unwrap();
+
+        assert!(!masked.contains("123:abc"));
+        assert!(!masked.contains("xyz"));
+        assert!(masked.contains("secret = \"literal:[REDACTED]\""));
+        assert!(masked.contains("secret_file = \"/etc/tailforward/tailforward.toml\""));
+    }
+}
@@ -0,0 +1,321 @@
+use crate::history::{Outcome, Query, Record, Store, MAX_LIMIT};
+use crate::models::event::{data_fields, TYPES};
+use clap::ValueEnum;
+use color_eyre::eyre::Result;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::io::Write;
+
+/// Columns every export starts with, followed by the fields of `data`
+const COLUMNS: &[&str] = &[
+    "id",
+    "received",
+    "endpoint",
+    "key_id",
+    "timestamp",
+    "version",
+    "type",
+    "tailnet",
+    "message",
+    "outcome",
+];
+
+/// Column holding the fields of `data` that have no column of their own, as JSON
+const OTHER: &str = "data.other";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Comma-separated values with a header row
+    Csv,
+    /// JSON Lines, one object per event
+    Jsonl,
+    /// Same as `jsonl`, served as `application/x-ndjson`
+    Ndjson,
+}
+
+impl Format {
+    #[must_use]
+    pub const fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Jsonl => "application/jsonl",
+            Self::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+/// Turns records into lines of a format, with the same columns for every record
+#[derive(Clone, Debug)]
+pub struct Exporter {
+    format: Format,
+    /// Fields of `data` with a column of their own
+    fields: Vec<&'static str>,
+}
+
+impl Exporter {
+    /// Columns for events of `type`, or of any type if unset, so that they don't
+    /// depend on which events happen to be exported
+    #[must_use]
+    pub fn new(format: Format, r#type: Option<&str>) -> Self {
+        let mut fields = Vec::new();
+        let types = r#type.map_or_else(|| TYPES.to_vec(), |r#type| vec![r#type]);
+        for field in types.into_iter().flat_map(data_fields) {
+            if !fields.contains(field) {
+                fields.push(*field);
+            }
+        }
+        Self { format, fields }
+    }
+
+    fn columns(&self) -> Vec<String> {
+        COLUMNS
+            .iter()
+            .map(|column| (*column).to_owned())
+            .chain(self.fields.iter().map(|field| format!("data.{field}")))
+            .chain([OTHER.to_owned()])
+            .collect()
+    }
+
+    /// Whatever comes before the records, the header row of CSV
+    ///
+    /// # Errors
+    /// If the header can't be written
+    pub fn header(&self) -> Result<Vec<u8>> {
+        match self.format {
+            Format::Csv => csv_line(self.columns()),
+            Format::Jsonl | Format::Ndjson => Ok(Vec::new()),
+        }
+    }
+
+    /// One line for the record, newline included
+    ///
+    /// # Errors
+    /// If the line can't be written
+    pub fn line(&self, record: &Record) -> Result<Vec<u8>> {
+        let values = self.values(record);
+        match self.format {
+            Format::Csv => csv_line(values.iter().map(|value| match value {
+                Value::Null => String::new(),
+                Value::String(text) => text.clone(),
+                other => other.to_string(),
+            })),
+            Format::Jsonl | Format::Ndjson => {
+                let object: Map<String, Value> = self.columns().into_iter().zip(values).collect();
+                let mut line = serde_json::to_vec(&object)?;
+                line.push(b'\n');
+                Ok(line)
+            }
+        }
+    }
+
+    fn values(&self, record: &Record) -> Vec<Value> {
+        let event = &record.event;
+        let outcome = record
+            .deliveries
+            .last()
+            .map(|delivery| match delivery.outcome {
+                Outcome::Delivered => "delivered",
+                Outcome::Failed => "failed",
+                Outcome::Muted => "muted",
+            });
+        let mut values = vec![
+            record.id.into(),
+            record.received.to_rfc3339().into(),
+            record.endpoint.clone().into(),
+            record.key_id.clone().into(),
+            event.timestamp.to_rfc3339().into(),
+            event.version.into(),
+            event.r#type.clone().into(),
+            event.tailnet.clone().into(),
+            event.message.clone().into(),
+            outcome.into(),
+        ];
+        let mut data = match &event.data {
+            Some(Value::Object(data)) => data.clone(),
+            Some(other) => Map::from_iter([("value".to_owned(), other.clone())]),
+            None => Map::new(),
+        };
+        for field in &self.fields {
+            values.push(data.remove(*field).unwrap_or(Value::Null));
+        }
+        values.push(if data.is_empty() {
+            Value::Null
+        } else {
+            Value::Object(data)
+        });
+        values
+    }
+}
+
+fn csv_line(fields: impl IntoIterator<Item = impl AsRef<[u8]>>) -> Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(fields)?;
+    Ok(writer.into_inner()?)
+}
+
+/// Next batch of events matching the query, oldest first, from the cursor `after`
+/// and without going over the `limit` of the query once `exported` are out
+///
+/// # Errors
+/// If the database can't be read
+pub fn batch(
+    store: &Store,
+    query: &Query,
+    after: i64,
+    exported: u64,
+) -> rusqlite::Result<Vec<Record>> {
+    let left = query
+        .limit
+        .map_or(u64::MAX, |limit| u64::from(limit).saturating_sub(exported));
+    if left == 0 {
+        return Ok(Vec::new());
+    }
+    store.query(&Query {
+        after: Some(after),
+        limit: Some(u32::try_from(left).unwrap_or(MAX_LIMIT).min(MAX_LIMIT)),
+        ..query.clone()
+    })
+}
+
+/// Writes every event matching the query, returning how many there were
+///
+/// # Errors
+/// If the database can't be read or the output written to
+pub fn export(
+    store: &Store,
+    query: &Query,
+    exporter: &Exporter,
+    output: &mut impl Write,
+) -> Result<u64> {
+    output.write_all(&exporter.header()?)?;
+    let mut after = query.after.unwrap_or_default();
+    let mut count = 0;
+    loop {
+        let records = batch(store, query, after, count)?;
+        let Some(last) = records.last() else {
+            break;
+        };
+        after = last.id;
+        for record in &records {
+            output.write_all(&exporter.line(record)?)?;
+            count += 1;
+        }
+    }
+    output.flush()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::history::Delivery;
+    use crate::models::Event;
+    use chrono::{DateTime, Utc};
+    use serde_json::json;
+    use test_case::test_case;
+
+    fn record(r#type: &str, data: Option<Value>) -> Record {
+        let time: DateTime<Utc> = "2026-01-02T03:04:05Z".parse().unwrap();
+        Record {
+            id: 7,
+            received: time,
+            endpoint: String::new(),
+            key_id: "abcd".to_owned(),
+            event: Event {
+                timestamp: time,
+                version: 1,
+                r#type: r#type.to_owned(),
+                tailnet: "example.com".to_owned(),
+                message: "Role, \"updated\"".to_owned(),
+                data,
+            },
+            deliveries: vec![Delivery {
+                sink: "telegram".to_owned(),
+                at: time,
+                outcome: Outcome::Delivered,
+                error: None,
+            }],
+        }
+    }
+
+    fn text(bytes: Vec<u8>) -> String {
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn writes_csv() {
+        let exporter = Exporter::new(Format::Csv, Some("userRoleUpdated"));
+        let data =
+            json!({"user": "bob", "oldRoles": ["member"], "newRoles": ["admin"], "extra": 1});
+
+        let header = text(exporter.header().unwrap());
+        let line = text(
+            exporter
+                .line(&record("userRoleUpdated", Some(data)))
+                .unwrap(),
+        );
+
+        assert_eq!(
+            header,
+            "id,received,endpoint,key_id,timestamp,version,type,tailnet,message,outcome,\
+             data.user,data.actor,data.oldRoles,data.newRoles,data.url,data.other\n"
+        );
+        assert_eq!(
+            line,
+            "7,2026-01-02T03:04:05+00:00,,abcd,2026-01-02T03:04:05+00:00,1,userRoleUpdated,\
+             example.com,\"Role, \"\"updated\"\"\",delivered,bob,,\"[\"\"member\"\"]\",\
+             \"[\"\"admin\"\"]\",,\"{\"\"extra\"\":1}\"\n"
+        );
+    }
+
+    #[test_case(Format::Jsonl; "when jsonl")]
+    #[test_case(Format::Ndjson; "when ndjson")]
+    fn writes_json_lines(format: Format) {
+        let exporter = Exporter::new(format, None);
+
+        let line = exporter.line(&record("test", None)).unwrap();
+        let object: Value = serde_json::from_slice(&line).unwrap();
+
+        assert!(exporter.header().unwrap().is_empty());
+        assert!(line.ends_with(b"\n"));
+        assert_eq!(object["type"], "test");
+        assert_eq!(object["data.nodeID"], Value::Null);
+        assert_eq!(object["data.other"], Value::Null);
+        assert_eq!(object.as_object().unwrap().len(), exporter.columns().len());
+    }
+
+    #[test_case(None => 3; "when unlimited")]
+    #[test_case(Some(2) => 2; "when limited")]
+    fn exports_in_batches(limit: Option<u32>) -> usize {
+        let store = Store::in_memory().unwrap();
+        let events: Vec<Event> = (0..3).map(|_| record("test", None).event).collect();
+        store.insert(Utc::now(), "", "abcd", &events).unwrap();
+        let query = Query {
+            limit,
+            ..Query::default()
+        };
+        let mut output = Vec::new();
+
+        let exported = export(
+            &store,
+            &query,
+            &Exporter::new(Format::Jsonl, None),
+            &mut output,
+        )
+        .unwrap();
+
+        let ids: Vec<i64> = text(output)
+            .lines()
+            .map(|line| {
+                serde_json::from_str::<Value>(line).unwrap()["id"]
+                    .as_i64()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(usize::try_from(exported).unwrap(), ids.len());
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        ids.len()
+    }
+}
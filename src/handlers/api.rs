@@ -0,0 +1,101 @@
+use crate::export::{batch, Exporter, Format};
+use crate::history::{Query, Record};
+use crate::models::report::Result;
+use crate::services::history::read;
+use crate::State as MyState;
+use axum::body::Body;
+use axum::extract::{Path, Query as QueryString, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use color_eyre::Report;
+use futures_util::{stream, StreamExt};
+use serde_json::json;
+use std::sync::Arc;
+use tracing::error;
+
+/// Stored events matching the query string, newest first unless `after` is set
+#[tracing::instrument(skip(state))]
+pub async fn events_handler(
+    State(state): State<MyState>,
+    QueryString(query): QueryString<Query>,
+) -> Result<Response> {
+    let records = read(&state, move |store| store.query(&query)).await?;
+    Ok(Json(json!({ "events": records })).into_response())
+}
+
+/// Stored event by ID
+#[tracing::instrument(skip(state))]
+pub async fn event_handler(State(state): State<MyState>, Path(id): Path<i64>) -> Result<Response> {
+    let record: Option<Record> = read(&state, move |store| store.get(id)).await?;
+    Ok(record.map_or_else(
+        || (StatusCode::NOT_FOUND, format!("No event {id}")).into_response(),
+        |record| Json(record).into_response(),
+    ))
+}
+
+/// Position of an export streamed in batches
+struct Cursor {
+    state: MyState,
+    query: Arc<Query>,
+    exporter: Arc<Exporter>,
+    after: i64,
+    exported: u64,
+}
+
+/// Every stored event matching the query string, oldest first, streamed as CSV,
+/// JSON Lines or NDJSON
+#[tracing::instrument(skip(state))]
+pub async fn export_handler(
+    State(state): State<MyState>,
+    Path(format): Path<Format>,
+    QueryString(query): QueryString<Query>,
+) -> Result<Response> {
+    let exporter = Exporter::new(format, query.r#type.as_deref());
+    let header = exporter.header()?;
+    let cursor = Cursor {
+        state,
+        after: query.after.unwrap_or_default(),
+        query: Arc::new(query),
+        exporter: Arc::new(exporter),
+        exported: 0,
+    };
+    let lines = stream::unfold(Some(cursor), |cursor| async move {
+        let mut cursor = cursor?;
+        let result = next_batch(&mut cursor).await;
+        match result {
+            Ok(Some(lines)) => Some((Ok(lines), Some(cursor))),
+            Ok(None) => None,
+            Err(error) => {
+                error!(?error, "Failed to export events");
+                Some((Err(error), None))
+            }
+        }
+    });
+    let body = stream::iter([Ok(header)]).chain(lines);
+    Ok((
+        [(CONTENT_TYPE, format.content_type())],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
+
+/// Lines of the next batch of an export, none once it's over
+async fn next_batch(cursor: &mut Cursor) -> Result<Option<Vec<u8>>, Report> {
+    let (query, after, exported) = (cursor.query.clone(), cursor.after, cursor.exported);
+    let records = read(&cursor.state, move |store| {
+        batch(store, &query, after, exported)
+    })
+    .await?;
+    let Some(last) = records.last() else {
+        return Ok(None);
+    };
+    cursor.after = last.id;
+    let mut lines = Vec::new();
+    for record in &records {
+        lines.extend(cursor.exporter.line(record)?);
+        cursor.exported += 1;
+    }
+    Ok(Some(lines))
+}
@@ -0,0 +1,129 @@
+use crate::dashboard::{masked_config, render, View};
+use crate::history::Query;
+use crate::models::report::Result;
+use crate::services::dispatch::dispatch;
+use crate::services::health::readiness;
+use crate::services::history::read;
+use crate::State as MyState;
+use axum::extract::{Path, Query as QueryString, State};
+use axum::http::header::{HOST, ORIGIN, REFERER};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+/// Events shown in each list
+const EVENTS: u32 = 50;
+
+/// Result of a replay, passed along the redirect back to the dashboard
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Flash {
+    replayed: Option<i64>,
+    delivered: Option<bool>,
+}
+
+/// Recent events, sinks, dead letters and configuration
+#[tracing::instrument(skip(state))]
+pub async fn dashboard_handler(
+    State(state): State<MyState>,
+    QueryString(flash): QueryString<Flash>,
+) -> Result<Html<String>> {
+    let settings = state.settings();
+    let (recent, dead_letters) = read(&state, |store| {
+        let recent = store.query(&Query {
+            limit: Some(EVENTS),
+            ..Query::default()
+        })?;
+        let dead_letters = store.query(&Query {
+            failed: true,
+            limit: Some(EVENTS),
+            ..Query::default()
+        })?;
+        Ok((recent, dead_letters))
+    })
+    .await?;
+    let flash = flash.replayed.map(|id| match flash.delivered {
+        Some(true) => format!("Replayed event {id}, it was delivered"),
+        _ => format!("Replayed event {id}, it failed again"),
+    });
+    let view = View {
+        readiness: readiness(&state, true).await,
+        recent,
+        dead_letters,
+        config: masked_config(&settings.base)?,
+        flash,
+    };
+    Ok(Html(render(&view)))
+}
+
+/// Sends a stored event to the sinks of its endpoint again
+#[tracing::instrument(skip(state, headers))]
+pub async fn replay_handler(
+    State(state): State<MyState>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    if !same_origin(&headers) {
+        let status = StatusCode::FORBIDDEN;
+        warn!(%status, id, "Refused replay from another site");
+        return Ok((status, "Replays must come from the dashboard").into_response());
+    }
+    let Some(record) = read(&state, move |store| store.get(id)).await? else {
+        return Ok((StatusCode::NOT_FOUND, format!("No event {id}")).into_response());
+    };
+    let settings = state.settings();
+    let Some(endpoint) = settings.endpoint(&record.endpoint) else {
+        let message = format!("Tailnet {} is no longer configured", record.endpoint);
+        return Ok((StatusCode::CONFLICT, message).into_response());
+    };
+    let result = dispatch(&state, endpoint, vec![record.event], vec![Some(id)]).await;
+    match &result {
+        Ok(()) => info!(id, "Replayed event"),
+        Err(error) => warn!(id, ?error, "Failed to replay event"),
+    }
+    let delivered = result.is_ok();
+    Ok(Redirect::to(&format!("/dashboard?replayed={id}&delivered={delivered}")).into_response())
+}
+
+/// Whether the request comes from a page of this server, as browsers send
+/// basic credentials along with forms posted from anywhere; without an `Origin`
+/// or `Referer` naming this host, it can't be told, so it's refused
+fn same_origin(headers: &HeaderMap) -> bool {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    if header("Sec-Fetch-Site").is_some_and(|site| site == "cross-site" || site == "same-site") {
+        return false;
+    }
+    let Some(host) = header(HOST.as_str()) else {
+        return false;
+    };
+    // Browsers send the origin with posted forms, the referer is for older ones
+    header(ORIGIN.as_str())
+        .or_else(|| header(REFERER.as_str()))
+        .and_then(|source| source.split_once("://"))
+        .is_some_and(|(_, rest)| rest.split('/').next() == Some(host))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(&[] => false; "when no headers")]
+    #[test_case(&[("Host", "tf.example")] => false; "when no origin nor referer")]
+    #[test_case(&[("Origin", "https://tf.example")] => false; "when no host")]
+    #[test_case(&[("Origin", "https://tf.example"), ("Host", "tf.example")] => true; "when same origin")]
+    #[test_case(&[("Referer", "https://tf.example/dashboard?failed=true"), ("Host", "tf.example")] => true; "when same referer")]
+    #[test_case(&[("Origin", "https://evil.example"), ("Host", "tf.example")] => false; "when other origin")]
+    #[test_case(&[("Referer", "https://tf.example.evil/"), ("Host", "tf.example")] => false; "when other referer")]
+    #[test_case(&[("Origin", "null"), ("Host", "tf.example")] => false; "when opaque origin")]
+    #[test_case(&[("Origin", "https://tf.example"), ("Host", "tf.example"), ("Sec-Fetch-Site", "cross-site")] => false; "when cross site")]
+    fn checks_origin(pairs: &[(&'static str, &str)]) -> bool {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        same_origin(&headers)
+    }
+}
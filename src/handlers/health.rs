@@ -0,0 +1,41 @@
+use crate::middleware::has_api_token;
+use crate::services::health::{readiness, Status};
+use crate::State as MyState;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::Json;
+use chrono::Utc;
+use serde_json::json;
+
+/// Liveness: answers as long as the process is serving requests
+#[tracing::instrument(skip(state))]
+pub async fn healthz_handler(State(state): State<MyState>) -> impl IntoResponse {
+    Json(json!({
+        "status": Status::Ok,
+        "uptime_seconds": (Utc::now() - state.runtime.started).num_seconds(),
+    }))
+}
+
+/// Readiness: 503 if webhooks can't be processed, 200 otherwise, even if degraded
+///
+/// Only the status and counts are given, unless `dedicated`, i.e. served on a
+/// listener without the webhooks, or asked with the API token
+#[tracing::instrument(skip(state, headers))]
+pub async fn readyz_handler(
+    State(state): State<MyState>,
+    headers: HeaderMap,
+    dedicated: bool,
+) -> impl IntoResponse {
+    let readiness = readiness(&state, true).await;
+    let status = if readiness.status == Status::Unavailable {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+    if dedicated || has_api_token(&state.settings(), &headers) {
+        (status, Json(readiness)).into_response()
+    } else {
+        (status, Json(readiness.summary())).into_response()
+    }
+}
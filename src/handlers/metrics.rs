@@ -0,0 +1,18 @@
+use crate::models::report::Result;
+use crate::State as MyState;
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
+use color_eyre::eyre::eyre;
+
+/// Content type of the Prometheus text exposition format
+const CONTENT_TYPE_TEXT: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+#[tracing::instrument(skip(state))]
+pub async fn metrics_handler(State(state): State<MyState>) -> Result<impl IntoResponse> {
+    let text = state
+        .metrics
+        .render(state.runtime.in_flight())
+        .map_err(|error| eyre!("Failed to encode metrics: {error}"))?;
+    Ok(([(CONTENT_TYPE, CONTENT_TYPE_TEXT)], text))
+}
@@ -1,44 +1,118 @@
+use crate::config::Endpoint;
 use crate::models::report::Result;
-use crate::models::Header;
-use crate::services::post_webhook::post_webhook;
-use crate::services::telegram::post;
+use crate::models::{Event, Header, TailscaleWebhook};
+use crate::runtime::Acceptance;
+use crate::services::archive::archive_events;
+use crate::services::dispatch::dispatch;
+use crate::services::history::record_events;
+use crate::services::post_webhook::{post_webhook, verify_tailnet};
 use crate::State as MyState;
-use axum::extract::State;
-use axum::http::HeaderMap;
-use axum::response::IntoResponse;
-use color_eyre::eyre::eyre;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use color_eyre::eyre::Report;
 use tap::Tap;
-use tracing::info;
+use tracing::{info, warn};
 
 #[tracing::instrument]
 pub async fn webhook_handler(
     State(state): State<MyState>,
     headers: HeaderMap,
     body: String,
-) -> Result<impl IntoResponse> {
+) -> Result<Response> {
+    forward(&state, &state.settings().endpoint, &headers, &body).await
+}
+
+#[tracing::instrument]
+pub async fn tailnet_webhook_handler(
+    State(state): State<MyState>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Response> {
+    let settings = state.settings();
+    let Some(endpoint) = settings.tailnets.get(&name) else {
+        let status = StatusCode::NOT_FOUND;
+        warn!(%status, name, "Tailnet is not configured");
+        return Ok((status, format!("No tailnet {name}")).into_response());
+    };
+    forward(&state, endpoint, &headers, &body).await
+}
+
+async fn forward(
+    state: &MyState,
+    endpoint: &Endpoint,
+    headers: &HeaderMap,
+    body: &str,
+) -> Result<Response> {
+    state.metrics.record_webhook();
+    let events = verify(headers, body, endpoint).inspect_err(|error| {
+        if let Some(error) = error.downcast_ref::<TailscaleWebhook>() {
+            state.metrics.record_signature_failure(error.reason());
+        } else if error.downcast_ref::<serde_json::Error>().is_some() {
+            // The signature checked out, so the sender is Tailscale
+            state.metrics.record_invalid_body();
+        }
+    })?;
+    info!(?events, "Got events");
+    if let Err(error) = verify_tailnet(&events, endpoint.tailnet.as_deref()) {
+        let status = StatusCode::FORBIDDEN;
+        warn!(%status, %error, "Refused events from another tailnet");
+        return Ok((status, error.to_string()).into_response());
+    }
+    state.runtime.record_webhook();
+    for event in &events {
+        state.metrics.record_event(&event.r#type, &event.tailnet);
+    }
+
+    // Events sent again, as a sink failed last time, are delivered again but
+    // neither archived nor stored twice
+    let acceptance = state.runtime.accept(&endpoint.name, &events);
+    let new: Vec<_> = events
+        .iter()
+        .zip(&acceptance)
+        .filter(|(_, acceptance)| **acceptance == Acceptance::New)
+        .map(|(event, _)| event.clone())
+        .collect();
+    archive_events(state, endpoint, &new).await;
+    let stored = record_events(state, endpoint, &new).await;
+    state.runtime.record_rows(&endpoint.name, &new, &stored);
+    let mut stored = stored.into_iter();
+    let ids = acceptance
+        .into_iter()
+        .map(|acceptance| match acceptance {
+            Acceptance::New => stored.next(),
+            Acceptance::Again(id) => id,
+        })
+        .collect();
+    dispatch(state, endpoint, events, ids).await?;
+    Ok(StatusCode::OK.into_response())
+}
+
+/// Parses the signature header and checks the body against it
+fn verify(headers: &HeaderMap, body: &str, endpoint: &Endpoint) -> Result<Vec<Event>, Report> {
     let header_name = "Tailscale-Webhook-Signature";
 
     let header: Header = headers
         .get(header_name)
-        .ok_or_else(|| eyre!("No header {header_name} received, the request is not coming from Tailscale or the format has changed"))?
+        .ok_or_else(|| {
+            Report::new(TailscaleWebhook::MissingHeader)
+                .wrap_err(format!("No header {header_name} received, the request is not coming from Tailscale or the format has changed"))
+        })?
         .to_str()
-        .map_err(|err| eyre!("Header {header_name} contains non-ASCII characters, Tailscale sends ASCII only: {err}"))?
+        .map_err(|err| {
+            Report::new(TailscaleWebhook::InvalidHeader {
+                expected: "ASCII".to_owned(),
+                got: "non-ASCII characters".to_owned(),
+            })
+            .wrap_err(format!("Header {header_name} contains non-ASCII characters, Tailscale sends ASCII only: {err}"))
+        })?
         .tap_deref(|header_val| info!(header_val, "Received header {header_name}"))
         .parse()
-        .map_err(|err| eyre!("Header {header_name} is invalid: {err}"))?;
-
-    let ts_secret = state.settings.tailscale_secret;
-    let events = post_webhook(header, &body, &ts_secret)?;
-    info!(?events, "Got events");
+        .map_err(|err: TailscaleWebhook| {
+            let message = format!("Header {header_name} is invalid: {err}");
+            Report::new(err).wrap_err(message)
+        })?;
 
-    let tg_secret = state.settings.telegram_secret;
-    let reqwest_client = state.reqwest_client;
-    let chat_id = state
-        .settings
-        .base
-        .telegram
-        .chat_id
-        .ok_or(eyre!("Chat id can't be read"))?;
-    post(events, reqwest_client, tg_secret, chat_id).await?;
-    Ok(())
+    post_webhook(header, body, &endpoint.tailscale_secret)
 }
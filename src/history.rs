@@ -0,0 +1,493 @@
+use crate::models::Event;
+use camino::Utf8Path;
+use chrono::{DateTime, Utc};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Most events returned by a single query
+pub const MAX_LIMIT: u32 = 1000;
+
+/// Changes to the schema, `PRAGMA user_version` being the number applied
+const MIGRATIONS: &[&str] = &["
+    CREATE TABLE events (
+        id INTEGER PRIMARY KEY,
+        received INTEGER NOT NULL,
+        endpoint TEXT NOT NULL,
+        key_id TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        version INTEGER NOT NULL,
+        type TEXT NOT NULL,
+        tailnet TEXT NOT NULL,
+        message TEXT NOT NULL,
+        data TEXT
+    );
+    CREATE INDEX events_received ON events (received);
+    CREATE TABLE deliveries (
+        event_id INTEGER NOT NULL REFERENCES events (id) ON DELETE CASCADE,
+        sink TEXT NOT NULL,
+        at INTEGER NOT NULL,
+        outcome TEXT NOT NULL,
+        error TEXT
+    );
+    CREATE INDEX deliveries_event_id ON deliveries (event_id);
+"];
+
+/// Verified events and their deliveries, kept in `SQLite`
+#[derive(Debug)]
+pub struct Store {
+    connection: Mutex<Connection>,
+}
+
+/// Event as received, with where it came from and what became of it
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct Record {
+    pub id: i64,
+    pub received: DateTime<Utc>,
+    /// Tailnet name of the endpoint that verified the signature, empty for the default one
+    pub endpoint: String,
+    /// Fingerprint of the webhook secret that verified the signature
+    pub key_id: String,
+    pub event: Event,
+    pub deliveries: Vec<Delivery>,
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct Delivery {
+    pub sink: String,
+    pub at: DateTime<Utc>,
+    pub outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Delivered,
+    Failed,
+    Muted,
+}
+
+impl Outcome {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Delivered => "delivered",
+            Self::Failed => "failed",
+            Self::Muted => "muted",
+        }
+    }
+
+    fn parse(outcome: &str) -> Self {
+        match outcome {
+            "delivered" => Self::Delivered,
+            "muted" => Self::Muted,
+            _ => Self::Failed,
+        }
+    }
+}
+
+/// Filters of a query, every one that is set having to match
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Query {
+    pub r#type: Option<String>,
+    pub tailnet: Option<String>,
+    /// Received at or after
+    pub since: Option<DateTime<Utc>>,
+    /// Received before
+    pub until: Option<DateTime<Utc>>,
+    /// Text found in the message or data, ignoring ASCII case
+    pub q: Option<String>,
+    /// Only events whose last delivery failed, the dead letters
+    pub failed: bool,
+    /// Only events with a lower ID, to page through results
+    pub before: Option<i64>,
+    /// Only events with a higher ID, returned oldest first instead
+    pub after: Option<i64>,
+    /// Most events to return
+    pub limit: Option<u32>,
+}
+
+impl Store {
+    /// Opens the database, creating it and bringing its schema up to date
+    ///
+    /// # Errors
+    /// If the database can't be opened or migrated
+    pub fn open(path: &Utf8Path) -> rusqlite::Result<Self> {
+        Self::new(Connection::open(path)?)
+    }
+
+    /// Database in memory, gone once dropped
+    ///
+    /// # Errors
+    /// If the schema can't be created
+    pub fn in_memory() -> rusqlite::Result<Self> {
+        Self::new(Connection::open_in_memory()?)
+    }
+
+    fn new(mut connection: Connection) -> rusqlite::Result<Self> {
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.pragma_update(None, "synchronous", "NORMAL")?;
+        connection.pragma_update(None, "foreign_keys", true)?;
+        let applied: usize =
+            connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        let transaction = connection.transaction()?;
+        for migration in MIGRATIONS.iter().skip(applied) {
+            transaction.execute_batch(migration)?;
+        }
+        transaction.pragma_update(None, "user_version", MIGRATIONS.len())?;
+        transaction.commit()?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.connection
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Stores events received together, returning their IDs in the same order
+    ///
+    /// # Errors
+    /// If the database can't be written to
+    pub fn insert(
+        &self,
+        received: DateTime<Utc>,
+        endpoint: &str,
+        key_id: &str,
+        events: &[Event],
+    ) -> rusqlite::Result<Vec<i64>> {
+        let mut connection = self.lock();
+        let transaction = connection.transaction()?;
+        let mut ids = Vec::with_capacity(events.len());
+        {
+            let mut insert = transaction.prepare_cached(
+                "INSERT INTO events
+                 (received, endpoint, key_id, timestamp, version, type, tailnet, message, data)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )?;
+            for event in events {
+                insert.execute(params![
+                    received.timestamp_millis(),
+                    endpoint,
+                    key_id,
+                    event.timestamp.timestamp_millis(),
+                    event.version,
+                    event.r#type,
+                    event.tailnet,
+                    event.message,
+                    event.data.as_ref().map(ToString::to_string),
+                ])?;
+                ids.push(transaction.last_insert_rowid());
+            }
+        }
+        transaction.commit()?;
+        drop(connection);
+        Ok(ids)
+    }
+
+    /// Records what became of events sent to a sink
+    ///
+    /// # Errors
+    /// If the database can't be written to
+    pub fn record_delivery(
+        &self,
+        ids: &[i64],
+        sink: &str,
+        outcome: Outcome,
+        error: Option<&str>,
+    ) -> rusqlite::Result<()> {
+        let mut connection = self.lock();
+        let transaction = connection.transaction()?;
+        {
+            let mut insert = transaction.prepare_cached(
+                "INSERT INTO deliveries (event_id, sink, at, outcome, error) VALUES (?, ?, ?, ?, ?)",
+            )?;
+            let at = Utc::now().timestamp_millis();
+            for id in ids {
+                insert.execute(params![id, sink, at, outcome.as_str(), error])?;
+            }
+        }
+        transaction.commit()?;
+        drop(connection);
+        Ok(())
+    }
+
+    /// Events matching the query, newest first unless `after` is set
+    ///
+    /// # Errors
+    /// If the database can't be read
+    pub fn query(&self, query: &Query) -> rusqlite::Result<Vec<Record>> {
+        let mut conditions = Vec::new();
+        let mut values = Vec::new();
+        if let Some(r#type) = &query.r#type {
+            conditions.push("type = ?");
+            values.push(Value::Text(r#type.clone()));
+        }
+        if let Some(tailnet) = &query.tailnet {
+            conditions.push("tailnet = ?");
+            values.push(Value::Text(tailnet.clone()));
+        }
+        if let Some(since) = query.since {
+            conditions.push("received >= ?");
+            values.push(Value::Integer(since.timestamp_millis()));
+        }
+        if let Some(until) = query.until {
+            conditions.push("received < ?");
+            values.push(Value::Integer(until.timestamp_millis()));
+        }
+        if query.failed {
+            conditions.push(
+                "(SELECT outcome FROM deliveries WHERE event_id = events.id
+                  ORDER BY rowid DESC LIMIT 1) = 'failed'",
+            );
+        }
+        if let Some(before) = query.before {
+            conditions.push("id < ?");
+            values.push(Value::Integer(before));
+        }
+        if let Some(after) = query.after {
+            conditions.push("id > ?");
+            values.push(Value::Integer(after));
+        }
+        if let Some(text) = &query.q {
+            let pattern = Value::Text(format!("%{}%", escape_like(text)));
+            conditions.push(r"(message LIKE ? ESCAPE '\' OR data LIKE ? ESCAPE '\')");
+            values.extend([pattern.clone(), pattern]);
+        }
+        let clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+        let limit = query.limit.unwrap_or(100).min(MAX_LIMIT);
+        let order = if query.after.is_some() { "ASC" } else { "DESC" };
+
+        let connection = self.lock();
+        let mut statement = connection.prepare(&format!(
+            "SELECT id, received, endpoint, key_id, timestamp, version, type, tailnet, message, data
+             FROM events {clause} ORDER BY id {order} LIMIT {limit}"
+        ))?;
+        let mut records = statement
+            .query_map(params_from_iter(values), record)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut deliveries = connection.prepare_cached(
+            "SELECT sink, at, outcome, error FROM deliveries WHERE event_id = ? ORDER BY rowid",
+        )?;
+        for record in &mut records {
+            record.deliveries = deliveries
+                .query_map([record.id], delivery)?
+                .collect::<rusqlite::Result<_>>()?;
+        }
+        drop((statement, deliveries));
+        drop(connection);
+        Ok(records)
+    }
+
+    /// Looks an event up by ID
+    ///
+    /// # Errors
+    /// If the database can't be read
+    pub fn get(&self, id: i64) -> rusqlite::Result<Option<Record>> {
+        let mut records = self.query(&Query {
+            before: Some(id.saturating_add(1)),
+            limit: Some(1),
+            ..Query::default()
+        })?;
+        Ok(records.pop().filter(|record| record.id == id))
+    }
+
+    /// Removes events received before `before` and all but the newest `keep`,
+    /// returning how many were removed
+    ///
+    /// # Errors
+    /// If the database can't be written to
+    pub fn prune(
+        &self,
+        before: Option<DateTime<Utc>>,
+        keep: Option<u64>,
+    ) -> rusqlite::Result<usize> {
+        let connection = self.lock();
+        let mut removed = 0;
+        if let Some(before) = before {
+            removed += connection.execute(
+                "DELETE FROM events WHERE received < ?",
+                [before.timestamp_millis()],
+            )?;
+        }
+        if let Some(keep) = keep {
+            let newest_removed: Option<i64> = connection
+                .query_row(
+                    "SELECT id FROM events ORDER BY id DESC LIMIT 1 OFFSET ?",
+                    [i64::try_from(keep).unwrap_or(i64::MAX)],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if let Some(id) = newest_removed {
+                removed += connection.execute("DELETE FROM events WHERE id <= ?", [id])?;
+            }
+        }
+        drop(connection);
+        Ok(removed)
+    }
+}
+
+fn record(row: &Row<'_>) -> rusqlite::Result<Record> {
+    let data: Option<String> = row.get(9)?;
+    Ok(Record {
+        id: row.get(0)?,
+        received: millis(row.get(1)?),
+        endpoint: row.get(2)?,
+        key_id: row.get(3)?,
+        event: Event {
+            timestamp: millis(row.get(4)?),
+            version: row.get(5)?,
+            r#type: row.get(6)?,
+            tailnet: row.get(7)?,
+            message: row.get(8)?,
+            data: data.and_then(|data| serde_json::from_str(&data).ok()),
+        },
+        deliveries: Vec::new(),
+    })
+}
+
+fn delivery(row: &Row<'_>) -> rusqlite::Result<Delivery> {
+    let outcome: String = row.get(2)?;
+    Ok(Delivery {
+        sink: row.get(0)?,
+        at: millis(row.get(1)?),
+        outcome: Outcome::parse(&outcome),
+        error: row.get(3)?,
+    })
+}
+
+fn millis(millis: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(millis).unwrap_or_default()
+}
+
+/// Escapes the wildcards of `LIKE`, `\` being the escape character
+fn escape_like(text: &str) -> String {
+    text.replace('\\', r"\\")
+        .replace('%', r"\%")
+        .replace('_', r"\_")
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use serde_json::json;
+    use test_case::test_case;
+
+    fn event(r#type: &str, tailnet: &str, message: &str) -> Event {
+        Event {
+            timestamp: Utc::now(),
+            version: 1,
+            r#type: r#type.to_owned(),
+            tailnet: tailnet.to_owned(),
+            message: message.to_owned(),
+            data: Some(json!({"deviceName": "laptop_1.example.ts.net"})),
+        }
+    }
+
+    fn store() -> Store {
+        let store = Store::in_memory().unwrap();
+        let now = Utc::now();
+        store
+            .insert(
+                now - Duration::days(2),
+                "",
+                "k1",
+                &[event("nodeCreated", "example.com", "Node laptop created")],
+            )
+            .unwrap();
+        store
+            .insert(
+                now,
+                "corp",
+                "k2",
+                &[
+                    event(
+                        "nodeKeyExpired",
+                        "corp.example",
+                        "Node key of laptop expired",
+                    ),
+                    event("userCreated", "corp.example", "User 100% created"),
+                ],
+            )
+            .unwrap();
+        store
+    }
+
+    #[test_case(&Query::default() => vec![3, 2, 1]; "when unfiltered")]
+    #[test_case(&Query { r#type: Some("nodeKeyExpired".to_owned()), ..Query::default() } => vec![2]; "when by type")]
+    #[test_case(&Query { tailnet: Some("example.com".to_owned()), ..Query::default() } => vec![1]; "when by tailnet")]
+    #[test_case(&Query { since: Some(Utc::now() - Duration::days(1)), ..Query::default() } => vec![3, 2]; "when since")]
+    #[test_case(&Query { until: Some(Utc::now() - Duration::days(1)), ..Query::default() } => vec![1]; "when until")]
+    #[test_case(&Query { q: Some("KEY".to_owned()), ..Query::default() } => vec![2]; "when text in message")]
+    #[test_case(&Query { q: Some("laptop_1".to_owned()), ..Query::default() } => vec![3, 2, 1]; "when text in data")]
+    #[test_case(&Query { q: Some("100%".to_owned()), ..Query::default() } => vec![3]; "when text has wildcards")]
+    #[test_case(&Query { q: Some("laptop".to_owned()), tailnet: Some("corp.example".to_owned()), ..Query::default() } => vec![3, 2]; "when combined")]
+    #[test_case(&Query { before: Some(3), limit: Some(1), ..Query::default() } => vec![2]; "when paging")]
+    #[test_case(&Query { after: Some(1), ..Query::default() } => vec![2, 3]; "when paging forward")]
+    #[test_case(&Query { failed: true, ..Query::default() } => vec![2]; "when failed")]
+    fn filters(query: &Query) -> Vec<i64> {
+        let store = store();
+        store
+            .record_delivery(&[1, 2], "telegram", Outcome::Failed, Some("boom"))
+            .unwrap();
+        store
+            .record_delivery(&[1], "telegram", Outcome::Delivered, None)
+            .unwrap();
+        store
+            .query(query)
+            .unwrap()
+            .iter()
+            .map(|record| record.id)
+            .collect()
+    }
+
+    #[test]
+    fn keeps_deliveries() {
+        let store = store();
+        store
+            .record_delivery(&[2, 3], "telegram", Outcome::Failed, Some("boom"))
+            .unwrap();
+        store
+            .record_delivery(&[2], "telegram", Outcome::Delivered, None)
+            .unwrap();
+
+        let record = store.get(2).unwrap().unwrap();
+        let outcomes: Vec<_> = record.deliveries.iter().map(|d| d.outcome).collect();
+
+        assert_eq!(record.endpoint, "corp");
+        assert_eq!(record.key_id, "k2");
+        assert_eq!(outcomes, vec![Outcome::Failed, Outcome::Delivered]);
+        assert_eq!(record.deliveries[0].error.as_deref(), Some("boom"));
+        assert_eq!(store.get(4).unwrap(), None);
+    }
+
+    #[test_case(Some(1), None => vec![3, 2]; "when by age")]
+    #[test_case(None, Some(1) => vec![3]; "when by count")]
+    #[test_case(None, Some(5) => vec![3, 2, 1]; "when under count")]
+    fn prunes(days: Option<i64>, keep: Option<u64>) -> Vec<i64> {
+        let store = store();
+        store
+            .record_delivery(&[1], "telegram", Outcome::Delivered, None)
+            .unwrap();
+        store
+            .prune(days.map(|days| Utc::now() - Duration::days(days)), keep)
+            .unwrap();
+        store
+            .query(&Query::default())
+            .unwrap()
+            .iter()
+            .map(|record| record.id)
+            .collect()
+    }
+}
@@ -1,13 +1,40 @@
+pub mod archive;
+pub mod cli;
 pub mod config;
+pub mod dashboard;
+pub mod export;
+pub mod history;
+pub mod listen;
+pub mod logging;
+pub mod metrics;
+mod middleware;
+pub mod redact;
+pub mod runtime;
+pub mod secret;
+pub mod systemd;
+pub mod telemetry;
+pub mod tls;
 
 pub mod handlers {
+    mod api;
+    pub use api::{event_handler, events_handler, export_handler};
+    mod dashboard;
+    pub use dashboard::{dashboard_handler, replay_handler};
+    mod health;
+    pub use health::{healthz_handler, readyz_handler};
+    mod metrics;
+    pub use metrics::metrics_handler;
     mod post_webhook;
-    pub use post_webhook::webhook_handler;
+    pub use post_webhook::{tailnet_webhook_handler, webhook_handler};
     mod ping;
     pub use ping::ping_handler;
 }
 
 pub mod models {
+    pub mod approval;
+
+    pub mod command;
+
     pub mod error;
     pub use error::TailscaleWebhook;
 
@@ -17,29 +44,60 @@ pub mod models {
     pub mod message;
     pub use message::Message;
 
+    pub mod notification;
+    pub use notification::Notification;
+
     pub mod report;
 
+    pub mod tailscale_api;
+
     pub mod tailscale_header;
     pub use tailscale_header::Header;
+
+    pub mod telegram;
 }
 
 mod services {
+    pub mod archive;
+    pub mod dispatch;
+    pub mod health;
+    pub mod history;
+    pub mod key_expiry;
     pub mod post_webhook;
+    pub mod reload;
+    pub mod tailscale_api;
     pub mod telegram;
+    pub mod telegram_updates;
 }
+pub use services::key_expiry::remind_key_expiry;
+pub use services::reload::{reload_config, run_workers};
+pub use services::telegram_updates::receive_updates;
 
+use crate::archive::Archive;
 use crate::config::Application;
+use crate::history::Store;
+use crate::metrics::Metrics;
+use crate::redact::Redacted;
+use crate::runtime::Runtime;
+use axum::extract::DefaultBodyLimit;
 use axum::http::StatusCode;
 use axum::routing::{get, post, Router};
 use color_eyre::eyre::Result;
-use handlers::{ping_handler, webhook_handler};
-use opentelemetry::trace::TracerProvider;
-use tokio::signal;
+use handlers::{
+    dashboard_handler, event_handler, events_handler, export_handler, healthz_handler,
+    metrics_handler, ping_handler, readyz_handler, replay_handler, tailnet_webhook_handler,
+    webhook_handler,
+};
+use std::sync::Arc;
+use tailforward_cfg::{config::Service, Config};
+use tokio::{
+    signal,
+    sync::{watch, Semaphore},
+};
 use tower_http::trace::TraceLayer;
 use tracing::{info, warn};
 use tracing_error::ErrorLayer;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Registry};
-use tracing_tree::HierarchicalLayer;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Registry};
 
 #[tracing::instrument]
 #[allow(clippy::expect_used, clippy::redundant_pub_crate)]
@@ -69,6 +127,7 @@ pub async fn shutdown_signal() {
     }
 
     info!("Starting graceful shutdown");
+    systemd::stopping();
 }
 
 #[tracing::instrument]
@@ -84,53 +143,165 @@ async fn fallback(uri: axum::http::Uri) -> impl axum::response::IntoResponse {
 
 #[derive(Clone, Debug)]
 pub struct State {
-    pub settings: Application,
+    settings: Arc<watch::Sender<Arc<Application>>>,
     pub reqwest_client: reqwest::Client,
+    pub runtime: Arc<Runtime>,
+    pub metrics: Arc<Metrics>,
+    /// Event history, if enabled
+    pub history: Option<Arc<Store>>,
+    /// JSON Lines archive, if enabled
+    pub archive: Option<Arc<Archive>>,
 }
 
-#[allow(clippy::missing_errors_doc)]
-pub fn setup_tracing() -> Result<()> {
-    // Create env filter
-    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+impl State {
+    #[must_use]
+    pub fn new(settings: Application) -> Self {
+        let reqwest_client = reqwest::Client::new();
+        info!("Created reqwest client");
+
+        Self {
+            settings: Arc::new(watch::Sender::new(Arc::new(settings))),
+            reqwest_client,
+            runtime: Arc::default(),
+            metrics: Arc::default(),
+            history: None,
+            archive: None,
+        }
+    }
 
-    // Install a new OpenTelemetry trace pipeline
-    let provider = opentelemetry_otlp::new_pipeline()
-        .tracing()
-        .with_exporter(opentelemetry_otlp::new_exporter().tonic())
-        .install_batch(opentelemetry_sdk::runtime::Tokio)?;
-    let tracer = provider.tracer("tailforward");
-    let telemetry_layer = tracing_opentelemetry::layer().with_tracer(tracer);
+    /// Keeps verified events and their deliveries in `store`
+    #[must_use]
+    pub fn with_history(self, store: Store) -> Self {
+        Self {
+            history: Some(Arc::new(store)),
+            ..self
+        }
+    }
+
+    /// Appends verified events to `archive`
+    #[must_use]
+    pub fn with_archive(self, archive: Archive) -> Self {
+        Self {
+            archive: Some(Arc::new(archive)),
+            ..self
+        }
+    }
+
+    /// Configuration currently in effect
+    #[must_use]
+    pub fn settings(&self) -> Arc<Application> {
+        self.settings.borrow().clone()
+    }
+
+    /// Atomically replaces the configuration for every request and task after this one
+    pub fn replace_settings(&self, settings: Application) {
+        self.settings.send_replace(Arc::new(settings));
+    }
+
+    /// Notified whenever the configuration is replaced
+    #[must_use]
+    pub fn subscribe(&self) -> watch::Receiver<Arc<Application>> {
+        self.settings.subscribe()
+    }
+}
+
+/// Sets up logging and trace export as configured
+///
+/// # Errors
+/// If the log filter is invalid or an output or exporter can't be set up
+pub fn setup_tracing(config: &Config) -> Result<()> {
+    let rust_log = std::env::var("RUST_LOG").ok();
+    let env_filter = logging::filter(config, rust_log.as_deref())?;
+
+    let telemetry_layer = telemetry::layer(&config.telemetry)?;
 
     Registry::default()
+        .with(logging::output(config)?)
         .with(env_filter)
-        .with(
-            HierarchicalLayer::new(2)
-                .with_targets(true)
-                .with_bracketed_fields(true),
-        )
         .with(ErrorLayer::default())
-        .with(telemetry_layer)
+        .with(Redacted(telemetry_layer))
         .init();
 
-    info!("Initialized tracing and logging systems");
+    info!(format = ?config.log.format, debug = config.debug, "Initialized tracing and logging systems");
 
     Ok(())
 }
 
+/// Every route, except metrics when they have an address of their own
 #[tracing::instrument]
-pub fn setup_app(settings: Application) -> Result<Router> {
-    let reqwest_client = reqwest::Client::new();
-    info!("Created reqwest client");
-
-    let state = State {
-        settings,
-        reqwest_client,
-    };
+pub fn setup_app(state: State) -> Result<Router> {
+    let separate_metrics = state.settings().base.metrics.address.is_some();
+    let services: Vec<_> = Service::ALL
+        .iter()
+        .copied()
+        .filter(|service| !(separate_metrics && *service == Service::Metrics))
+        .collect();
+    Ok(router(state, &services))
+}
 
-    Ok(Router::new()
-        .fallback(fallback)
-        .route("/tailscale-webhook", post(webhook_handler))
-        .route("/ping", get(ping_handler))
+/// Routes of the given services, metrics and the dashboard being left out if
+/// disabled, and the API and dashboard without a history
+#[tracing::instrument]
+pub fn router(state: State, services: &[Service]) -> Router {
+    let mut router = Router::new().fallback(fallback);
+    if services.contains(&Service::Webhooks) {
+        let webhooks = Router::new()
+            .route("/tailscale-webhook", post(webhook_handler))
+            .route("/tailscale-webhook/:name", post(tailnet_webhook_handler))
+            .route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                middleware::guard_webhook,
+            ))
+            // `guard_webhook` enforces `requests.max_body_size` instead
+            .layer(DefaultBodyLimit::disable());
+        router = router.merge(webhooks).route("/ping", get(ping_handler));
+    }
+    if services.contains(&Service::Health) {
+        let dedicated = !services.contains(&Service::Webhooks);
+        router = router.route("/healthz", get(healthz_handler)).route(
+            "/readyz",
+            get(move |state, headers| readyz_handler(state, headers, dedicated)),
+        );
+    }
+    if services.contains(&Service::Metrics) && state.settings().base.metrics.enabled {
+        router = router.route("/metrics", get(metrics_handler));
+    }
+    if services.contains(&Service::Api) && state.history.is_some() {
+        let api = Router::new()
+            .route("/api/events", get(events_handler))
+            .route("/api/events/:id", get(event_handler))
+            .route("/api/export/:format", get(export_handler))
+            .route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                middleware::authorize_api,
+            ));
+        router = router.merge(api);
+    }
+    if services.contains(&Service::Dashboard)
+        && state.history.is_some()
+        && state.settings().base.dashboard.enabled
+    {
+        let dashboard = Router::new()
+            .route("/dashboard", get(dashboard_handler))
+            .route("/dashboard/replay/:id", post(replay_handler))
+            .route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                middleware::authorize_dashboard,
+            ));
+        router = router.merge(dashboard);
+    }
+    // Permits are per listener, as the limit can't change without a restart
+    let permits = state
+        .settings()
+        .base
+        .requests
+        .concurrency_limit
+        .map(|limit| Arc::new(Semaphore::new(limit)));
+    let limited = state.clone();
+    router
+        .layer(axum::middleware::from_fn(move |request, next| {
+            middleware::limit(limited.clone(), permits.clone(), request, next)
+        }))
         .layer(TraceLayer::new_for_http())
-        .with_state(state))
+        .with_state(state)
 }
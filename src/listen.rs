@@ -0,0 +1,174 @@
+use axum::Router;
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use color_eyre::eyre::Result;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::{conn::auto, graceful::GracefulShutdown};
+use hyper_util::service::TowerToHyperService;
+use std::net::SocketAddr;
+use tailforward_cfg::{config::Listener, ListenAddress};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+/// Bound socket, ready to accept connections
+#[derive(Debug)]
+pub enum Socket {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+impl Socket {
+    /// Binds the address of a listener, setting the permissions and owner of Unix sockets
+    ///
+    /// # Errors
+    /// If the address can't be bound or the socket can't be given the permissions and owner
+    pub async fn bind(listener: &Listener) -> Result<Self> {
+        let socket = match &listener.address {
+            ListenAddress::Tcp(address) => Self::Tcp(TcpListener::bind(address).await?),
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => Self::Unix(unix::bind(path, listener)?),
+            #[cfg(not(unix))]
+            ListenAddress::Unix(_) => {
+                return Err(color_eyre::eyre::eyre!(
+                    "Unix sockets are only available on Unix"
+                ))
+            }
+        };
+        info!(address = %listener.address, services = ?listener.services, "Listening");
+        Ok(socket)
+    }
+}
+
+/// Serves `router` on `socket` until `stop` turns true, letting open connections finish
+///
+/// # Errors
+/// If the server fails
+pub async fn serve(
+    socket: Socket,
+    router: Router,
+    tls: Option<RustlsConfig>,
+    mut stop: watch::Receiver<bool>,
+) -> Result<()> {
+    match socket {
+        Socket::Tcp(listener) => {
+            if let Some(tls) = tls {
+                let handle = Handle::new();
+                tokio::spawn({
+                    let handle = handle.clone();
+                    async move {
+                        let _ = stop.wait_for(|stop| *stop).await;
+                        handle.graceful_shutdown(None);
+                    }
+                });
+                axum_server::from_tcp_rustls(listener.into_std()?, tls)
+                    .handle(handle)
+                    .serve(router.into_make_service_with_connect_info::<SocketAddr>())
+                    .await?;
+            } else {
+                axum::serve(
+                    listener,
+                    router.into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .with_graceful_shutdown(async move {
+                    let _ = stop.wait_for(|stop| *stop).await;
+                })
+                .await?;
+            }
+        }
+        #[cfg(unix)]
+        Socket::Unix(listener) => {
+            let builder = auto::Builder::new(TokioExecutor::new());
+            let graceful = GracefulShutdown::new();
+            loop {
+                let stream = tokio::select! {
+                    accepted = listener.accept() => match accepted {
+                        Ok((stream, _)) => stream,
+                        Err(error) => {
+                            warn!(?error, "Failed to accept connection");
+                            continue;
+                        }
+                    },
+                    _ = stop.wait_for(|stop| *stop) => break,
+                };
+                let connection = builder.serve_connection_with_upgrades(
+                    TokioIo::new(stream),
+                    TowerToHyperService::new(router.clone()),
+                );
+                let connection = graceful.watch(connection.into_owned());
+                tokio::spawn(async move {
+                    if let Err(error) = connection.await {
+                        debug!(?error, "Connection failed");
+                    }
+                });
+            }
+            graceful.shutdown().await;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+mod unix {
+    use camino::{Utf8Path, Utf8PathBuf};
+    use color_eyre::eyre::{eyre, Result, WrapErr};
+    use nix::unistd::{Gid, Group, Uid, User};
+    use std::fs::{self, Permissions};
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    use tailforward_cfg::config::Listener;
+    use tokio::net::UnixListener;
+
+    pub fn bind(path: &Utf8Path, listener: &Listener) -> Result<UnixListener> {
+        // Bound under another name and only moved in place once its mode and
+        // owner are set, so that it's never reachable with looser ones
+        let bound = Utf8PathBuf::from(format!("{path}.tmp"));
+        for path in [path, &bound] {
+            // A socket left behind by a previous run would make binding fail
+            if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+                fs::remove_file(path)
+                    .wrap_err_with(|| format!("Can't remove stale socket {path}"))?;
+            }
+        }
+        let socket = UnixListener::bind(&bound).wrap_err_with(|| format!("Can't bind {path}"))?;
+        let result = restrict(&bound, listener).and_then(|()| {
+            fs::rename(&bound, path).wrap_err_with(|| format!("Can't move socket to {path}"))
+        });
+        if result.is_err() {
+            let _ = fs::remove_file(&bound);
+        }
+        result.map(|()| socket)
+    }
+
+    fn restrict(path: &Utf8Path, listener: &Listener) -> Result<()> {
+        if let Some(mode) = &listener.mode {
+            let mode = u32::from_str_radix(mode, 8)?;
+            fs::set_permissions(path, Permissions::from_mode(mode))
+                .wrap_err_with(|| format!("Can't set the permissions of {path}"))?;
+        }
+        if listener.owner.is_some() || listener.group.is_some() {
+            let owner = listener.owner.as_deref().map(uid).transpose()?;
+            let group = listener.group.as_deref().map(gid).transpose()?;
+            std::os::unix::fs::chown(path, owner, group)
+                .wrap_err_with(|| format!("Can't change the owner of {path}"))?;
+        }
+        Ok(())
+    }
+
+    fn uid(user: &str) -> Result<u32> {
+        if let Ok(id) = user.parse() {
+            return Ok(id);
+        }
+        User::from_name(user)?
+            .map(|user| Uid::as_raw(user.uid))
+            .ok_or_else(|| eyre!("No user {user}"))
+    }
+
+    fn gid(group: &str) -> Result<u32> {
+        if let Ok(id) = group.parse() {
+            return Ok(id);
+        }
+        Group::from_name(group)?
+            .map(|group| Gid::as_raw(group.gid))
+            .ok_or_else(|| eyre!("No group {group}"))
+    }
+}
@@ -0,0 +1,194 @@
+use crate::redact::Redacted;
+use color_eyre::eyre::{eyre, Result};
+use tailforward_cfg::config::LogFormat;
+use tailforward_cfg::Config;
+use tracing_subscriber::filter::{Directive, EnvFilter, LevelFilter};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::{Layer, Registry};
+use tracing_tree::HierarchicalLayer;
+
+/// Layer writing log output
+pub type Output = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Directives used when neither `log.filter` nor `RUST_LOG` is set
+const fn default_directives(debug: bool) -> &'static str {
+    if debug {
+        "info,tailforward=debug,tailforward_cfg=debug,tower_http=debug"
+    } else {
+        "info"
+    }
+}
+
+/// Filter from the configuration, with directives from `rust_log` taking precedence
+///
+/// # Errors
+/// If either contains an invalid directive
+pub fn filter(config: &Config, rust_log: Option<&str>) -> Result<EnvFilter> {
+    let base = config
+        .log
+        .filter
+        .as_deref()
+        .unwrap_or_else(|| default_directives(config.debug));
+    let mut filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .parse(base)?;
+    for directive in rust_log
+        .into_iter()
+        .flat_map(|directives| directives.split(','))
+    {
+        if !directive.trim().is_empty() {
+            filter = filter.add_directive(directive.trim().parse::<Directive>()?);
+        }
+    }
+    Ok(filter)
+}
+
+/// Layer rendering logs in the configured format
+///
+/// # Errors
+/// If journald output is requested and the journal can't be reached
+pub fn output(config: &Config) -> Result<Output> {
+    let debug = config.debug;
+    let spans = if debug { FmtSpan::CLOSE } else { FmtSpan::NONE };
+    let output = match config.log.format {
+        LogFormat::Hierarchical => HierarchicalLayer::new(2)
+            .with_writer(std::io::stderr)
+            .with_targets(true)
+            .with_bracketed_fields(true)
+            .with_thread_ids(debug)
+            .with_verbose_exit(debug)
+            .boxed(),
+        LogFormat::Compact => tracing_subscriber::fmt::layer()
+            .compact()
+            .with_writer(std::io::stderr)
+            .with_file(debug)
+            .with_line_number(debug)
+            .with_thread_ids(debug)
+            .with_span_events(spans)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_writer(std::io::stderr)
+            .with_current_span(true)
+            .with_span_list(debug)
+            .with_file(debug)
+            .with_line_number(debug)
+            .with_thread_ids(debug)
+            .with_span_events(spans)
+            .boxed(),
+        LogFormat::Journald => journald()?,
+    };
+    Ok(redacted(output))
+}
+
+/// Scrubs secrets from whatever reaches `output`: formatted lines, and the
+/// fields journald is given as is
+fn redacted(output: Output) -> Output {
+    Redacted(output).boxed()
+}
+
+#[cfg(unix)]
+fn journald() -> Result<Output> {
+    let layer = tracing_journald::layer()
+        .map_err(|error| eyre!("Can't connect to journald: {error}"))?
+        .with_syslog_identifier("tailforward".to_owned());
+    Ok(layer.boxed())
+}
+
+#[cfg(not(unix))]
+fn journald() -> Result<Output> {
+    Err(eyre!("journald is only available on Unix"))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::redact::register;
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+    use tailforward_cfg::config::Log;
+    use test_case::test_case;
+    use tracing::field::{Field, Visit};
+    use tracing::{info, Event, Subscriber};
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+
+    /// Records event fields one by one, like the journald layer does
+    #[derive(Clone, Default)]
+    struct Journal(Arc<Mutex<Vec<String>>>);
+
+    impl Visit for Journal {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0.lock().unwrap().push(format!("{field}={value:?}"));
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for Journal {
+        fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+            event.record(&mut self.clone());
+        }
+    }
+
+    fn config(debug: bool, filter: Option<&str>) -> Config {
+        Config {
+            debug,
+            log: Log {
+                filter: filter.map(ToOwned::to_owned),
+                ..Log::default()
+            },
+            ..Config::default()
+        }
+    }
+
+    #[test_case(false, None, None => "info"; "by default")]
+    #[test_case(true, None, None => "debug"; "when debugging")]
+    #[test_case(true, Some("warn"), None => "warn"; "with configured filter")]
+    #[test_case(false, Some("warn"), Some("trace") => "trace"; "with RUST_LOG")]
+    #[test_case(false, Some("tailforward=warn"), Some("") => "warn"; "with empty RUST_LOG")]
+    fn max_level(debug: bool, filter: Option<&str>, rust_log: Option<&str>) -> String {
+        self::filter(&config(debug, filter), rust_log)
+            .unwrap()
+            .max_level_hint()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn rust_log_overrides_same_target() {
+        let filter = filter(
+            &config(false, Some("info,tailforward=debug")),
+            Some("tailforward=warn"),
+        )
+        .unwrap();
+        assert_eq!(filter.to_string(), "tailforward=warn,info");
+    }
+
+    #[test]
+    fn rejects_invalid_directives() {
+        assert!(filter(&config(false, None), Some("tailforward=loud")).is_err());
+    }
+
+    #[test]
+    fn journald_fields_are_redacted() {
+        register("hunter2-journald");
+        let journal = Journal::default();
+        let subscriber = Registry::default().with(redacted(journal.clone().boxed()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            info!(
+                secret = "hunter2-journald",
+                url = "https://api.telegram.org/bot42:token/getMe",
+                "Read secret"
+            );
+        });
+
+        assert_eq!(
+            *journal.0.lock().unwrap(),
+            [
+                "message=Read secret",
+                "secret=\"[REDACTED]\"",
+                "url=\"https://api.telegram.org/bot[REDACTED]/getMe\"",
+            ]
+        );
+    }
+}
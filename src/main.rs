@@ -1,23 +1,129 @@
-use color_eyre::eyre::Result;
-use tailforward::{config::new_config, setup_app, setup_tracing, shutdown_signal};
-use tap::Tap;
-use tokio::net::TcpListener;
-use tracing::debug;
+use axum_server::tls_rustls::RustlsConfig;
+use clap::Parser;
+use color_eyre::eyre::{Result, WrapErr};
+use std::env;
+use tailforward::{
+    archive::Archive,
+    cli::{
+        check_config, config_schema, curl, default_config, export_history, fire, send_test, Cli,
+        Command,
+    },
+    config::ignored_vars,
+    history::Store,
+    listen::{self, Socket},
+    reload_config, router, run_workers, setup_tracing, shutdown_signal, systemd, telemetry, tls,
+    State,
+};
+use tokio::{sync::watch, task::JoinSet};
+use tracing::{debug, info, warn};
 
 #[tokio::main]
 #[tracing::instrument]
 async fn main() -> Result<()> {
-    setup_tracing()?;
+    let cli = Cli::parse();
     color_eyre::install()?;
-    let settings = new_config()?.tap(|settings| debug!(?settings, "Read settings"));
 
-    let addr = settings.base.address;
-    let app = setup_app(settings)?;
-    let listener = TcpListener::bind(&addr).await?;
-    axum::serve(listener, app.into_make_service())
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
+    match &cli.command {
+        None | Some(Command::Serve) => serve(&cli).await?,
+        Some(Command::CheckConfig) => {
+            let settings = cli.settings()?;
+            let ignored = ignored_vars(env::vars(), &settings.secret_sources());
+            println!("{}", check_config(&settings, &ignored));
+        }
+        Some(Command::PrintDefaultConfig) => print!("{}", default_config()?),
+        Some(Command::PrintSchema) => println!("{}", config_schema()?),
+        Some(Command::SendTest { tailnet, message }) => {
+            let settings = cli.settings()?;
+            setup_tracing(&settings.base)?;
+            let state = State::new(settings);
+            send_test(&state, tailnet.as_deref(), message).await?;
+            telemetry::shutdown();
+        }
+        Some(Command::Sign { webhook, url }) => {
+            let (header, body) = webhook.sign(&cli)?;
+            println!("{}", curl(url, &header, &body));
+        }
+        Some(Command::Fire { webhook, url }) => {
+            let (header, body) = webhook.sign(&cli)?;
+            println!("{}", fire(url, &header, body).await?);
+        }
+        Some(Command::Export {
+            filters,
+            format,
+            output,
+        }) => {
+            let exported = export_history(&cli, filters, *format, output.as_deref())?;
+            eprintln!("Exported {exported} events");
+        }
+    }
+    Ok(())
+}
+
+async fn serve(cli: &Cli) -> Result<()> {
+    // Tracing is set up from the configuration, so reading it can't be traced
+    let settings = cli.settings()?;
+    setup_tracing(&settings.base)?;
+    debug!(?settings, "Read settings");
+    for problem in ignored_vars(env::vars(), &settings.secret_sources()) {
+        warn!(%problem, "Ignored environment variable");
+    }
+
+    let listeners = settings.listeners();
+    let tls = match settings.base.tls.clone() {
+        Some(tls) => {
+            let config = RustlsConfig::from_config(tls::server_config(&tls)?);
+            tokio::spawn(tls::watch(config.clone(), tls));
+            Some(config)
+        }
+        None => None,
+    };
+    let mut state = State::new(settings);
+    let history = state.settings().base.history.clone();
+    if history.enabled {
+        let store = Store::open(&history.path)
+            .wrap_err_with(|| format!("Can't open the event history {}", history.path))?;
+        state = state.with_history(store);
+        info!(path = %history.path, "Opened event history");
+    }
+    let archive = state.settings().base.archive.clone();
+    if archive.enabled {
+        let opened = Archive::open(&archive.directory)
+            .wrap_err_with(|| format!("Can't open the archive in {}", archive.directory))?;
+        state = state.with_archive(opened);
+        info!(directory = %archive.directory, "Opened archive");
+    }
+    tokio::spawn(run_workers(state.clone()));
+    tokio::spawn(reload_config(
+        state.clone(),
+        cli.config_file(),
+        cli.overrides.clone(),
+    ));
+
+    // With socket activation, the sockets are taken in the order of the listeners
+    let mut activated = systemd::listeners()?.into_iter();
+    let (stop, stopped) = watch::channel(false);
+    let mut servers = JoinSet::new();
+    for listener in &listeners {
+        let socket = match activated.next() {
+            Some(socket) => socket,
+            None => Socket::bind(listener).await?,
+        };
+        let app = router(state.clone(), &listener.services);
+        let tls = tls.clone().filter(|_| listener.tls);
+        servers.spawn(listen::serve(socket, app, tls, stopped.clone()));
+    }
+    tokio::spawn(systemd::watchdog(state));
+    systemd::ready();
+
+    tokio::select! {
+        () = shutdown_signal() => {}
+        Some(result) = servers.join_next() => result??,
+    }
+    stop.send_replace(true);
+    while let Some(result) = servers.join_next().await {
+        result??;
+    }
 
-    opentelemetry::global::shutdown_tracer_provider();
+    telemetry::shutdown();
     Ok(())
 }
@@ -0,0 +1,225 @@
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::metrics::histogram::{exponential_buckets, Histogram};
+use prometheus_client::registry::Registry;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct ReasonLabels {
+    reason: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct EventLabels {
+    r#type: String,
+    tailnet: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct DeliveryLabels {
+    sink: String,
+    outcome: &'static str,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct SinkLabels {
+    sink: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct OperationLabels {
+    operation: String,
+}
+
+/// Prometheus metrics, rendered on `/metrics`
+pub struct Metrics {
+    registry: Registry,
+    webhooks: Counter,
+    signature_failures: Family<ReasonLabels, Counter>,
+    invalid_bodies: Counter,
+    events: Family<EventLabels, Counter>,
+    deliveries: Family<DeliveryLabels, Counter>,
+    delivery_seconds: Family<SinkLabels, Histogram>,
+    queue_depth: Gauge,
+    retries: Family<OperationLabels, Counter>,
+    rejections: Family<ReasonLabels, Counter>,
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let mut registry = Registry::with_prefix("tailforward");
+        let webhooks = Counter::default();
+        registry.register("webhooks", "Webhook requests received", webhooks.clone());
+        let signature_failures = Family::default();
+        registry.register(
+            "signature_failures",
+            "Webhooks rejected while checking the signature, by reason",
+            signature_failures.clone(),
+        );
+        let invalid_bodies = Counter::default();
+        registry.register(
+            "invalid_bodies",
+            "Webhooks with a valid signature whose body isn't a list of events",
+            invalid_bodies.clone(),
+        );
+        let events = Family::default();
+        registry.register(
+            "events",
+            "Events received, by type and tailnet",
+            events.clone(),
+        );
+        let deliveries = Family::default();
+        registry.register(
+            "deliveries",
+            "Deliveries to sinks, by outcome",
+            deliveries.clone(),
+        );
+        let delivery_seconds = Family::<SinkLabels, Histogram>::new_with_constructor(|| {
+            Histogram::new(exponential_buckets(0.05, 2.0, 10))
+        });
+        registry.register(
+            "delivery_seconds",
+            "Time taken by deliveries to sinks",
+            delivery_seconds.clone(),
+        );
+        let queue_depth = Gauge::default();
+        registry.register("queue_depth", "Deliveries in flight", queue_depth.clone());
+        let retries = Family::default();
+        registry.register(
+            "retries",
+            "Retried calls to external services, by operation",
+            retries.clone(),
+        );
+        let rejections = Family::default();
+        registry.register(
+            "rejections",
+            "Requests rejected before reaching a handler, by reason",
+            rejections.clone(),
+        );
+
+        Self {
+            registry,
+            webhooks,
+            signature_failures,
+            invalid_bodies,
+            events,
+            deliveries,
+            delivery_seconds,
+            queue_depth,
+            retries,
+            rejections,
+        }
+    }
+}
+
+impl Metrics {
+    pub fn record_webhook(&self) {
+        self.webhooks.inc();
+    }
+
+    pub fn record_signature_failure(&self, reason: &str) {
+        self.signature_failures
+            .get_or_create(&ReasonLabels {
+                reason: reason.to_owned(),
+            })
+            .inc();
+    }
+
+    pub fn record_invalid_body(&self) {
+        self.invalid_bodies.inc();
+    }
+
+    pub fn record_event(&self, r#type: &str, tailnet: &str) {
+        self.events
+            .get_or_create(&EventLabels {
+                r#type: r#type.to_owned(),
+                tailnet: tailnet.to_owned(),
+            })
+            .inc();
+    }
+
+    pub fn record_delivery(&self, sink: &str, success: bool, elapsed: Duration) {
+        self.deliveries
+            .get_or_create(&DeliveryLabels {
+                sink: sink.to_owned(),
+                outcome: if success { "success" } else { "failure" },
+            })
+            .inc();
+        self.delivery_seconds
+            .get_or_create(&SinkLabels {
+                sink: sink.to_owned(),
+            })
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn record_retry(&self, operation: &str) {
+        self.retries
+            .get_or_create(&OperationLabels {
+                operation: operation.to_owned(),
+            })
+            .inc();
+    }
+
+    pub fn record_rejection(&self, reason: &str) {
+        self.rejections
+            .get_or_create(&ReasonLabels {
+                reason: reason.to_owned(),
+            })
+            .inc();
+    }
+
+    /// Text exposition format, with the queue depth as of now
+    ///
+    /// # Errors
+    /// If a metric can't be encoded
+    pub fn render(&self, queue_depth: usize) -> Result<String, fmt::Error> {
+        self.queue_depth
+            .set(i64::try_from(queue_depth).unwrap_or(i64::MAX));
+        let mut text = String::new();
+        prometheus_client::encoding::text::encode(&mut text, &self.registry)?;
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_recorded_metrics() {
+        let metrics = Metrics::default();
+        metrics.record_webhook();
+        metrics.record_signature_failure("invalid_signature");
+        metrics.record_invalid_body();
+        metrics.record_event("nodeCreated", "example.com");
+        metrics.record_delivery("telegram", true, Duration::from_millis(30));
+        metrics.record_retry("getUpdates");
+        metrics.record_rejection("forbidden");
+
+        let text = metrics.render(2).unwrap();
+
+        for line in [
+            "tailforward_webhooks_total 1",
+            "tailforward_signature_failures_total{reason=\"invalid_signature\"} 1",
+            "tailforward_invalid_bodies_total 1",
+            "tailforward_events_total{type=\"nodeCreated\",tailnet=\"example.com\"} 1",
+            "tailforward_deliveries_total{sink=\"telegram\",outcome=\"success\"} 1",
+            "tailforward_delivery_seconds_bucket{le=\"0.05\",sink=\"telegram\"} 1",
+            "tailforward_queue_depth 2",
+            "tailforward_retries_total{operation=\"getUpdates\"} 1",
+            "tailforward_rejections_total{reason=\"forbidden\"} 1",
+        ] {
+            assert!(text.contains(line), "{line} is missing from\n{text}");
+        }
+    }
+}
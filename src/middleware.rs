@@ -0,0 +1,257 @@
+use crate::config::{Application, Credentials};
+use crate::State;
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Request};
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use ipnet::IpNet;
+use secrecy::{ExposeSecret, SecretString};
+use sha2::{Digest, Sha256};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tracing::warn;
+
+/// Answers with `status` and counts the rejection
+fn reject(state: &State, status: StatusCode, reason: &str, message: &str) -> Response {
+    warn!(%status, reason, "Rejected request: {message}");
+    state.metrics.record_rejection(reason);
+    (status, message.to_owned()).into_response()
+}
+
+/// Applies the timeout and concurrency limit to every request
+pub async fn limit(
+    state: State,
+    permits: Option<Arc<Semaphore>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let _permit = match permits.map(Semaphore::try_acquire_owned) {
+        Some(Err(_)) => {
+            return reject(
+                &state,
+                StatusCode::SERVICE_UNAVAILABLE,
+                "overloaded",
+                "Too many requests at once",
+            )
+        }
+        Some(Ok(permit)) => Some(permit),
+        None => None,
+    };
+    let timeout = Duration::from_secs(state.settings().base.requests.timeout);
+    tokio::time::timeout(timeout, next.run(request))
+        .await
+        .unwrap_or_else(|_| {
+            reject(
+                &state,
+                StatusCode::REQUEST_TIMEOUT,
+                "timeout",
+                "Request took too long",
+            )
+        })
+}
+
+/// Checks the source address, content type and body size of webhooks
+pub async fn guard_webhook(
+    axum::extract::State(state): axum::extract::State<State>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let settings = state.settings();
+    let requests = &settings.base.requests;
+
+    if !requests.allow.is_empty() {
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| address.ip());
+        let client = client_ip(peer, request.headers(), &requests.trusted_proxies);
+        if !client.is_some_and(|client| requests.allow.iter().any(|net| net.contains(&client))) {
+            warn!(?peer, ?client, "Source address is not allowed");
+            return reject(
+                &state,
+                StatusCode::FORBIDDEN,
+                "forbidden",
+                "Source address is not allowed",
+            );
+        }
+    }
+
+    if requests.require_json && !is_json(request.headers()) {
+        return reject(
+            &state,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "content_type",
+            "Content-Type must be application/json",
+        );
+    }
+
+    let (parts, body) = request.into_parts();
+    let Ok(body) = axum::body::to_bytes(body, requests.max_body_size).await else {
+        return reject(
+            &state,
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "body_too_large",
+            "Body is too large or couldn't be read",
+        );
+    };
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+/// Lets API requests through only with the configured bearer token
+pub async fn authorize_api(
+    axum::extract::State(state): axum::extract::State<State>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !has_api_token(&state.settings(), request.headers()) {
+        return unauthorized(&state, "Bearer", "Missing or wrong bearer token");
+    }
+    next.run(request).await
+}
+
+/// Whether the request carries the configured API bearer token
+pub fn has_api_token(settings: &Application, headers: &HeaderMap) -> bool {
+    settings.api_token.as_ref().is_some_and(|token| {
+        credential(headers, "Bearer").is_some_and(|given| matches(given, token))
+    })
+}
+
+/// Lets dashboard requests through only with the configured credentials
+pub async fn authorize_dashboard(
+    axum::extract::State(state): axum::extract::State<State>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let settings = state.settings();
+    let headers = request.headers();
+    let (scheme, authorized) = match &settings.dashboard_auth {
+        Some(Credentials::Basic { username, password }) => {
+            let given = credential(headers, "Basic")
+                .and_then(|encoded| BASE64.decode(encoded).ok())
+                .and_then(|decoded| String::from_utf8(decoded).ok());
+            let authorized = given
+                .as_deref()
+                .and_then(|given| given.split_once(':'))
+                .is_some_and(|(given_username, given_password)| {
+                    // Both are compared, so that the time taken doesn't tell which is wrong
+                    let username_matches = given_username == username;
+                    matches(given_password, password) && username_matches
+                });
+            ("Basic realm=\"tailforward\", charset=\"UTF-8\"", authorized)
+        }
+        Some(Credentials::Bearer(token)) => (
+            "Bearer",
+            credential(headers, "Bearer").is_some_and(|given| matches(given, token)),
+        ),
+        None => ("Bearer", false),
+    };
+    if !authorized {
+        return unauthorized(&state, scheme, "Missing or wrong credentials");
+    }
+    next.run(request).await
+}
+
+/// Credentials of the `Authorization` header sent with `scheme`
+fn credential<'a>(headers: &'a HeaderMap, scheme: &str) -> Option<&'a str> {
+    let (given, credential) = headers.get(AUTHORIZATION)?.to_str().ok()?.split_once(' ')?;
+    given
+        .eq_ignore_ascii_case(scheme)
+        .then(|| credential.trim())
+}
+
+/// Compares digests, which takes the same time wherever the secrets differ
+fn matches(given: &str, secret: &SecretString) -> bool {
+    Sha256::digest(given) == Sha256::digest(secret.expose_secret())
+}
+
+fn unauthorized(state: &State, challenge: &'static str, message: &str) -> Response {
+    let mut response = reject(state, StatusCode::UNAUTHORIZED, "unauthorized", message);
+    response
+        .headers_mut()
+        .insert(WWW_AUTHENTICATE, HeaderValue::from_static(challenge));
+    response
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"))
+}
+
+/// Address of the client, walking `X-Forwarded-For` back from the peer for as long
+/// as addresses belong to trusted proxies; a missing peer is a Unix socket, so a
+/// local proxy
+fn client_ip(peer: Option<IpAddr>, headers: &HeaderMap, trusted: &[IpNet]) -> Option<IpAddr> {
+    let is_trusted = |address: &IpAddr| trusted.iter().any(|net| net.contains(address));
+    if peer.is_some_and(|peer| !is_trusted(&peer)) {
+        return peer;
+    }
+    let forwarded: Vec<IpAddr> = headers
+        .get_all("X-Forwarded-For")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|address| address.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    forwarded
+        .iter()
+        .rev()
+        .find(|address| !is_trusted(address))
+        .or_else(|| forwarded.first())
+        .copied()
+        .or(peer)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(Some("203.0.113.5"), None => Some("203.0.113.5".to_owned()); "when direct")]
+    #[test_case(Some("203.0.113.5"), Some("198.51.100.1") => Some("203.0.113.5".to_owned()); "when untrusted peer forwards")]
+    #[test_case(Some("10.0.0.1"), Some("198.51.100.1, 10.0.0.2") => Some("198.51.100.1".to_owned()); "when behind trusted proxies")]
+    #[test_case(Some("10.0.0.1"), Some("10.0.0.3, 10.0.0.2") => Some("10.0.0.3".to_owned()); "when every address is trusted")]
+    #[test_case(Some("10.0.0.1"), None => Some("10.0.0.1".to_owned()); "when trusted peer doesn't forward")]
+    #[test_case(None, Some("198.51.100.1") => Some("198.51.100.1".to_owned()); "when over unix socket")]
+    #[test_case(None, None => None; "when unknown")]
+    #[test_case(Some("10.0.0.1"), Some("not an address") => None; "when forwarded garbage")]
+    fn finds_client(peer: Option<&str>, forwarded: Option<&str>) -> Option<String> {
+        let mut headers = HeaderMap::new();
+        if let Some(forwarded) = forwarded {
+            headers.insert("X-Forwarded-For", forwarded.parse().unwrap());
+        }
+        let trusted = ["10.0.0.0/8".parse().unwrap()];
+        client_ip(peer.map(|peer| peer.parse().unwrap()), &headers, &trusted)
+            .map(|address| address.to_string())
+    }
+
+    #[test_case("Bearer abc", "Bearer" => Some("abc".to_owned()); "when bearer")]
+    #[test_case("bearer  abc ", "Bearer" => Some("abc".to_owned()); "when lowercase")]
+    #[test_case("Basic abc", "Bearer" => None; "when other scheme")]
+    #[test_case("abc", "Bearer" => None; "when no scheme")]
+    fn reads_credential(authorization: &str, scheme: &str) -> Option<String> {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, authorization.parse().unwrap());
+        credential(&headers, scheme).map(ToOwned::to_owned)
+    }
+
+    #[test_case(Some("application/json") => true; "when json")]
+    #[test_case(Some("Application/JSON; charset=utf-8") => true; "when json with charset")]
+    #[test_case(Some("text/plain") => false; "when text")]
+    #[test_case(None => false; "when missing")]
+    fn checks_json(content_type: Option<&str>) -> bool {
+        let mut headers = HeaderMap::new();
+        if let Some(content_type) = content_type {
+            headers.insert(CONTENT_TYPE, content_type.parse().unwrap());
+        }
+        is_json(&headers)
+    }
+}
@@ -0,0 +1,97 @@
+use super::error::Callback;
+use derive_more::Display;
+use std::str::FromStr;
+
+/// Longest callback data Telegram accepts, in bytes
+pub const MAX_CALLBACK_DATA: usize = 64;
+
+/// Decision taken with an inline button, carried in the callback data as
+/// `<decision>:<subject>:<tailnet>:<id>`, where tailnet is the reference of the
+/// configured tailnet (empty for the default one)
+///
+/// The ID comes last as it may contain colons.
+#[derive(Debug, PartialEq, Eq, Clone, Display)]
+#[display(fmt = "{decision}:{subject}:{tailnet}:{id}")]
+pub struct Approval {
+    pub decision: Decision,
+    pub subject: Subject,
+    pub id: String,
+    pub tailnet: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Display)]
+pub enum Decision {
+    #[display(fmt = "approve")]
+    Approve,
+    #[display(fmt = "reject")]
+    Reject,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Display)]
+pub enum Subject {
+    #[display(fmt = "node")]
+    Node,
+    #[display(fmt = "user")]
+    User,
+}
+
+impl FromStr for Approval {
+    type Err = Callback;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Callback::InvalidData(s.to_owned());
+        let mut parts = s.splitn(4, ':');
+        let decision = match parts.next() {
+            Some("approve") => Decision::Approve,
+            Some("reject") => Decision::Reject,
+            _ => return Err(invalid()),
+        };
+        let subject = match parts.next() {
+            Some("node") => Subject::Node,
+            Some("user") => Subject::User,
+            _ => return Err(invalid()),
+        };
+        let tailnet = parts.next().ok_or_else(invalid)?;
+        let id = parts
+            .next()
+            .filter(|id| !id.is_empty())
+            .ok_or_else(invalid)?;
+        Ok(Self {
+            decision,
+            subject,
+            id: id.to_owned(),
+            tailnet: tailnet.to_owned(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("approve:node::n1" => matches Ok(_); "when default tailnet")]
+    #[test_case("reject:user:corp:u1" => matches Ok(_); "when named tailnet")]
+    #[test_case("approve:node:corp:a:b" => matches Ok(Approval { ref id, .. }) if id == "a:b"; "when id has a colon")]
+    #[test_case("approve:node:n1" => matches Err(_); "when a part is missing")]
+    #[test_case("approve:node:corp:" => matches Err(_); "when no id")]
+    #[test_case("allow:node::n1" => matches Err(_); "when decision unknown")]
+    #[test_case("approve:group::n1" => matches Err(_); "when subject unknown")]
+    fn is_callback_data_correct(data: &str) -> Result<Approval, Callback> {
+        data.parse()
+    }
+
+    #[test]
+    fn roundtrips() {
+        let approval = Approval {
+            decision: Decision::Reject,
+            subject: Subject::User,
+            id: "user:1".to_owned(),
+            tailnet: "corp".to_owned(),
+        };
+        assert_eq!(
+            approval.to_string().parse::<Approval>().ok(),
+            Some(approval)
+        );
+    }
+}
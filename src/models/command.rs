@@ -0,0 +1,110 @@
+use super::error::BotCommand;
+use super::event::TYPES;
+use crate::runtime::MUTE_ALL;
+use crate::services::key_expiry::EVENT_TYPE as KEY_EXPIRY_REMINDER;
+use chrono::{Duration, Utc};
+use std::str::FromStr;
+
+/// Number of events `/recent` shows when not told otherwise
+const DEFAULT_RECENT: usize = 5;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Command {
+    Status,
+    /// Mutes an event type, or every event if the target is `all`
+    Mute {
+        target: String,
+        duration: Duration,
+    },
+    Unmute,
+    Recent(usize),
+}
+
+impl FromStr for Command {
+    type Err = BotCommand;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        // Commands sent in groups are addressed as `/status@botname`
+        let command = command.split_once('@').map_or(command, |(name, _)| name);
+        let args: Vec<_> = words.collect();
+
+        match (command, args.as_slice()) {
+            ("/status", []) => Ok(Self::Status),
+            ("/mute", [target, duration]) => Ok(Self::Mute {
+                target: parse_target(target)?,
+                duration: parse_duration(duration)?,
+            }),
+            ("/unmute", []) => Ok(Self::Unmute),
+            ("/recent", []) => Ok(Self::Recent(DEFAULT_RECENT)),
+            ("/recent", [n]) => n
+                .parse()
+                .map(Self::Recent)
+                .map_err(|_| BotCommand::InvalidArgument((*n).to_owned())),
+            ("/status" | "/mute" | "/unmute" | "/recent", _) => {
+                Err(BotCommand::Usage(command.to_owned()))
+            }
+            _ => Err(BotCommand::Unknown(command.to_owned())),
+        }
+    }
+}
+
+/// Accepts `all` and the event types Tailscale sends, plus key expiry reminders
+fn parse_target(s: &str) -> Result<String, BotCommand> {
+    if s == MUTE_ALL || s == KEY_EXPIRY_REMINDER || TYPES.contains(&s) {
+        Ok(s.to_owned())
+    } else {
+        Err(BotCommand::InvalidArgument(s.to_owned()))
+    }
+}
+
+/// Parses durations like `90s`, `30m`, `2h` or `1d`, short enough to end at a
+/// representable time
+fn parse_duration(s: &str) -> Result<Duration, BotCommand> {
+    let invalid = || BotCommand::InvalidArgument(s.to_owned());
+    let split = s.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+    let (amount, unit) = s.split_at(split);
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+    match unit {
+        "s" => Duration::try_seconds(amount),
+        "m" => Duration::try_minutes(amount),
+        "h" => Duration::try_hours(amount),
+        "d" => Duration::try_days(amount),
+        _ => None,
+    }
+    .filter(|duration| Utc::now().checked_add_signed(*duration).is_some())
+    .ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("/status" => matches Ok(Command::Status); "when status")]
+    #[test_case("/status@tailforward_bot" => matches Ok(Command::Status); "when addressed")]
+    #[test_case("/mute all 2h" => matches Ok(Command::Mute { .. }); "when mute")]
+    #[test_case("/mute nodeCreated 30m" => matches Ok(Command::Mute { .. }); "when mute type")]
+    #[test_case("/mute nodeKeyExpiringSoon 1d" => matches Ok(Command::Mute { .. }); "when mute reminders")]
+    #[test_case("/mute nodeCreatd 30m" => matches Err(BotCommand::InvalidArgument(_)); "when mute type unknown")]
+    #[test_case("/mute all" => matches Err(BotCommand::Usage(_)); "when mute without duration")]
+    #[test_case("/mute all 2w" => matches Err(BotCommand::InvalidArgument(_)); "when unit unknown")]
+    #[test_case("/mute all h" => matches Err(BotCommand::InvalidArgument(_)); "when no amount")]
+    #[test_case("/mute all 100000000d" => matches Err(BotCommand::InvalidArgument(_)); "when mute would overflow")]
+    #[test_case("/unmute" => matches Ok(Command::Unmute); "when unmute")]
+    #[test_case("/recent" => matches Ok(Command::Recent(DEFAULT_RECENT)); "when recent")]
+    #[test_case("/recent 3" => matches Ok(Command::Recent(3)); "when recent with count")]
+    #[test_case("/recent x" => matches Err(BotCommand::InvalidArgument(_)); "when recent count invalid")]
+    #[test_case("/start" => matches Err(BotCommand::Unknown(_)); "when unknown")]
+    fn is_command_correct(text: &str) -> Result<Command, BotCommand> {
+        text.parse()
+    }
+
+    #[test_case("90s" => Some(Duration::seconds(90)); "when seconds")]
+    #[test_case("1d" => Some(Duration::days(1)); "when days")]
+    #[test_case("1.5h" => None; "when fractional")]
+    fn duration(s: &str) -> Option<Duration> {
+        parse_duration(s).ok()
+    }
+}
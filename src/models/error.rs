@@ -1,3 +1,4 @@
+use camino::Utf8PathBuf;
 use std::num::ParseIntError;
 use thiserror::Error;
 
@@ -7,6 +8,8 @@ pub enum TailscaleWebhook {
     InvalidSignature,
     #[error("webhook has an invalid header (expected: {expected}, got: {got})")]
     InvalidHeader { expected: String, got: String },
+    #[error("Tailscale-Webhook-Signature header is missing")]
+    MissingHeader,
     #[error("Tailscale-Webhook-Signature header is empty")]
     EmptyHeader,
     #[error("the difference in timestamp is too large ({found}s)")]
@@ -17,3 +20,87 @@ pub enum TailscaleWebhook {
         source: ParseIntError,
     },
 }
+
+impl TailscaleWebhook {
+    /// Short label for the `reason` of the signature failures metric
+    #[must_use]
+    pub const fn reason(&self) -> &'static str {
+        match self {
+            Self::InvalidSignature => "invalid_signature",
+            Self::InvalidHeader { .. } => "invalid_header",
+            Self::MissingHeader => "missing_header",
+            Self::EmptyHeader => "empty_header",
+            Self::TimestampDifference { .. } => "timestamp_difference",
+            Self::ParseIntError { .. } => "invalid_timestamp",
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Tailnet {
+    #[error("event is from tailnet {got}, expected {expected}")]
+    Mismatch { got: String, expected: String },
+}
+
+#[derive(Error, Debug)]
+pub enum Callback {
+    #[error("callback data {0:?} is invalid")]
+    InvalidData(String),
+}
+
+#[derive(Error, Debug)]
+pub enum BotCommand {
+    #[error("unknown command {0}")]
+    Unknown(String),
+    #[error("wrong arguments for {0}")]
+    Usage(String),
+    #[error("invalid argument {0:?}")]
+    InvalidArgument(String),
+}
+
+#[derive(Error, Debug)]
+pub enum Secret {
+    #[error("{0} secret is not specified")]
+    Missing(&'static str),
+    #[error("{0} secret is specified with both secret and secret_file")]
+    Ambiguous(&'static str),
+    #[error("CREDENTIALS_DIRECTORY is not set, can't read credential {0}")]
+    NoCredentialsDirectory(String),
+    #[error("environment variable {0} is not set or is not valid unicode")]
+    MissingEnv(String),
+    #[error("can't read secret file {path}: {source}")]
+    Io {
+        path: Utf8PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{what} secret from {origin} {problem}")]
+    Format {
+        what: &'static str,
+        origin: String,
+        problem: SecretFormat,
+    },
+}
+
+/// Problems with the contents of a secret; never includes the contents themselves
+#[derive(Error, Debug)]
+pub enum SecretFormat {
+    #[error("line {0} is not in KEY=value format")]
+    InvalidLine(usize),
+    #[error("line {0} has an unterminated quote")]
+    UnterminatedQuote(usize),
+    #[error("key {0} is not present")]
+    MissingKey(String),
+    #[error("contains {0} keys, set secret_key to choose one of them")]
+    AmbiguousKey(usize),
+    #[error("contains no keys")]
+    Empty,
+    #[error("is not valid {format}{}", location.map(|(line, column)| format!(" (line {line}, column {column})")).unwrap_or_default())]
+    Syntax {
+        format: &'static str,
+        location: Option<(usize, usize)>,
+    },
+    #[error("field {0} is not a string or a number")]
+    NotAString(String),
+    #[error("secret_key must be set to choose a field")]
+    NoKey,
+}
@@ -1,8 +1,8 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Event {
     pub timestamp: DateTime<Utc>,
     pub version: u8,
@@ -12,3 +12,144 @@ pub struct Event {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub data: Option<Value>,
 }
+
+/// Types of the events Tailscale sends webhooks for
+pub const TYPES: &[&str] = &[
+    "test",
+    "nodeCreated",
+    "nodeNeedsApproval",
+    "nodeApproved",
+    "nodeKeyExpiringInOneDay",
+    "nodeKeyExpired",
+    "nodeDeleted",
+    "policyUpdate",
+    "userCreated",
+    "userNeedsApproval",
+    "userSuspended",
+    "userRestored",
+    "userDeleted",
+    "userApproved",
+    "userRoleUpdated",
+    "subnetIPForwardingNotEnabled",
+    "exitNodeIPForwardingNotEnabled",
+    "webhookUpdated",
+    "webhookDeleted",
+];
+
+/// Fields of `data` by event type, as they're laid out in exported columns
+#[must_use]
+pub fn data_fields(r#type: &str) -> &'static [&'static str] {
+    const NODE: &[&str] = &["nodeID", "deviceName", "managedBy", "actor", "url"];
+    const USER: &[&str] = &["user", "actor", "url"];
+    match r#type {
+        "nodeCreated"
+        | "nodeNeedsApproval"
+        | "nodeApproved"
+        | "nodeKeyExpiringInOneDay"
+        | "nodeKeyExpired"
+        | "nodeDeleted"
+        | "subnetIPForwardingNotEnabled"
+        | "exitNodeIPForwardingNotEnabled" => NODE,
+        "policyUpdate" => &["actor", "oldPolicy", "newPolicy", "url"],
+        "userCreated" | "userNeedsApproval" | "userSuspended" | "userRestored" | "userDeleted"
+        | "userApproved" => USER,
+        "userRoleUpdated" => &["user", "actor", "oldRoles", "newRoles", "url"],
+        "webhookUpdated" | "webhookDeleted" => &["actor", "url"],
+        _ => &[],
+    }
+}
+
+impl Event {
+    /// Made-up event of one of [`TYPES`], for testing webhooks by hand
+    #[must_use]
+    pub fn sample(r#type: &str, tailnet: &str) -> Option<Self> {
+        let console = "https://login.tailscale.com/admin";
+        let node = json!({
+            "nodeID": "nSample1CNTRL",
+            "deviceName": "laptop.example.ts.net",
+            "managedBy": "alice@example.com",
+            "actor": "alice@example.com",
+            "url": format!("{console}/machines/100.64.0.1"),
+        });
+        let user = |extra: Value| {
+            let mut data = json!({
+                "user": "bob@example.com",
+                "actor": "alice@example.com",
+                "url": format!("{console}/users"),
+            });
+            if let (Some(data), Value::Object(extra)) = (data.as_object_mut(), extra) {
+                data.extend(extra);
+            }
+            data
+        };
+        let (message, data) = match r#type {
+            "test" => ("This is a test event", None),
+            "nodeCreated" => ("Node laptop created", Some(node)),
+            "nodeNeedsApproval" => ("Node laptop needs approval", Some(node)),
+            "nodeApproved" => ("Node laptop approved", Some(node)),
+            "nodeKeyExpiringInOneDay" => ("Node key of laptop expires in one day", Some(node)),
+            "nodeKeyExpired" => ("Node key of laptop expired", Some(node)),
+            "nodeDeleted" => ("Node laptop deleted", Some(node)),
+            "policyUpdate" => (
+                "Tailnet policy file updated",
+                Some(json!({
+                    "actor": "alice@example.com",
+                    "oldPolicy": "{}",
+                    "newPolicy": "{\"acls\": []}",
+                    "url": format!("{console}/acls"),
+                })),
+            ),
+            "userCreated" => ("User bob created", Some(user(Value::Null))),
+            "userNeedsApproval" => ("User bob needs approval", Some(user(Value::Null))),
+            "userSuspended" => ("User bob suspended", Some(user(Value::Null))),
+            "userRestored" => ("User bob restored", Some(user(Value::Null))),
+            "userDeleted" => ("User bob deleted", Some(user(Value::Null))),
+            "userApproved" => ("User bob approved", Some(user(Value::Null))),
+            "userRoleUpdated" => (
+                "Role of user bob updated",
+                Some(user(json!({"oldRoles": ["member"], "newRoles": ["admin"]}))),
+            ),
+            "subnetIPForwardingNotEnabled" => (
+                "Subnet router laptop has IP forwarding disabled",
+                Some(node),
+            ),
+            "exitNodeIPForwardingNotEnabled" => {
+                ("Exit node laptop has IP forwarding disabled", Some(node))
+            }
+            "webhookUpdated" => (
+                "Webhook endpoint updated",
+                Some(
+                    json!({"actor": "alice@example.com", "url": format!("{console}/settings/webhooks")}),
+                ),
+            ),
+            "webhookDeleted" => (
+                "Webhook endpoint deleted",
+                Some(
+                    json!({"actor": "alice@example.com", "url": format!("{console}/settings/webhooks")}),
+                ),
+            ),
+            _ => return None,
+        };
+        Some(Self {
+            timestamp: Utc::now(),
+            version: 1,
+            r#type: r#type.to_owned(),
+            tailnet: tailnet.to_owned(),
+            message: message.to_owned(),
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_every_type() {
+        for r#type in TYPES {
+            assert!(Event::sample(r#type, "example.com").is_some(), "{type}");
+        }
+        assert!(Event::sample("nodeExploded", "example.com").is_none());
+    }
+}
@@ -0,0 +1,98 @@
+use super::tailscale_api::{Device, User};
+use super::Event;
+use std::fmt::{self, Display, Formatter};
+
+/// An event together with everything we know about it, ready to be sent out
+#[derive(Debug)]
+pub struct Notification {
+    pub event: Event,
+    pub device: Option<Device>,
+    pub user: Option<User>,
+}
+
+impl From<Event> for Notification {
+    fn from(event: Event) -> Self {
+        Self {
+            event,
+            device: None,
+            user: None,
+        }
+    }
+}
+
+impl Display for Notification {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.event)?;
+        if let Some(device) = &self.device {
+            write!(f, "\n\nDevice: {} ({})", device.hostname, device.os)?;
+            write!(f, "\nOwner: {}", device.user)?;
+            if !device.tags.is_empty() {
+                write!(f, "\nTags: {}", device.tags.join(", "))?;
+            }
+            if !device.addresses.is_empty() {
+                write!(f, "\nIPs: {}", device.addresses.join(", "))?;
+            }
+            if let Some(last_seen) = device.last_seen {
+                write!(f, "\nLast seen: {last_seen}")?;
+            }
+        }
+        if let Some(user) = &self.user {
+            write!(f, "\n\nUser: {} ({})", user.display_name, user.login_name)?;
+            write!(f, "\nRole: {}", user.role)?;
+            if let Some(last_seen) = user.last_seen {
+                write!(f, "\nLast seen: {last_seen}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use pretty_assertions::assert_eq;
+
+    fn event() -> Event {
+        Event {
+            timestamp: Utc::now(),
+            version: 1,
+            r#type: "nodeCreated".to_owned(),
+            tailnet: "example.com".to_owned(),
+            message: "Node created".to_owned(),
+            data: None,
+        }
+    }
+
+    #[test]
+    fn renders_event_only() {
+        let notification = Notification::from(event());
+        let expected = format!("{:?}", notification.event);
+        assert_eq!(notification.to_string(), expected);
+    }
+
+    #[test]
+    fn renders_device_details() {
+        let notification = Notification {
+            device: Some(Device {
+                id: "1".to_owned(),
+                node_id: "n1".to_owned(),
+                hostname: "laptop".to_owned(),
+                name: "laptop.example.ts.net".to_owned(),
+                os: "linux".to_owned(),
+                user: "alice@example.com".to_owned(),
+                tags: vec!["tag:server".to_owned()],
+                addresses: vec!["100.64.0.1".to_owned()],
+                last_seen: None,
+                expires: None,
+                key_expiry_disabled: false,
+            }),
+            ..Notification::from(event())
+        };
+        let expected = format!(
+            "{:?}\n\nDevice: laptop (linux)\nOwner: alice@example.com\nTags: tag:server\nIPs: 100.64.0.1",
+            notification.event
+        );
+        assert_eq!(notification.to_string(), expected);
+    }
+}
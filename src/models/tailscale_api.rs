@@ -0,0 +1,54 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Device {
+    pub id: String,
+    #[serde(default)]
+    pub node_id: String,
+    pub hostname: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub os: String,
+    #[serde(default)]
+    pub user: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub addresses: Vec<String>,
+    pub last_seen: Option<DateTime<Utc>>,
+    /// When the node key expires
+    pub expires: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub key_expiry_disabled: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Devices {
+    pub devices: Vec<Device>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    pub id: String,
+    pub login_name: String,
+    #[serde(default)]
+    pub display_name: String,
+    #[serde(default)]
+    pub role: String,
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Users {
+    pub users: Vec<User>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Token {
+    pub access_token: String,
+    pub expires_in: u64,
+}
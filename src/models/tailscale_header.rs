@@ -1,6 +1,7 @@
 use super::TailscaleWebhook;
 use chrono::{DateTime, Utc};
 use derive_more::Display;
+use std::fmt;
 use std::str::FromStr;
 use tracing::info;
 
@@ -33,6 +34,12 @@ impl FromStr for Header {
     }
 }
 
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "t={},{}", self.timestamp.timestamp(), self.signature)
+    }
+}
+
 #[tracing::instrument]
 fn parse_header(header: &str) -> Result<(DateTime<Utc>, Signature), TailscaleWebhook> {
     let (t, v): (&str, &str) =
@@ -58,7 +65,7 @@ fn get_timestamp(field: &str) -> Result<DateTime<Utc>, TailscaleWebhook> {
             })?;
     if key == "t" {
         let parsed = timestamp.parse()?;
-        DateTime::from_timestamp(parsed, 0).ok_or(TailscaleWebhook::InvalidHeader {
+        DateTime::from_timestamp(parsed, 0).ok_or_else(|| TailscaleWebhook::InvalidHeader {
             expected: "t".to_owned(),
             got: key.to_owned(),
         })
@@ -82,6 +89,12 @@ impl AsRef<[u8]> for Signature {
     }
 }
 
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.version, self.value)
+    }
+}
+
 impl FromStr for Signature {
     type Err = TailscaleWebhook;
 
@@ -118,6 +131,7 @@ impl FromStr for Version {
 }
 
 #[cfg(test)]
+#[allow(clippy::unwrap_used)]
 mod tests {
     use super::*;
     use chrono::TimeZone;
@@ -136,6 +150,15 @@ mod tests {
         parse_header(header)
     }
 
+    #[test]
+    fn header_round_trips() {
+        let header = Header::from_str(&format!("t={},v1=abc", Utc::now().timestamp())).unwrap();
+        let parsed = Header::from_str(&header.to_string()).unwrap();
+
+        assert_eq!(parsed.timestamp, header.timestamp);
+        assert_eq!(parsed.signature, header.signature);
+    }
+
     #[test_case(0 => matches Ok(_); "when equal")]
     #[test_case(2 => matches Err(_); "when newer")]
     #[test_case(-299 => matches Ok(_); "when old lt")]
@@ -148,7 +171,7 @@ mod tests {
             .unwrap()
             .timestamp();
         println!("{now:?}");
-        let header = Header::from_str(&format!("t={0},v1=ss", now))?;
+        let header = Header::from_str(&format!("t={now},v1=ss"))?;
         Ok(header)
     }
 }
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+/// Envelope of every Telegram Bot API response
+#[derive(Deserialize, Debug)]
+pub struct Response<T> {
+    pub ok: bool,
+    pub result: Option<T>,
+    pub description: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Update {
+    pub update_id: i64,
+    pub message: Option<IncomingMessage>,
+    pub callback_query: Option<CallbackQuery>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CallbackQuery {
+    pub id: String,
+    pub from: User,
+    pub message: Option<IncomingMessage>,
+    pub data: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct User {
+    pub id: i64,
+    pub first_name: String,
+    pub username: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct IncomingMessage {
+    pub message_id: i64,
+    pub from: Option<User>,
+    pub chat: Chat,
+    pub text: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Chat {
+    pub id: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct InlineKeyboardMarkup {
+    pub inline_keyboard: Vec<Vec<InlineKeyboardButton>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct InlineKeyboardButton {
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub callback_data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
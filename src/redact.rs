@@ -0,0 +1,389 @@
+use regex::Regex;
+use std::any::TypeId;
+use std::fmt;
+use std::sync::{LazyLock, PoisonError, RwLock};
+use tracing::field::{DisplayValue, Field, Value, ValueSet, Visit};
+use tracing::metadata::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::Interest;
+use tracing::{Dispatch, Event, Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Replacement for anything that looks like a secret
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Secret values read at runtime, scrubbed from every log line
+static SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Telegram bot tokens embedded in Bot API URLs
+static BOT_TOKEN: LazyLock<Option<Regex>> =
+    LazyLock::new(|| Regex::new(r"/bot\d+:[A-Za-z0-9_-]+").ok());
+
+/// Remembers a secret so that it gets scrubbed from logs
+pub fn register(secret: &str) {
+    if secret.is_empty() {
+        return;
+    }
+    let mut secrets = SECRETS.write().unwrap_or_else(PoisonError::into_inner);
+    if !secrets.iter().any(|known| known == secret) {
+        secrets.push(secret.to_owned());
+        // Longer secrets first, so that a secret containing another one is scrubbed whole
+        secrets.sort_by_key(|known| std::cmp::Reverse(known.len()));
+    }
+}
+
+/// Replaces known secrets and bot tokens in `text`
+pub fn redact(text: &str) -> String {
+    let mut text = BOT_TOKEN.as_ref().map_or_else(
+        || text.to_owned(),
+        |pattern| {
+            pattern
+                .replace_all(text, format!("/bot{REDACTED}"))
+                .into_owned()
+        },
+    );
+    for secret in SECRETS
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+    {
+        if text.contains(secret.as_str()) {
+            text = text.replace(secret.as_str(), REDACTED);
+        }
+    }
+    text
+}
+
+/// Most fields of a span or event that are handed on redacted, any further
+/// ones are left out
+const MAX_FIELDS: usize = 32;
+
+/// Wraps a [`Layer`] so that the fields of every span and event it sees are
+/// redacted, whatever it does with them
+#[derive(Debug, Clone)]
+pub struct Redacted<L>(pub L);
+
+/// Field value as recorded, redacted if it's text
+#[derive(Debug)]
+enum Owned {
+    Bool(bool),
+    I64(i64),
+    U64(u64),
+    I128(i128),
+    U128(u128),
+    F64(f64),
+    Str(String),
+    Debug(DisplayValue<String>),
+}
+
+impl Owned {
+    fn as_value(&self) -> &dyn Value {
+        match self {
+            Self::Bool(value) => value,
+            Self::I64(value) => value,
+            Self::U64(value) => value,
+            Self::I128(value) => value,
+            Self::U128(value) => value,
+            Self::F64(value) => value,
+            Self::Str(value) => value,
+            Self::Debug(value) => value,
+        }
+    }
+}
+
+/// Fields of a span or event, along with whether any of them had to be redacted
+#[derive(Debug, Default)]
+struct Fields {
+    values: Vec<(Field, Owned)>,
+    redacted: bool,
+}
+
+impl Fields {
+    fn text(&mut self, text: &str) -> String {
+        let redacted = redact(text);
+        self.redacted |= redacted != text;
+        redacted
+    }
+
+    /// Hands the redacted fields to `f`, or `None` if nothing was redacted and
+    /// the original ones can be used as they are
+    fn with<R>(
+        &self,
+        metadata: &'static Metadata<'static>,
+        f: impl FnOnce(Option<&ValueSet<'_>>) -> R,
+    ) -> R {
+        let Some((first, _)) = self.values.first().filter(|_| self.redacted) else {
+            return f(None);
+        };
+        let mut values: [(&Field, Option<&dyn Value>); MAX_FIELDS] = [(first, None); MAX_FIELDS];
+        for (slot, (field, value)) in values.iter_mut().zip(&self.values) {
+            *slot = (field, Some(value.as_value()));
+        }
+        f(Some(&metadata.fields().value_set(&values)))
+    }
+}
+
+impl Visit for Fields {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.values.push((field.clone(), Owned::F64(value)));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.values.push((field.clone(), Owned::I64(value)));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.values.push((field.clone(), Owned::U64(value)));
+    }
+
+    fn record_i128(&mut self, field: &Field, value: i128) {
+        self.values.push((field.clone(), Owned::I128(value)));
+    }
+
+    fn record_u128(&mut self, field: &Field, value: u128) {
+        self.values.push((field.clone(), Owned::U128(value)));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.values.push((field.clone(), Owned::Bool(value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        let value = self.text(value);
+        self.values.push((field.clone(), Owned::Str(value)));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let value = self.text(&format!("{value:?}"));
+        self.values
+            .push((field.clone(), Owned::Debug(tracing::field::display(value))));
+    }
+}
+
+impl<S, L> Layer<S> for Redacted<L>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    L: Layer<S>,
+{
+    fn on_register_dispatch(&self, subscriber: &Dispatch) {
+        self.0.on_register_dispatch(subscriber);
+    }
+
+    fn on_layer(&mut self, subscriber: &mut S) {
+        self.0.on_layer(subscriber);
+    }
+
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.0.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, S>) -> bool {
+        self.0.enabled(metadata, ctx)
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        let metadata = attrs.metadata();
+        fields.with(metadata, |values| match values {
+            None => self.0.on_new_span(attrs, id, ctx),
+            Some(values) => {
+                let redacted = if attrs.is_root() {
+                    Attributes::new_root(metadata, values)
+                } else if let Some(parent) = attrs.parent() {
+                    Attributes::child_of(parent.clone(), metadata, values)
+                } else {
+                    Attributes::new(metadata, values)
+                };
+                self.0.on_new_span(&redacted, id, ctx);
+            }
+        });
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        self.0.max_level_hint()
+    }
+
+    fn on_record(&self, span: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(metadata) = ctx.metadata(span) else {
+            return self.0.on_record(span, values, ctx);
+        };
+        let mut fields = Fields::default();
+        values.record(&mut fields);
+        fields.with(metadata, |redacted| match redacted {
+            None => self.0.on_record(span, values, ctx),
+            Some(redacted) => self.0.on_record(span, &Record::new(redacted), ctx),
+        });
+    }
+
+    fn on_follows_from(&self, span: &Id, follows: &Id, ctx: Context<'_, S>) {
+        self.0.on_follows_from(span, follows, ctx);
+    }
+
+    fn event_enabled(&self, event: &Event<'_>, ctx: Context<'_, S>) -> bool {
+        self.0.event_enabled(event, ctx)
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let metadata = event.metadata();
+        fields.with(metadata, |values| match values {
+            None => self.0.on_event(event, ctx),
+            Some(values) => {
+                let redacted = if event.is_root() {
+                    Event::new_child_of(None, metadata, values)
+                } else if let Some(parent) = event.parent() {
+                    Event::new_child_of(parent.clone(), metadata, values)
+                } else {
+                    Event::new(metadata, values)
+                };
+                self.0.on_event(&redacted, ctx);
+            }
+        });
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        self.0.on_enter(id, ctx);
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        self.0.on_exit(id, ctx);
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        self.0.on_close(id, ctx);
+    }
+
+    fn on_id_change(&self, old: &Id, new: &Id, ctx: Context<'_, S>) {
+        self.0.on_id_change(old, new, ctx);
+    }
+
+    // Lets layers such as the OpenTelemetry one still be found behind the wrapper
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        if id == TypeId::of::<Self>() {
+            Some(std::ptr::from_ref(self).cast())
+        } else {
+            self.0.downcast_raw(id)
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+    use tracing::{info, info_span};
+    use tracing_subscriber::fmt::MakeWriter;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry;
+    use tracing_tree::HierarchicalLayer;
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Captured {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    /// Keeps the fields it's given as is, the way span exporters do
+    #[derive(Clone, Default)]
+    struct Exported(Arc<Mutex<Vec<String>>>);
+
+    impl Visit for Exported {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0.lock().unwrap().push(format!("{field}={value:?}"));
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for Exported {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+            attrs.record(&mut self.clone());
+        }
+
+        fn on_record(&self, _: &Id, values: &Record<'_>, _: Context<'_, S>) {
+            values.record(&mut self.clone());
+        }
+
+        fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+            event.record(&mut self.clone());
+        }
+    }
+
+    #[test]
+    fn redacts_bot_token_urls() {
+        assert_eq!(
+            redact("error sending request for url (https://api.telegram.org/bot123:AA-b_c/getMe)"),
+            "error sending request for url (https://api.telegram.org/bot[REDACTED]/getMe)"
+        );
+    }
+
+    #[test]
+    fn captured_logs_contain_no_secrets() {
+        register("hunter2-tailscale");
+        let captured = Captured::default();
+        let subscriber = Registry::default().with(Redacted(
+            HierarchicalLayer::new(2).with_writer(captured.clone()),
+        ));
+
+        tracing::subscriber::with_default(subscriber, || {
+            info!(secret = "hunter2-tailscale", "Read secret");
+            info!(
+                url = "https://api.telegram.org/bot42:token/sendMessage",
+                "Called"
+            );
+        });
+
+        let logs = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("Read secret"), "{logs}");
+        assert!(!logs.contains("hunter2-tailscale"), "{logs}");
+        assert!(!logs.contains("42:token"), "{logs}");
+    }
+
+    #[test]
+    fn exported_fields_contain_no_secrets() {
+        register("hunter2-telegram");
+        let exported = Exported::default();
+        let subscriber = Registry::default().with(Redacted(exported.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = info_span!(
+                "call",
+                token = "hunter2-telegram",
+                status = tracing::field::Empty
+            );
+            span.record("status", "failed with hunter2-telegram");
+            let _entered = span.enter();
+            info!(attempt = 1, "Calling with {}", "hunter2-telegram");
+        });
+
+        let fields = exported.0.lock().unwrap().clone();
+        assert_eq!(
+            fields,
+            [
+                "token=\"[REDACTED]\"",
+                "status=\"failed with [REDACTED]\"",
+                "message=Calling with [REDACTED]",
+                "attempt=1",
+            ]
+        );
+    }
+}
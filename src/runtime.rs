@@ -0,0 +1,390 @@
+use crate::models::Event;
+use chrono::{DateTime, Utc};
+use color_eyre::Report;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Number of events kept around for `/recent`
+const RECENT_CAPACITY: usize = 50;
+
+/// Number of events remembered to recognize those Tailscale sends again
+const ACCEPTED_CAPACITY: usize = 1000;
+
+/// Mute target that matches every event type
+pub const MUTE_ALL: &str = "all";
+
+/// Key expiry reminders already sent, as device node ID, key expiry and threshold in days
+pub type Reminders = HashSet<(String, DateTime<Utc>, u32)>;
+
+/// Event as accepted by an endpoint: endpoint name, timestamp, type, tailnet and message
+type EventKey = (String, DateTime<Utc>, String, String, String);
+
+fn key(endpoint: &str, event: &Event) -> EventKey {
+    (
+        endpoint.to_owned(),
+        event.timestamp,
+        event.r#type.clone(),
+        event.tailnet.clone(),
+        event.message.clone(),
+    )
+}
+
+/// Whether an event was accepted before, as Tailscale sends webhooks again
+/// until they are answered with success
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Acceptance {
+    New,
+    /// Along with its row in the history, if stored
+    Again(Option<i64>),
+}
+
+/// What happened since the process started, shared between the server and the bot
+#[derive(Debug)]
+pub struct Runtime {
+    pub started: DateTime<Utc>,
+    in_flight: AtomicUsize,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    last_webhook: Option<DateTime<Utc>>,
+    /// End of each mute, by endpoint name and target
+    mutes: HashMap<(String, String), DateTime<Utc>>,
+    /// Events along with the name of the endpoint they came in on
+    recent: VecDeque<(String, Event)>,
+    /// Events accepted lately, with their row in the history once stored
+    accepted: VecDeque<(EventKey, Option<i64>)>,
+    sinks: BTreeMap<String, SinkHealth>,
+    reminders: HashMap<String, Reminders>,
+    update_offsets: HashMap<String, i64>,
+    reload_failure: Option<(DateTime<Utc>, String)>,
+    /// Time and outcome of the last probe of each sink
+    probes: HashMap<String, (DateTime<Utc>, Result<(), String>)>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct SinkHealth {
+    /// Endpoint the sink delivers for, `None` if it's shared by every endpoint
+    pub endpoint: Option<String>,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_failure: Option<(DateTime<Utc>, String)>,
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self {
+            started: Utc::now(),
+            in_flight: AtomicUsize::new(0),
+            inner: Mutex::default(),
+        }
+    }
+}
+
+impl Runtime {
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn record_webhook(&self) {
+        self.lock().last_webhook = Some(Utc::now());
+    }
+
+    pub fn last_webhook(&self) -> Option<DateTime<Utc>> {
+        self.lock().last_webhook
+    }
+
+    pub fn record_events(&self, endpoint: &str, events: &[Event]) {
+        let mut inner = self.lock();
+        for event in events {
+            if inner.recent.len() == RECENT_CAPACITY {
+                inner.recent.pop_front();
+            }
+            inner.recent.push_back((endpoint.to_owned(), event.clone()));
+        }
+    }
+
+    /// Up to `n` most recent events of the given endpoints, newest first
+    pub fn recent(&self, endpoints: &[String], n: usize) -> Vec<Event> {
+        self.lock()
+            .recent
+            .iter()
+            .rev()
+            .filter(|(endpoint, _)| endpoints.contains(endpoint))
+            .take(n)
+            .map(|(_, event)| event.clone())
+            .collect()
+    }
+
+    /// Remembers events accepted by an endpoint, telling for each one whether it already was
+    pub fn accept(&self, endpoint: &str, events: &[Event]) -> Vec<Acceptance> {
+        let mut inner = self.lock();
+        events
+            .iter()
+            .map(|event| {
+                let key = key(endpoint, event);
+                if let Some((_, id)) = inner.accepted.iter().find(|(accepted, _)| *accepted == key)
+                {
+                    return Acceptance::Again(*id);
+                }
+                if inner.accepted.len() == ACCEPTED_CAPACITY {
+                    inner.accepted.pop_front();
+                }
+                inner.accepted.push_back((key, None));
+                Acceptance::New
+            })
+            .collect()
+    }
+
+    /// Remembers the history rows of accepted events
+    pub fn record_rows(&self, endpoint: &str, events: &[Event], ids: &[i64]) {
+        let mut inner = self.lock();
+        for (event, id) in events.iter().zip(ids) {
+            let key = key(endpoint, event);
+            if let Some((_, row)) = inner
+                .accepted
+                .iter_mut()
+                .find(|(accepted, _)| *accepted == key)
+            {
+                *row = Some(*id);
+            }
+        }
+    }
+
+    /// Mutes events of an endpoint until the given time
+    pub fn mute(&self, endpoint: &str, target: &str, until: DateTime<Utc>) {
+        self.lock()
+            .mutes
+            .insert((endpoint.to_owned(), target.to_owned()), until);
+    }
+
+    /// Lifts every mute of an endpoint
+    pub fn unmute(&self, endpoint: &str) {
+        self.lock().mutes.retain(|(muted, _), _| muted != endpoint);
+    }
+
+    /// Active mutes of an endpoint along with the time they end at
+    pub fn mutes(&self, endpoint: &str) -> Vec<(String, DateTime<Utc>)> {
+        let now = Utc::now();
+        let mut inner = self.lock();
+        inner.mutes.retain(|_, until| *until > now);
+        inner
+            .mutes
+            .iter()
+            .filter(|((muted, _), _)| muted == endpoint)
+            .map(|((_, target), until)| (target.clone(), *until))
+            .collect()
+    }
+
+    pub fn is_muted(&self, endpoint: &str, r#type: &str) -> bool {
+        self.mutes(endpoint)
+            .iter()
+            .any(|(target, _)| target == MUTE_ALL || target == r#type)
+    }
+
+    /// Counts a delivery as in flight until the guard is dropped
+    pub fn deliver(&self) -> Delivery<'_> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        Delivery(self)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Remembers the outcome of a delivery to a sink, that of `endpoint` if it isn't shared
+    pub fn record_delivery(&self, endpoint: Option<&str>, sink: &str, result: &Result<(), Report>) {
+        let now = Utc::now();
+        let mut inner = self.lock();
+        let health = inner.sinks.entry(sink.to_owned()).or_default();
+        health.endpoint = endpoint.map(ToOwned::to_owned);
+        match result {
+            Ok(()) => health.last_success = Some(now),
+            Err(error) => health.last_failure = Some((now, error.to_string())),
+        }
+        drop(inner);
+    }
+
+    /// Remembers the failure of the last reload, or clears it once a reload succeeds
+    pub fn record_reload(&self, result: &Result<(), Report>) {
+        self.lock().reload_failure = result
+            .as_ref()
+            .err()
+            .map(|error| (Utc::now(), error.to_string()));
+    }
+
+    pub fn reload_failure(&self) -> Option<(DateTime<Utc>, String)> {
+        self.lock().reload_failure.clone()
+    }
+
+    pub fn sinks(&self) -> BTreeMap<String, SinkHealth> {
+        self.lock().sinks.clone()
+    }
+
+    /// Outcome of the last probe of a sink, unless it's older than `max_age`
+    pub fn probe(&self, sink: &str, max_age: std::time::Duration) -> Option<Result<(), String>> {
+        let now = Utc::now();
+        self.lock()
+            .probes
+            .get(sink)
+            .filter(|(at, _)| (now - *at).to_std().is_ok_and(|age| age < max_age))
+            .map(|(_, result)| result.clone())
+    }
+
+    pub fn record_probe(&self, sink: &str, result: Result<(), String>) {
+        self.lock()
+            .probes
+            .insert(sink.to_owned(), (Utc::now(), result));
+    }
+
+    /// Reminders sent for an endpoint, kept so that reloads don't send them again
+    pub fn reminders(&self, endpoint: &str) -> Reminders {
+        self.lock()
+            .reminders
+            .get(endpoint)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn record_reminders(&self, endpoint: &str, reminders: Reminders) {
+        self.lock().reminders.insert(endpoint.to_owned(), reminders);
+    }
+
+    /// Next Telegram update to ask a bot for, kept so that reloads don't handle updates twice
+    pub fn update_offset(&self, bot: &str) -> i64 {
+        self.lock()
+            .update_offsets
+            .get(bot)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn record_update_offset(&self, bot: &str, offset: i64) {
+        self.lock().update_offsets.insert(bot.to_owned(), offset);
+    }
+}
+
+pub struct Delivery<'a>(&'a Runtime);
+
+impl Drop for Delivery<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use color_eyre::eyre::eyre;
+
+    fn event(r#type: &str) -> Event {
+        Event {
+            timestamp: Utc::now(),
+            version: 1,
+            r#type: r#type.to_owned(),
+            tailnet: "example.com".to_owned(),
+            message: "test".to_owned(),
+            data: None,
+        }
+    }
+
+    #[test]
+    fn keeps_recent_events_bounded() {
+        let runtime = Runtime::default();
+        let events: Vec<_> = (0..=RECENT_CAPACITY)
+            .map(|i| event(&i.to_string()))
+            .collect();
+        runtime.record_events("", &events);
+
+        let recent = runtime.recent(&[String::new()], RECENT_CAPACITY + 1);
+        assert_eq!(recent.len(), RECENT_CAPACITY);
+        assert_eq!(recent[0].r#type, RECENT_CAPACITY.to_string());
+    }
+
+    #[test]
+    fn keeps_recent_events_by_endpoint() {
+        let runtime = Runtime::default();
+        runtime.record_events("", &[event("nodeCreated")]);
+        runtime.record_events("corp", &[event("nodeDeleted")]);
+
+        let recent = runtime.recent(&["corp".to_owned()], RECENT_CAPACITY);
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].r#type, "nodeDeleted");
+    }
+
+    #[test]
+    fn recognizes_events_sent_again() {
+        let runtime = Runtime::default();
+        let events = [event("nodeCreated"), event("nodeDeleted")];
+        assert_eq!(
+            runtime.accept("", &events),
+            [Acceptance::New, Acceptance::New]
+        );
+        runtime.record_rows("", &events, &[1, 2]);
+
+        assert_eq!(
+            runtime.accept("", &events),
+            [Acceptance::Again(Some(1)), Acceptance::Again(Some(2))]
+        );
+        assert_eq!(runtime.accept("corp", &events[..1]), [Acceptance::New]);
+    }
+
+    #[test]
+    fn mutes_by_type() {
+        let runtime = Runtime::default();
+        runtime.mute("", "nodeCreated", Utc::now() + Duration::hours(1));
+        runtime.mute("", "nodeDeleted", Utc::now() - Duration::hours(1));
+
+        assert!(runtime.is_muted("", "nodeCreated"));
+        assert!(!runtime.is_muted("", "nodeDeleted"));
+        assert!(!runtime.is_muted("", "userCreated"));
+    }
+
+    #[test]
+    fn mutes_all() {
+        let runtime = Runtime::default();
+        runtime.mute("", MUTE_ALL, Utc::now() + Duration::hours(1));
+        assert!(runtime.is_muted("", "userCreated"));
+
+        runtime.unmute("");
+        assert!(!runtime.is_muted("", "userCreated"));
+    }
+
+    #[test]
+    fn mutes_by_endpoint() {
+        let runtime = Runtime::default();
+        runtime.mute("corp", MUTE_ALL, Utc::now() + Duration::hours(1));
+        runtime.mute("", "nodeCreated", Utc::now() + Duration::hours(1));
+
+        runtime.unmute("");
+
+        assert!(runtime.is_muted("corp", "nodeCreated"));
+        assert!(!runtime.is_muted("", "nodeCreated"));
+    }
+
+    #[test]
+    fn counts_deliveries_in_flight() {
+        let runtime = Runtime::default();
+        let delivery = runtime.deliver();
+        assert_eq!(runtime.in_flight(), 1);
+        drop(delivery);
+        assert_eq!(runtime.in_flight(), 0);
+    }
+
+    #[test]
+    fn tracks_sink_health() {
+        let runtime = Runtime::default();
+        runtime.record_delivery(Some(""), "telegram", &Ok(()));
+        runtime.record_delivery(Some(""), "telegram", &Err(eyre!("boom")));
+
+        let health = &runtime.sinks()["telegram"];
+        assert_eq!(health.endpoint.as_deref(), Some(""));
+        assert!(health.last_success.is_some());
+        assert_eq!(
+            health.last_failure.as_ref().map(|(_, e)| e.as_str()),
+            Some("boom")
+        );
+    }
+}
@@ -0,0 +1,346 @@
+use crate::models::error::{Secret, SecretFormat};
+use crate::redact;
+use camino::{Utf8Path, Utf8PathBuf};
+use secrecy::SecretString;
+use serde_json::Value;
+use std::{
+    env,
+    fs::{read_to_string, File},
+};
+use tailforward_cfg::{config::Format, SecretSource};
+use tap::TapFallible;
+use tracing::{info, warn};
+
+/// Picks the source of a secret from the `secret` and `secret_file` settings
+///
+/// # Errors
+/// If neither or both of them are set
+pub fn source(
+    what: &'static str,
+    secret: Option<&SecretSource>,
+    secret_file: Option<&Utf8PathBuf>,
+) -> Result<SecretSource, Secret> {
+    match (secret, secret_file) {
+        (Some(secret), None) => Ok(secret.clone()),
+        (None, Some(path)) => Ok(SecretSource::File(path.clone())),
+        (Some(_), Some(_)) => Err(Secret::Ambiguous(what)),
+        (None, None) => Err(Secret::Missing(what)),
+    }
+}
+
+/// Reads the raw contents of a secret, trimmed of surrounding whitespace
+///
+/// # Errors
+/// If the credential, environment variable or file can't be read
+#[tracing::instrument]
+pub fn read(what: &'static str, source: &SecretSource) -> Result<String, Secret> {
+    let contents = match source {
+        SecretSource::Credential(name) => read_credential_in(&credentials_directory(name)?, name)?,
+        SecretSource::Env(var) => env::var(var).map_err(|_| Secret::MissingEnv(var.clone()))?,
+        SecretSource::File(path) => read_file(path)?,
+        SecretSource::Literal(value) => {
+            warn!("{what} secret is specified inline, this is only meant for development");
+            value.clone()
+        }
+    };
+    info!(?source, "Read {what} secret");
+    Ok(contents.trim().to_owned())
+}
+
+/// Checks that a secret can be read, without reading or logging it
+///
+/// # Errors
+/// If the credential, environment variable or file isn't accessible
+pub fn check(source: &SecretSource) -> Result<(), Secret> {
+    let path = match source {
+        SecretSource::Credential(name) => credentials_directory(name)?.join(name),
+        SecretSource::Env(var) => {
+            return env::var_os(var)
+                .map(drop)
+                .ok_or_else(|| Secret::MissingEnv(var.clone()));
+        }
+        SecretSource::File(path) => path.clone(),
+        SecretSource::Literal(_) => return Ok(()),
+    };
+    File::open(&path)
+        .map(drop)
+        .map_err(|source| Secret::Io { path, source })
+}
+
+/// Reads a secret and picks it out of the contents according to `format`
+///
+/// # Errors
+/// If the secret can't be read or isn't in the expected format
+pub fn load(
+    what: &'static str,
+    source: &SecretSource,
+    format: &Format,
+    key: Option<&str>,
+) -> Result<SecretString, Secret> {
+    let contents = read(what, source)?;
+    extract(&contents, format, key)
+        .tap_ok(|secret| redact::register(secret))
+        .map(SecretString::from)
+        .map_err(|problem| Secret::Format {
+            what,
+            origin: format!("{source:?}"),
+            problem,
+        })
+}
+
+fn extract(contents: &str, format: &Format, key: Option<&str>) -> Result<String, SecretFormat> {
+    match format {
+        Format::Plain => Ok(contents.to_owned()),
+        Format::Alertmanager | Format::Env => {
+            let entries = parse_env(contents)?;
+            match (key, entries.as_slice()) {
+                (Some(key), entries) => entries
+                    .iter()
+                    .rev()
+                    .find(|(name, _)| *name == key)
+                    .map(|(_, value)| value.clone())
+                    .ok_or_else(|| SecretFormat::MissingKey(key.to_owned())),
+                (None, [(_, value)]) => Ok(value.clone()),
+                (None, []) => Err(SecretFormat::Empty),
+                (None, entries) => Err(SecretFormat::AmbiguousKey(entries.len())),
+            }
+        }
+        Format::Json => {
+            let document: Value =
+                serde_json::from_str(contents).map_err(|error| SecretFormat::Syntax {
+                    format: "JSON",
+                    location: Some((error.line(), error.column())),
+                })?;
+            select(&document, key)
+        }
+        Format::Yaml => {
+            let document: Value =
+                serde_yaml::from_str(contents).map_err(|error| SecretFormat::Syntax {
+                    format: "YAML",
+                    location: error
+                        .location()
+                        .map(|location| (location.line(), location.column())),
+                })?;
+            select(&document, key)
+        }
+    }
+}
+
+/// Parses `KEY=value` lines, skipping blank lines and `#` comments
+fn parse_env(contents: &str) -> Result<Vec<(&str, String)>, SecretFormat> {
+    let mut entries = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").map_or(line, str::trim_start);
+        let (key, value) = line
+            .split_once('=')
+            .ok_or(SecretFormat::InvalidLine(number))?;
+        let key = key.trim();
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            return Err(SecretFormat::InvalidLine(number));
+        }
+        entries.push((key, unquote(value.trim(), number)?));
+    }
+    Ok(entries)
+}
+
+fn unquote(value: &str, line: usize) -> Result<String, SecretFormat> {
+    let mut chars = value.chars();
+    let Some(quote @ ('"' | '\'')) = chars.next() else {
+        // Unquoted values end at a comment
+        let value = value.split_once(" #").map_or(value, |(value, _)| value);
+        return Ok(value.trim_end().to_owned());
+    };
+
+    let mut unquoted = String::new();
+    loop {
+        match chars.next() {
+            None => return Err(SecretFormat::UnterminatedQuote(line)),
+            Some(c) if c == quote => break,
+            Some('\\') if quote == '"' => match chars.next() {
+                Some('n') => unquoted.push('\n'),
+                Some(escaped) => unquoted.push(escaped),
+                None => return Err(SecretFormat::UnterminatedQuote(line)),
+            },
+            Some(c) => unquoted.push(c),
+        }
+    }
+
+    let rest = chars.as_str().trim_start();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(unquoted)
+    } else {
+        Err(SecretFormat::InvalidLine(line))
+    }
+}
+
+/// Picks a field by dot-separated path, or the whole document if it's a string
+fn select(document: &Value, key: Option<&str>) -> Result<String, SecretFormat> {
+    let Some(key) = key else {
+        return match document {
+            Value::String(value) => Ok(value.clone()),
+            _ => Err(SecretFormat::NoKey),
+        };
+    };
+    let field = key
+        .split('.')
+        .try_fold(document, |value, segment| value.get(segment))
+        .ok_or_else(|| SecretFormat::MissingKey(key.to_owned()))?;
+    match field {
+        Value::String(value) => Ok(value.clone()),
+        Value::Number(value) => Ok(value.to_string()),
+        _ => Err(SecretFormat::NotAString(key.to_owned())),
+    }
+}
+
+/// Directory systemd passes credentials in, needed to read `name`
+fn credentials_directory(name: &str) -> Result<Utf8PathBuf, Secret> {
+    env::var("CREDENTIALS_DIRECTORY")
+        .map(Utf8PathBuf::from)
+        .map_err(|_| Secret::NoCredentialsDirectory(name.to_owned()))
+}
+
+fn read_credential_in(dir: &Utf8Path, name: &str) -> Result<String, Secret> {
+    read_file(&dir.join(name))
+}
+
+fn read_file(path: &Utf8Path) -> Result<String, Secret> {
+    read_to_string(path).map_err(|source| Secret::Io {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use test_case::test_case;
+
+    #[test]
+    fn checks_without_reading() {
+        let path = env::temp_dir().join(format!("tailforward-check-{}", std::process::id()));
+        fs::write(&path, "secret").unwrap();
+        let file = SecretSource::File(Utf8PathBuf::try_from(path.clone()).unwrap());
+        assert!(check(&file).is_ok());
+
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(check(&file), Err(Secret::Io { .. })));
+        assert!(matches!(
+            check(&SecretSource::Env("TAILFORWARD_CHECK_UNSET".to_owned())),
+            Err(Secret::MissingEnv(_))
+        ));
+    }
+
+    #[test_case(Some("env:A"), None => matches Ok(SecretSource::Env(_)); "when secret")]
+    #[test_case(None, Some("/a") => matches Ok(SecretSource::File(_)); "when secret file")]
+    #[test_case(Some("env:A"), Some("/a") => matches Err(Secret::Ambiguous(_)); "when both")]
+    #[test_case(None, None => matches Err(Secret::Missing(_)); "when neither")]
+    fn picks_source(
+        secret: Option<&str>,
+        secret_file: Option<&str>,
+    ) -> Result<SecretSource, Secret> {
+        let secret = secret.map(|secret| secret.parse().unwrap());
+        let secret_file = secret_file.map(Utf8PathBuf::from);
+        source("Test", secret.as_ref(), secret_file.as_ref())
+    }
+
+    #[test]
+    fn reads_file() {
+        let path = env::temp_dir().join(format!("tailforward-secret-{}", std::process::id()));
+        fs::write(&path, "  file-secret\n").unwrap();
+        let source = SecretSource::File(Utf8PathBuf::from_path_buf(path.clone()).unwrap());
+        let secret = read("Test", &source).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(secret, "file-secret");
+    }
+
+    #[test]
+    fn reads_literal() {
+        let source = SecretSource::Literal("literal-secret".to_owned());
+        assert_eq!(read("Test", &source).unwrap(), "literal-secret");
+    }
+
+    #[test]
+    fn reads_env() {
+        let source = SecretSource::Env("PATH".to_owned());
+        assert_eq!(
+            read("Test", &source).unwrap(),
+            env::var("PATH").unwrap().trim()
+        );
+    }
+
+    #[test]
+    fn reads_credential() {
+        let dir = env::temp_dir().join(format!("tailforward-credentials-{}", std::process::id()));
+        let dir = Utf8PathBuf::from_path_buf(dir).unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("telegram"), "credential-secret").unwrap();
+        let secret = read_credential_in(&dir, "telegram").unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(secret, "credential-secret");
+    }
+
+    #[test]
+    fn fails_on_missing_env() {
+        let source = SecretSource::Env("TAILFORWARD_TEST_SURELY_MISSING".to_owned());
+        assert!(matches!(read("Test", &source), Err(Secret::MissingEnv(_))));
+    }
+
+    #[test_case("TOKEN=abc" => matches Ok(ref s) if s == "abc"; "when single key")]
+    #[test_case("TOKEN=abc=def" => matches Ok(ref s) if s == "abc=def"; "when value contains equals")]
+    #[test_case("# comment\n\nexport TOKEN=abc" => matches Ok(ref s) if s == "abc"; "when comments and export")]
+    #[test_case("TOKEN=\"a b\\\"c\" # comment" => matches Ok(ref s) if s == "a b\"c"; "when double quoted")]
+    #[test_case("TOKEN='a#b'" => matches Ok(ref s) if s == "a#b"; "when single quoted")]
+    #[test_case("TOKEN=abc # comment" => matches Ok(ref s) if s == "abc"; "when trailing comment")]
+    #[test_case("TOKEN=\"abc" => matches Err(SecretFormat::UnterminatedQuote(1)); "when unterminated")]
+    #[test_case("abc" => matches Err(SecretFormat::InvalidLine(1)); "when no equals")]
+    #[test_case("A=1\nB=2" => matches Err(SecretFormat::AmbiguousKey(2)); "when many keys")]
+    #[test_case("# nothing" => matches Err(SecretFormat::Empty); "when empty")]
+    fn extracts_env(contents: &str) -> Result<String, SecretFormat> {
+        extract(contents, &Format::Env, None)
+    }
+
+    #[test_case(Some("B") => matches Ok(ref s) if s == "2"; "when key present")]
+    #[test_case(Some("C") => matches Err(SecretFormat::MissingKey(_)); "when key missing")]
+    fn extracts_env_key(key: Option<&str>) -> Result<String, SecretFormat> {
+        extract("A=1\nB=1\nB=2", &Format::Env, key)
+    }
+
+    #[test_case(r#"{"telegram": {"token": "abc"}}"#, Some("telegram.token") => matches Ok(ref s) if s == "abc"; "when nested field")]
+    #[test_case(r#"{"id": 42}"#, Some("id") => matches Ok(ref s) if s == "42"; "when number")]
+    #[test_case(r#""abc""#, None => matches Ok(ref s) if s == "abc"; "when whole document")]
+    #[test_case(r#"{"token": "abc"}"#, None => matches Err(SecretFormat::NoKey); "when no key")]
+    #[test_case(r#"{"token": "abc"}"#, Some("other") => matches Err(SecretFormat::MissingKey(_)); "when field missing")]
+    #[test_case(r#"{"token": ["abc"]}"#, Some("token") => matches Err(SecretFormat::NotAString(_)); "when not a string")]
+    #[test_case(r#"{"token": "#, Some("token") => matches Err(SecretFormat::Syntax { .. }); "when invalid")]
+    fn extracts_json(contents: &str, key: Option<&str>) -> Result<String, SecretFormat> {
+        extract(contents, &Format::Json, key)
+    }
+
+    #[test_case("telegram:\n  token: abc", Some("telegram.token") => matches Ok(ref s) if s == "abc"; "when nested field")]
+    #[test_case("token: [abc", Some("token") => matches Err(SecretFormat::Syntax { .. }); "when invalid")]
+    fn extracts_yaml(contents: &str, key: Option<&str>) -> Result<String, SecretFormat> {
+        extract(contents, &Format::Yaml, key)
+    }
+
+    #[test]
+    fn errors_do_not_contain_secrets() {
+        let source = SecretSource::Literal("TOKEN=\"supersecret".to_owned());
+        let error = load("Test", &source, &Format::Env, None).unwrap_err();
+        let message = error.to_string();
+        assert!(!message.contains("supersecret"), "{message}");
+        assert!(message.contains("unterminated quote"), "{message}");
+    }
+
+    #[test]
+    fn fails_on_missing_file() {
+        let source = SecretSource::File("/surely/missing".into());
+        assert!(matches!(read("Test", &source), Err(Secret::Io { .. })));
+    }
+}
@@ -0,0 +1,60 @@
+use crate::archive::compress;
+use crate::config::Endpoint;
+use crate::models::Event;
+use crate::State;
+use chrono::Utc;
+use color_eyre::Report;
+use std::time::Instant;
+use tracing::{error, info};
+
+/// Name of the archive among the sinks
+pub const ARCHIVE: &str = "archive";
+
+/// Appends verified events to the archive, if enabled, then compresses the file
+/// it rotated in the background; failures are logged as forwarding goes on regardless
+#[tracing::instrument(skip(state, endpoint, events))]
+pub async fn archive_events(state: &State, endpoint: &Endpoint, events: &[Event]) {
+    let Some(archive) = state.archive.clone().filter(|_| !events.is_empty()) else {
+        return;
+    };
+    let settings = state.settings().base.archive.clone();
+    let received = Utc::now();
+    let name = endpoint.name.clone();
+    let key_id = endpoint.key_id();
+    let events = events.to_vec();
+    let started = Instant::now();
+    let result = {
+        let settings = settings.clone();
+        tokio::task::spawn_blocking(move || {
+            archive.append(&settings, received, &name, &key_id, &events)
+        })
+        .await
+        .map_err(Report::from)
+        .and_then(|result| result.map_err(Report::from))
+    };
+    state
+        .metrics
+        .record_delivery(ARCHIVE, result.is_ok(), started.elapsed());
+    let rotated = match result {
+        Ok(rotated) => {
+            state.runtime.record_delivery(None, ARCHIVE, &Ok(()));
+            rotated
+        }
+        Err(error) => {
+            error!(?error, "Failed to archive events");
+            state.runtime.record_delivery(None, ARCHIVE, &Err(error));
+            return;
+        }
+    };
+    let Some(rotated) = rotated else {
+        return;
+    };
+    info!(%rotated, "Rotated archive");
+    tokio::task::spawn_blocking(move || {
+        match compress(&rotated, settings.compression, settings.fsync) {
+            Ok(Some(compressed)) => info!(%compressed, "Compressed archive"),
+            Ok(None) => {}
+            Err(error) => error!(?error, %rotated, "Failed to compress archive"),
+        }
+    });
+}
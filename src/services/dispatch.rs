@@ -0,0 +1,70 @@
+use crate::config::Endpoint;
+use crate::history::Outcome;
+use crate::models::Event;
+use crate::services::history::record_delivery;
+use crate::services::{tailscale_api::enrich, telegram::post};
+use crate::State;
+use color_eyre::Report;
+use std::time::Instant;
+use tracing::info;
+
+/// Renders events and sends them to the sinks of the endpoint, skipping muted ones;
+/// `ids` are the events' rows in the history, if stored, to record deliveries against
+#[tracing::instrument(skip(state, events, ids))]
+pub async fn dispatch(
+    state: &State,
+    endpoint: &Endpoint,
+    events: Vec<Event>,
+    ids: Vec<Option<i64>>,
+) -> Result<(), Report> {
+    state.runtime.record_events(&endpoint.name, &events);
+    let sink = sink_name(endpoint);
+    let mut ids = ids.into_iter();
+    let (muted, events): (Vec<_>, Vec<_>) = events
+        .into_iter()
+        .map(|event| (ids.next().flatten(), event))
+        .partition(|(_, event)| state.runtime.is_muted(&endpoint.name, &event.r#type));
+    if !muted.is_empty() {
+        info!(muted = muted.len(), "Skipped muted events");
+        let ids = muted.into_iter().filter_map(|(id, _)| id).collect();
+        record_delivery(state, ids, &sink, Outcome::Muted, None).await;
+    }
+    if events.is_empty() {
+        return Ok(());
+    }
+    let (ids, events): (Vec<_>, Vec<_>) = events.into_iter().unzip();
+
+    let notifications = enrich(endpoint.api.as_ref(), events).await;
+    let _delivery = state.runtime.deliver();
+    let started = Instant::now();
+    let result = post(notifications, state.reqwest_client.clone(), endpoint).await;
+    state
+        .metrics
+        .record_delivery(&sink, result.is_ok(), started.elapsed());
+    state
+        .runtime
+        .record_delivery(Some(&endpoint.name), &sink, &result);
+    let outcome = if result.is_ok() {
+        Outcome::Delivered
+    } else {
+        Outcome::Failed
+    };
+    let error = result.as_ref().err().map(ToString::to_string);
+    record_delivery(
+        state,
+        ids.into_iter().flatten().collect(),
+        &sink,
+        outcome,
+        error,
+    )
+    .await;
+    result
+}
+
+pub fn sink_name(endpoint: &Endpoint) -> String {
+    if endpoint.name.is_empty() {
+        "telegram".to_owned()
+    } else {
+        format!("telegram/{}", endpoint.name)
+    }
+}
@@ -0,0 +1,343 @@
+use crate::config::Endpoint;
+use crate::redact::redact;
+use crate::runtime::SinkHealth;
+use crate::secret;
+use crate::services::archive::ARCHIVE;
+use crate::services::dispatch::sink_name;
+use crate::services::telegram::call;
+use crate::State;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tailforward_cfg::config::Health;
+
+/// Outcome of a check, ordered from best to worst
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    /// Serving, but something needs attention, e.g. a sink is failing
+    Degraded,
+    /// Can't process webhooks
+    Unavailable,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Check {
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Check {
+    const fn ok() -> Self {
+        Self {
+            status: Status::Ok,
+            detail: None,
+        }
+    }
+
+    /// The detail ends up in logs and on the dashboard, so secrets are redacted from it
+    fn failed(status: Status, detail: &str) -> Self {
+        Self {
+            status,
+            detail: Some(redact(detail)),
+        }
+    }
+}
+
+/// Deliveries are made while the webhook waits, so there's nothing to check but their number
+#[derive(Clone, Debug, Serialize)]
+pub struct Queue {
+    /// Deliveries in flight
+    pub depth: usize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Sink {
+    pub status: Status,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_failure: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub probe: Option<Check>,
+}
+
+impl Sink {
+    /// Degraded if its last delivery or probe failed
+    fn new(health: Option<&SinkHealth>, probe: Option<Check>) -> Self {
+        let (last_success, last_failure) = health.map_or((None, None), |health| {
+            (health.last_success, health.last_failure.clone())
+        });
+        let failing = match (&last_success, &last_failure) {
+            (Some(success), Some((failure, _))) => failure > success,
+            (None, Some(_)) => true,
+            _ => false,
+        };
+        let status = if failing
+            || probe
+                .as_ref()
+                .is_some_and(|probe| probe.status != Status::Ok)
+        {
+            Status::Degraded
+        } else {
+            Status::Ok
+        };
+        Self {
+            status,
+            last_success,
+            last_failure: last_failure.as_ref().map(|(at, _)| *at),
+            error: last_failure
+                .filter(|_| failing)
+                .map(|(_, error)| redact(&error)),
+            probe,
+        }
+    }
+}
+
+/// Body of `/readyz`
+#[derive(Clone, Debug, Serialize)]
+pub struct Readiness {
+    pub status: Status,
+    pub config: Check,
+    pub secrets: Check,
+    pub queue: Queue,
+    pub sinks: BTreeMap<String, Sink>,
+}
+
+/// Body of `/readyz` for callers who aren't trusted with the details, which
+/// name secret files, variables and sinks
+#[derive(Clone, Debug, Serialize)]
+pub struct Summary {
+    pub status: Status,
+    pub queue: Queue,
+    /// Number of sinks in each status
+    pub sinks: BTreeMap<Status, usize>,
+}
+
+impl Readiness {
+    #[must_use]
+    pub fn summary(&self) -> Summary {
+        let mut sinks = BTreeMap::new();
+        for sink in self.sinks.values() {
+            *sinks.entry(sink.status).or_default() += 1;
+        }
+        Summary {
+            status: self.status,
+            queue: self.queue.clone(),
+            sinks,
+        }
+    }
+}
+
+/// Checks everything webhooks depend on, probing sinks if configured to unless
+/// `probe` is unset
+#[tracing::instrument(skip(state))]
+pub async fn readiness(state: &State, probe: bool) -> Readiness {
+    let settings = state.settings();
+
+    let config = state
+        .runtime
+        .reload_failure()
+        .map_or_else(Check::ok, |(at, error)| {
+            Check::failed(
+                Status::Degraded,
+                &format!(
+                    "reloading at {at} failed, still using the previous configuration: {error}"
+                ),
+            )
+        });
+
+    let problems: Vec<_> = settings
+        .secret_sources()
+        .iter()
+        .filter_map(|source| secret::check(source).err())
+        .map(|error| error.to_string())
+        .collect();
+    let secrets = if problems.is_empty() {
+        Check::ok()
+    } else {
+        Check::failed(Status::Unavailable, &problems.join("; "))
+    };
+
+    let queue = Queue {
+        depth: state.runtime.in_flight(),
+    };
+
+    let health = state.runtime.sinks();
+    let mut sinks = BTreeMap::new();
+    for endpoint in settings.endpoints() {
+        let name = sink_name(endpoint);
+        let probe = if probe && settings.base.health.probe_sinks {
+            Some(self::probe(state, endpoint, &name, &settings.base.health).await)
+        } else {
+            None
+        };
+        let sink = Sink::new(health.get(&name), probe);
+        sinks.insert(name, sink);
+    }
+    if state.archive.is_some() {
+        sinks.insert(ARCHIVE.to_owned(), Sink::new(health.get(ARCHIVE), None));
+    }
+
+    let status = [config.status, secrets.status]
+        .into_iter()
+        .chain(sinks.values().map(|sink| sink.status))
+        .max()
+        .unwrap_or(Status::Ok);
+    Readiness {
+        status,
+        config,
+        secrets,
+        queue,
+        sinks,
+    }
+}
+
+/// Asks Telegram who the bot is, which fails if the token is revoked or Telegram is unreachable,
+/// unless it was asked less than `probe_interval` ago
+async fn probe(state: &State, endpoint: &Endpoint, sink: &str, health: &Health) -> Check {
+    let cached = state
+        .runtime
+        .probe(sink, Duration::from_secs(health.probe_interval));
+    let result = if let Some(result) = cached {
+        result
+    } else {
+        let body = json!({});
+        let request = call::<Value>(&state.reqwest_client, endpoint, "getMe", &body);
+        let timeout = health.probe_timeout;
+        let result = match tokio::time::timeout(Duration::from_secs(timeout), request).await {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(error)) => Err(error.to_string()),
+            Err(_) => Err(format!("no answer within {timeout} seconds")),
+        };
+        state.runtime.record_probe(sink, result.clone());
+        result
+    };
+    result.map_or_else(
+        |error| Check::failed(Status::Degraded, &error),
+        |()| Check::ok(),
+    )
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::config::new_config_with_secrets;
+    use color_eyre::eyre::eyre;
+    use tailforward_cfg::config::Auth;
+    use tailforward_cfg::SecretSource;
+
+    fn state() -> State {
+        State::new(
+            new_config_with_secrets("tail".to_owned().into(), "tele".to_owned().into()).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn is_ok_without_deliveries() {
+        let readiness = readiness(&state(), true).await;
+        assert_eq!(readiness.status, Status::Ok);
+        assert_eq!(readiness.sinks["telegram"].status, Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn is_degraded_while_sink_fails() {
+        let state = state();
+        state.runtime.record_delivery(Some(""), "telegram", &Ok(()));
+        state
+            .runtime
+            .record_delivery(Some(""), "telegram", &Err(eyre!("boom")));
+
+        let readiness = readiness(&state, true).await;
+        assert_eq!(readiness.status, Status::Degraded);
+        assert_eq!(readiness.sinks["telegram"].error.as_deref(), Some("boom"));
+
+        state.runtime.record_delivery(Some(""), "telegram", &Ok(()));
+        assert_eq!(super::readiness(&state, true).await.status, Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn is_unavailable_without_secrets() {
+        let mut settings =
+            new_config_with_secrets("tail".to_owned().into(), "tele".to_owned().into()).unwrap();
+        settings.base.telegram.secret = Some(SecretSource::File("/nonexistent/tailforward".into()));
+        let state = State::new(settings);
+
+        let readiness = readiness(&state, true).await;
+        assert_eq!(readiness.status, Status::Unavailable);
+        assert!(readiness
+            .secrets
+            .detail
+            .unwrap()
+            .contains("/nonexistent/tailforward"));
+    }
+
+    #[tokio::test]
+    async fn is_unavailable_without_dashboard_secret() {
+        let mut settings =
+            new_config_with_secrets("tail".to_owned().into(), "tele".to_owned().into()).unwrap();
+        settings.base.dashboard.auth = Some(Auth::Bearer {
+            token: SecretSource::Env("TAILFORWARD_TEST_NO_DASHBOARD_TOKEN".to_owned()),
+        });
+        let state = State::new(settings);
+
+        let readiness = readiness(&state, true).await;
+        assert_eq!(readiness.status, Status::Unavailable);
+        assert!(readiness
+            .secrets
+            .detail
+            .unwrap()
+            .contains("TAILFORWARD_TEST_NO_DASHBOARD_TOKEN"));
+    }
+
+    #[tokio::test]
+    async fn summarizes_sinks() {
+        let state = state();
+        state
+            .runtime
+            .record_delivery(Some(""), "telegram", &Err(eyre!("boom")));
+
+        let summary = readiness(&state, false).await.summary();
+        assert_eq!(
+            serde_json::to_value(summary).unwrap(),
+            json!({ "status": "degraded", "queue": { "depth": 0 }, "sinks": { "degraded": 1 } })
+        );
+    }
+
+    #[tokio::test]
+    async fn redacts_details() {
+        let state = state();
+        state.runtime.record_reload(&Err(eyre!(
+            "https://api.telegram.org/bot123:secret-token/getMe failed"
+        )));
+
+        let readiness = readiness(&state, false).await;
+        let detail = readiness.config.detail.unwrap();
+        assert!(!detail.contains("secret-token"));
+        assert!(detail.contains(crate::redact::REDACTED));
+    }
+
+    #[tokio::test]
+    async fn reuses_recent_probes() {
+        let mut settings =
+            new_config_with_secrets("tail".to_owned().into(), "tele".to_owned().into()).unwrap();
+        // Nothing listens there, so only a cached probe can succeed
+        settings.endpoint.telegram.api_url = "http://127.0.0.1:1".to_owned();
+        settings.base.health.probe_sinks = true;
+        let state = State::new(settings.clone());
+        state.runtime.record_probe("telegram", Ok(()));
+
+        let cached = readiness(&state, true).await;
+        settings.base.health.probe_interval = 0;
+        state.replace_settings(settings);
+        let probed = readiness(&state, true).await;
+
+        assert_eq!(cached.sinks["telegram"].status, Status::Ok);
+        assert_eq!(probed.sinks["telegram"].status, Status::Degraded);
+    }
+}
@@ -0,0 +1,101 @@
+use crate::config::Endpoint;
+use crate::history::{Outcome, Store};
+use crate::models::Event;
+use crate::State;
+use chrono::{Duration as ChronoDuration, Utc};
+use color_eyre::{eyre::eyre, Report};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info};
+
+/// Runs `f` on the store off the async threads, logging its failure
+async fn with_store<T: Send + 'static>(
+    store: Arc<Store>,
+    what: &'static str,
+    f: impl FnOnce(&Store) -> rusqlite::Result<T> + Send + 'static,
+) -> Option<T> {
+    tokio::task::spawn_blocking(move || f(&store))
+        .await
+        .map_err(Report::from)
+        .and_then(|result| result.map_err(Report::from))
+        .inspect_err(|error| error!(?error, "Failed to {what}"))
+        .ok()
+}
+
+/// Reads from the store off the async threads
+///
+/// # Errors
+/// If the history is disabled or can't be read
+pub async fn read<T: Send + 'static>(
+    state: &State,
+    f: impl FnOnce(&Store) -> rusqlite::Result<T> + Send + 'static,
+) -> Result<T, Report> {
+    let store = state
+        .history
+        .clone()
+        .ok_or_else(|| eyre!("Event history is disabled"))?;
+    Ok(tokio::task::spawn_blocking(move || f(&store)).await??)
+}
+
+/// Stores verified events, returning their IDs; none if the history is disabled
+/// or can't be written to, as forwarding goes on regardless
+#[tracing::instrument(skip(state, endpoint, events))]
+pub async fn record_events(state: &State, endpoint: &Endpoint, events: &[Event]) -> Vec<i64> {
+    let Some(store) = state.history.clone().filter(|_| !events.is_empty()) else {
+        return Vec::new();
+    };
+    let received = Utc::now();
+    let name = endpoint.name.clone();
+    let key_id = endpoint.key_id();
+    let events = events.to_vec();
+    with_store(store, "store events", move |store| {
+        store.insert(received, &name, &key_id, &events)
+    })
+    .await
+    .unwrap_or_default()
+}
+
+/// Records what became of stored events sent to a sink
+#[tracing::instrument(skip(state, ids))]
+pub async fn record_delivery(
+    state: &State,
+    ids: Vec<i64>,
+    sink: &str,
+    outcome: Outcome,
+    error: Option<String>,
+) {
+    let Some(store) = state.history.clone().filter(|_| !ids.is_empty()) else {
+        return;
+    };
+    let sink = sink.to_owned();
+    with_store(store, "store delivery", move |store| {
+        store.record_delivery(&ids, &sink, outcome, error.as_deref())
+    })
+    .await;
+}
+
+/// Removes events older than `retention_days` or beyond `max_events` every
+/// `prune_interval` seconds
+#[tracing::instrument(skip(state))]
+pub async fn prune_history(state: State) {
+    let Some(store) = state.history.clone() else {
+        return;
+    };
+    loop {
+        let history = state.settings().base.history.clone();
+        let before = history
+            .retention_days
+            .map(|days| Utc::now() - ChronoDuration::days(days.into()));
+        let keep = history.max_events;
+        if before.is_some() || keep.is_some() {
+            let removed = with_store(store.clone(), "prune history", move |store| {
+                store.prune(before, keep)
+            })
+            .await;
+            if let Some(removed) = removed.filter(|removed| *removed > 0) {
+                info!(removed, "Pruned event history");
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(history.prune_interval)).await;
+    }
+}
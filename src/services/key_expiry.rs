@@ -0,0 +1,260 @@
+use crate::config::Endpoint;
+use crate::models::tailscale_api::Device;
+use crate::models::Event;
+use crate::runtime::Reminders;
+use crate::services::dispatch::dispatch;
+use crate::State;
+use chrono::{DateTime, Duration, Utc};
+use color_eyre::{eyre::eyre, Report};
+use serde_json::json;
+use tokio::task::JoinSet;
+use tracing::{error, info, warn};
+
+/// Event type of the synthetic events sent ahead of `nodeKeyExpiringInOneDay`
+pub const EVENT_TYPE: &str = "nodeKeyExpiringSoon";
+
+/// Periodically polls the devices of every endpoint that has reminders configured
+#[tracing::instrument(skip(state))]
+pub async fn remind_key_expiry(state: State) {
+    // Dropping the set, e.g. when the configuration is reloaded, stops every endpoint's loop
+    let mut tasks = JoinSet::new();
+    for endpoint in state.settings().endpoints() {
+        let Some(api) = &endpoint.tailscale.api else {
+            continue;
+        };
+        if api.key_expiry_reminders.is_empty() {
+            continue;
+        }
+        info!(
+            name = endpoint.name,
+            reminders = ?api.key_expiry_reminders,
+            "Checking device key expiry"
+        );
+        let interval = std::time::Duration::from_secs(api.key_expiry_interval);
+        let (state, endpoint) = (state.clone(), endpoint.clone());
+        tasks.spawn(async move {
+            let mut sent = state.runtime.reminders(&endpoint.name);
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                if let Err(error) = check(&state, &endpoint, &mut sent).await {
+                    warn!(?error, "Failed to check device key expiry");
+                }
+                state.runtime.record_reminders(&endpoint.name, sent.clone());
+            }
+        });
+    }
+    while let Some(result) = tasks.join_next().await {
+        if let Err(error) = result {
+            error!(?error, "Key expiry reminders stopped");
+        }
+    }
+}
+
+/// Sends a reminder for every device that crossed a threshold since the last check
+#[tracing::instrument(skip(state, endpoint, sent))]
+pub async fn check(state: &State, endpoint: &Endpoint, sent: &mut Reminders) -> Result<(), Report> {
+    let api = endpoint
+        .api
+        .as_ref()
+        .ok_or_else(|| eyre!("Tailscale API is not configured"))?;
+    let thresholds = endpoint
+        .tailscale
+        .api
+        .as_ref()
+        .map(|api| api.key_expiry_reminders.as_slice())
+        .unwrap_or_default();
+    let now = Utc::now();
+    // Forget about keys that expired, so that the set doesn't grow forever
+    sent.retain(|(_, expires, _)| *expires > now);
+
+    let mut events = Vec::new();
+    let mut pending = Vec::new();
+    for device in api.devices().await? {
+        let Some(expires) = device.expires.filter(|_| !device.key_expiry_disabled) else {
+            continue;
+        };
+        let Some(threshold) = due(expires - now, thresholds) else {
+            continue;
+        };
+        // Crossing a threshold also covers every larger one
+        let crossed: Vec<_> = thresholds
+            .iter()
+            .filter(|days| **days >= threshold)
+            .map(|days| (device.node_id.clone(), expires, *days))
+            .filter(|reminder| !sent.contains(reminder))
+            .collect();
+        if !crossed.is_empty() {
+            pending.extend(crossed);
+            events.push(reminder(
+                endpoint,
+                api.tailnet(),
+                &device,
+                expires,
+                threshold,
+            ));
+        }
+    }
+
+    if events.is_empty() {
+        return Ok(());
+    }
+    info!(reminders = events.len(), "Sending key expiry reminders");
+    dispatch(state, endpoint, events, Vec::new()).await?;
+    // Only now, so that reminders that failed to be delivered are sent again
+    sent.extend(pending);
+    Ok(())
+}
+
+/// Smallest threshold in days that the remaining time falls within
+fn due(remaining: Duration, thresholds: &[u32]) -> Option<u32> {
+    if remaining <= Duration::zero() {
+        return None;
+    }
+    thresholds
+        .iter()
+        .copied()
+        .filter(|days| remaining <= Duration::days((*days).into()))
+        .min()
+}
+
+fn reminder(
+    endpoint: &Endpoint,
+    tailnet: &str,
+    device: &Device,
+    expires: DateTime<Utc>,
+    threshold: u32,
+) -> Event {
+    let days = if threshold == 1 { "day" } else { "days" };
+    Event {
+        timestamp: Utc::now(),
+        version: 1,
+        r#type: EVENT_TYPE.to_owned(),
+        tailnet: endpoint.tailnet.as_deref().unwrap_or(tailnet).to_owned(),
+        message: format!(
+            "Node key of {} expires within {threshold} {days}, at {expires}",
+            device.hostname
+        ),
+        data: Some(json!({
+            "nodeID": device.node_id,
+            "deviceName": device.name,
+            "keyExpiry": expires,
+            "thresholdDays": threshold,
+        })),
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::config::new_config_with_secrets;
+    use crate::services::tailscale_api;
+    use axum::extract::State as AxumState;
+    use axum::http::StatusCode;
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use std::future::IntoFuture;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tailforward_cfg::config::TailscaleApi;
+    use test_case::test_case;
+    use tokio::net::TcpListener;
+
+    #[test_case(Duration::days(20) => None; "when far away")]
+    #[test_case(Duration::days(10) => Some(14); "when within largest")]
+    #[test_case(Duration::days(7) => Some(7); "when on threshold")]
+    #[test_case(Duration::hours(5) => Some(1); "when within smallest")]
+    #[test_case(Duration::hours(-5) => None; "when expired")]
+    fn due_threshold(remaining: Duration) -> Option<u32> {
+        due(remaining, &[14, 7, 1])
+    }
+
+    async fn mock(expires: DateTime<Utc>, sent: Arc<AtomicUsize>) -> String {
+        let app = Router::new()
+            .route(
+                "/api/v2/tailnet/-/devices",
+                get(move || async move {
+                    Json(json!({"devices": [
+                        {"id": "1", "nodeId": "n1", "hostname": "laptop", "expires": expires},
+                        {"id": "2", "nodeId": "n2", "hostname": "server", "expires": expires, "keyExpiryDisabled": true},
+                    ]}))
+                }),
+            )
+            .route(
+                "/api/v2/device/:id",
+                get(|| async { Json(json!({"id": "1", "hostname": "laptop"})) }),
+            )
+            .route(
+                "/bottele/sendMessage",
+                post(|AxumState(sent): AxumState<Arc<AtomicUsize>>| async move {
+                    sent.fetch_add(1, Ordering::SeqCst);
+                    Json(json!({"ok": true, "result": true}))
+                }),
+            )
+            .route(
+                "/botfailing/sendMessage",
+                post(|AxumState(sent): AxumState<Arc<AtomicUsize>>| async move {
+                    if sent.fetch_add(1, Ordering::SeqCst) == 0 {
+                        Err(StatusCode::BAD_GATEWAY)
+                    } else {
+                        Ok(Json(json!({"ok": true, "result": true})))
+                    }
+                }),
+            )
+            .with_state(sent);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, app).into_future());
+        format!("http://{addr}")
+    }
+
+    async fn state(bot: &str, messages: Arc<AtomicUsize>) -> State {
+        let url = mock(Utc::now() + Duration::days(3), messages).await;
+        let mut settings =
+            new_config_with_secrets("tail".to_owned().into(), bot.to_owned().into()).unwrap();
+        let config = TailscaleApi {
+            base_url: url.clone(),
+            key_expiry_reminders: vec![14, 7, 1],
+            ..TailscaleApi::default()
+        };
+        settings.endpoint.api = Some(tailscale_api::Client::new(&config, "key".to_owned().into()));
+        settings.endpoint.tailscale.api = Some(config);
+        settings.endpoint.telegram.api_url = url;
+        settings.endpoint.telegram.chat_id = Some(-1);
+        State::new(settings)
+    }
+
+    #[tokio::test]
+    async fn reminds_once_per_threshold() {
+        let messages = Arc::new(AtomicUsize::new(0));
+        let state = state("tele", messages.clone()).await;
+        let endpoint = state.settings().endpoint.clone();
+        let mut sent = Reminders::new();
+
+        check(&state, &endpoint, &mut sent).await.unwrap();
+        check(&state, &endpoint, &mut sent).await.unwrap();
+
+        assert_eq!(messages.load(Ordering::SeqCst), 1);
+        assert_eq!(sent.len(), 2);
+        assert_eq!(
+            state.runtime.recent(&[String::new()], 10)[0].r#type,
+            EVENT_TYPE
+        );
+    }
+
+    #[tokio::test]
+    async fn reminds_again_after_failure() {
+        let messages = Arc::new(AtomicUsize::new(0));
+        let state = state("failing", messages.clone()).await;
+        let endpoint = state.settings().endpoint.clone();
+        let mut sent = Reminders::new();
+
+        assert!(check(&state, &endpoint, &mut sent).await.is_err());
+        assert!(sent.is_empty());
+        check(&state, &endpoint, &mut sent).await.unwrap();
+
+        assert_eq!(messages.load(Ordering::SeqCst), 2);
+        assert_eq!(sent.len(), 2);
+    }
+}
@@ -1,4 +1,6 @@
-use crate::models::{event::Event, Header};
+use crate::models::error::Tailnet;
+use crate::models::{event::Event, Header, TailscaleWebhook};
+use chrono::{DateTime, Utc};
 use color_eyre::Report;
 use hmac::{Hmac, Mac};
 use secrecy::{ExposeSecret, SecretString};
@@ -12,22 +14,59 @@ pub fn post_webhook(
     body: &str,
     secret: &SecretString,
 ) -> Result<Vec<Event>, Report> {
-    let sig = hex::decode(header.signature.value)?;
+    let sig =
+        hex::decode(header.signature.value).map_err(|_| TailscaleWebhook::InvalidSignature)?;
+    mac(header.timestamp, body, secret)?
+        .verify_slice(&sig)
+        .map_err(|_| TailscaleWebhook::InvalidSignature)?;
 
-    let string_to_sign = format!("{0}.{body}", header.timestamp.timestamp())
-        .tap(|string| debug!(string, "Got string to sign"));
-    let secret_exposed = secret
-        .expose_secret()
-        .tap_deref_dbg(|secret_value| debug!(secret_value));
+    Ok(serde_json::from_str::<Vec<Event>>(body)?)
+}
+
+/// Hex-encoded signature of a webhook body, the way Tailscale computes it
+///
+/// # Errors
+/// If the secret can't be used as an HMAC key
+pub fn sign(timestamp: DateTime<Utc>, body: &str, secret: &SecretString) -> Result<String, Report> {
+    Ok(hex::encode(
+        mac(timestamp, body, secret)?.finalize().into_bytes(),
+    ))
+}
 
-    let mut mac = Hmac::<Sha256>::new_from_slice(secret_exposed.as_bytes())?;
+fn mac(
+    timestamp: DateTime<Utc>,
+    body: &str,
+    secret: &SecretString,
+) -> Result<Hmac<Sha256>, Report> {
+    let string_to_sign = format!("{0}.{body}", timestamp.timestamp())
+        .tap(|string| debug!(string, "Got string to sign"));
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())?;
     mac.update(string_to_sign.as_bytes());
-    mac.verify_slice(&sig)?;
+    Ok(mac)
+}
 
-    Ok(serde_json::from_str::<Vec<Event>>(body)?)
+/// Checks that every event comes from the tailnet the endpoint serves, if set
+///
+/// # Errors
+/// If an event comes from another tailnet
+#[tracing::instrument(skip(events))]
+pub fn verify_tailnet(events: &[Event], expected: Option<&str>) -> Result<(), Tailnet> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+    events
+        .iter()
+        .find(|event| event.tailnet != expected)
+        .map_or(Ok(()), |event| {
+            Err(Tailnet::Mismatch {
+                got: event.tailnet.clone(),
+                expected: expected.to_owned(),
+            })
+        })
 }
 
 #[cfg(test)]
+#[allow(clippy::unwrap_used)]
 mod tests {
     use super::*;
     use crate::models::tailscale_header::{Signature, Version};
@@ -68,4 +107,37 @@ mod tests {
 
         post_webhook(header, &body_str, &secret)
     }
+
+    #[test]
+    fn signs_known_vector() {
+        let timestamp = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let secret = SecretString::from_str("secret").unwrap();
+
+        let signature = sign(timestamp, "{\"a\":1}", &secret).unwrap();
+
+        assert_eq!(
+            signature,
+            "49f24e537407743fa4a0242bb63b94b9a47ee99cbbe071ccd8a22550ae411686"
+        );
+    }
+
+    #[test_case(&["example.com"], None => matches Ok(()); "when not restricted")]
+    #[test_case(&["example.com"], Some("example.com") => matches Ok(()); "when matches")]
+    #[test_case(&["example.com", "example.org"], Some("example.com") => matches Err(_); "when one differs")]
+    #[test_case(&[], Some("example.com") => matches Ok(()); "when no events")]
+    fn is_tailnet_good(tailnets: &[&str], expected: Option<&str>) -> Result<(), Tailnet> {
+        let events: Vec<_> = tailnets
+            .iter()
+            .map(|tailnet| Event {
+                timestamp: Utc::now(),
+                version: 1,
+                r#type: "test".to_owned(),
+                tailnet: (*tailnet).to_owned(),
+                message: "This is a test event".to_owned(),
+                data: None,
+            })
+            .collect();
+
+        verify_tailnet(&events, expected)
+    }
 }
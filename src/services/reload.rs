@@ -0,0 +1,262 @@
+use crate::config::{new_config_with, Application};
+use crate::services::history::prune_history;
+use crate::services::key_expiry::remind_key_expiry;
+use crate::services::telegram_updates::receive_updates;
+use crate::{systemd, State};
+use camino::Utf8PathBuf;
+use color_eyre::Report;
+use std::time::{Duration, SystemTime};
+use tracing::{error, info, warn};
+
+/// Modification times of the watched files, `None` if a file can't be read
+type Fingerprint = Vec<(Utf8PathBuf, Option<SystemTime>)>;
+
+/// Runs the Telegram bot, key expiry reminders and history pruning, restarting them whenever
+/// the configuration is replaced
+#[tracing::instrument(skip(state))]
+pub async fn run_workers(state: State) {
+    let mut changes = state.subscribe();
+    loop {
+        let updates = tokio::spawn(receive_updates(state.clone()));
+        let reminders = tokio::spawn(remind_key_expiry(state.clone()));
+        let pruning = tokio::spawn(prune_history(state.clone()));
+        let changed = changes.changed().await;
+        updates.abort();
+        reminders.abort();
+        pruning.abort();
+        if changed.is_err() {
+            return;
+        }
+        info!("Restarting workers with the new configuration");
+    }
+}
+
+/// Reloads the configuration on SIGHUP and, if `watch_interval` is set,
+/// whenever the configuration or one of the secret files changes
+#[tracing::instrument(skip(state))]
+pub async fn reload_config(state: State, file: String, overrides: Vec<(String, String)>) {
+    let mut hangup = Hangup::new();
+    let mut fingerprint = fingerprint(&file, &state.settings());
+    loop {
+        let interval = state.settings().base.watch_interval;
+        tokio::select! {
+            () = hangup.recv() => info!("SIGHUP received"),
+            () = sleep(interval) => {
+                if fingerprint == self::fingerprint(&file, &state.settings()) {
+                    continue;
+                }
+                info!("Configuration or secret files changed");
+            }
+        }
+        systemd::reloading();
+        let result = reload(&state, &file, &overrides);
+        systemd::ready();
+        if let Err(error) = &result {
+            error!(
+                ?error,
+                "Failed to reload configuration, keeping the current one"
+            );
+        }
+        state.runtime.record_reload(&result);
+        fingerprint = self::fingerprint(&file, &state.settings());
+    }
+}
+
+/// Reads and validates the configuration again, then swaps it in
+///
+/// # Errors
+/// If the new configuration is invalid, in which case the current one stays in effect
+pub fn reload(state: &State, file: &str, overrides: &[(String, String)]) -> Result<(), Report> {
+    let settings = new_config_with(file, overrides)?;
+    let current = state.settings();
+    if settings.listeners() != current.listeners() {
+        warn!("Changing the listen address requires a restart");
+    }
+    if settings.base.tls != current.base.tls {
+        warn!(
+            "Changing the TLS settings requires a restart, certificates are reloaded by themselves"
+        );
+    }
+    if settings.base.log != current.base.log || settings.base.debug != current.base.debug {
+        warn!("Changing the log settings requires a restart");
+    }
+    let (history, new_history) = (&current.base.history, &settings.base.history);
+    if (history.enabled, &history.path) != (new_history.enabled, &new_history.path) {
+        warn!("Enabling the event history or moving its database requires a restart");
+    }
+    let (archive, new_archive) = (&current.base.archive, &settings.base.archive);
+    if (archive.enabled, &archive.directory) != (new_archive.enabled, &new_archive.directory) {
+        warn!("Enabling the archive or moving its directory requires a restart");
+    }
+    if settings.base.dashboard.enabled != current.base.dashboard.enabled {
+        warn!("Enabling the dashboard requires a restart");
+    }
+    state.replace_settings(settings);
+    info!("Reloaded configuration");
+    Ok(())
+}
+
+fn fingerprint(file: &str, settings: &Application) -> Fingerprint {
+    // The configuration file may be given with or without its extension
+    [
+        Utf8PathBuf::from(file),
+        Utf8PathBuf::from(format!("{file}.toml")),
+    ]
+    .into_iter()
+    .chain(settings.secret_files())
+    .map(|path| {
+        let modified = std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        (path, modified)
+    })
+    .collect()
+}
+
+async fn sleep(interval: Option<u64>) {
+    match interval {
+        Some(seconds) => tokio::time::sleep(Duration::from_secs(seconds)).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(unix)]
+struct Hangup(Option<tokio::signal::unix::Signal>);
+
+#[cfg(unix)]
+impl Hangup {
+    fn new() -> Self {
+        let signal = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+            .map_err(|error| warn!(?error, "Can't reload on SIGHUP"))
+            .ok();
+        Self(signal)
+    }
+
+    async fn recv(&mut self) {
+        if let Some(signal) = &mut self.0 {
+            if signal.recv().await.is_some() {
+                return;
+            }
+        }
+        std::future::pending::<()>().await;
+    }
+}
+
+#[cfg(not(unix))]
+struct Hangup;
+
+#[cfg(not(unix))]
+impl Hangup {
+    const fn new() -> Self {
+        Self
+    }
+
+    async fn recv(&mut self) {
+        std::future::pending::<()>().await;
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::config::new_config_with_secrets;
+    use axum::extract::State as AxumState;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use serde_json::{json, Value};
+    use std::env;
+    use std::fs;
+    use std::future::IntoFuture;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    /// `getUpdates` requests in flight, and the most seen at once
+    #[derive(Default)]
+    struct Polls {
+        open: AtomicUsize,
+        most: AtomicUsize,
+    }
+
+    /// Counts a request as open until it's answered or its client goes away
+    struct Open(Arc<Polls>);
+
+    impl Drop for Open {
+        fn drop(&mut self) {
+            self.0.open.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    async fn telegram(polls: Arc<Polls>) -> String {
+        let get_updates = |AxumState(polls): AxumState<Arc<Polls>>| async move {
+            let open = polls.open.fetch_add(1, Ordering::SeqCst) + 1;
+            polls.most.fetch_max(open, Ordering::SeqCst);
+            let _open = Open(polls);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Json::<Value>(json!({"ok": true, "result": []}))
+        };
+        let app = Router::new()
+            .route("/bottele/getUpdates", post(get_updates))
+            .with_state(polls);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, app).into_future());
+        format!("http://{addr}")
+    }
+
+    fn write_config(path: &std::path::Path, chat_id: i64) {
+        fs::write(
+            path,
+            format!(
+                "[tailscale]\nsecret = \"literal:tail\"\n\
+                 [telegram]\nsecret = \"literal:tele\"\nchat_id = {chat_id}\n"
+            ),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn swaps_valid_and_keeps_current_on_invalid() {
+        let path = env::temp_dir().join(format!("tailforward-reload-{}.toml", std::process::id()));
+        let file = path.to_str().unwrap();
+        write_config(&path, -1);
+        let state = State::new(new_config_with(file, &[]).unwrap());
+        let mut changes = state.subscribe();
+
+        write_config(&path, -2);
+        reload(&state, file, &[]).unwrap();
+        assert_eq!(state.settings().endpoint.telegram.chat_id, Some(-2));
+        assert!(changes.has_changed().unwrap());
+        changes.mark_unchanged();
+
+        fs::write(&path, "[telegram]\nchat_id = \"not a number\"\n").unwrap();
+        assert!(reload(&state, file, &[]).is_err());
+        assert_eq!(state.settings().endpoint.telegram.chat_id, Some(-2));
+        assert!(!changes.has_changed().unwrap());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn restart_leaves_one_poller() {
+        let polls = Arc::new(Polls::default());
+        let mut settings =
+            new_config_with_secrets("tail".to_owned().into(), "tele".to_owned().into()).unwrap();
+        settings.endpoint.telegram.api_url = telegram(polls.clone()).await;
+        settings.endpoint.telegram.admins = vec![1];
+        let state = State::new(settings.clone());
+        tokio::spawn(run_workers(state.clone()));
+
+        for _ in 0..3 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            state.replace_settings(settings.clone());
+        }
+        // Let the requests of the stopped pollers finish
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        polls.most.store(0, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        assert_eq!(polls.most.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,418 @@
+use crate::models::notification::Notification;
+use crate::models::tailscale_api::{Device, Devices, Token, User, Users};
+use crate::models::Event;
+use crate::redact;
+use color_eyre::{eyre::eyre, Report};
+use secrecy::{ExposeSecret, SecretString};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tailforward_cfg::config::TailscaleApi;
+use tracing::{debug, info, warn};
+
+/// Tailscale API client, cheap to clone
+#[derive(Clone, Debug)]
+pub struct Client {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    http: reqwest::Client,
+    base_url: String,
+    tailnet: String,
+    credentials: Credentials,
+    token: Mutex<Option<(Instant, SecretString)>>,
+    devices: Cache<Device>,
+    users: Cache<User>,
+}
+
+#[derive(Debug)]
+enum Credentials {
+    ApiKey(SecretString),
+    OAuth {
+        client_id: String,
+        client_secret: SecretString,
+    },
+}
+
+impl Client {
+    pub fn new(config: &TailscaleApi, secret: SecretString) -> Self {
+        let credentials = match &config.oauth_client_id {
+            Some(client_id) => Credentials::OAuth {
+                client_id: client_id.clone(),
+                client_secret: secret,
+            },
+            None => Credentials::ApiKey(secret),
+        };
+        let ttl = Duration::from_secs(config.cache_ttl);
+        Self {
+            inner: Arc::new(Inner {
+                http: reqwest::Client::new(),
+                base_url: config.base_url.trim_end_matches('/').to_owned(),
+                tailnet: config.tailnet.clone(),
+                credentials,
+                token: Mutex::new(None),
+                devices: Cache::new(ttl),
+                users: Cache::new(ttl),
+            }),
+        }
+    }
+
+    /// Tailnet the client looks devices and users up in
+    pub fn tailnet(&self) -> &str {
+        &self.inner.tailnet
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn token(&self) -> Result<SecretString, Report> {
+        let (client_id, client_secret) = match &self.inner.credentials {
+            Credentials::ApiKey(key) => return Ok(key.clone()),
+            Credentials::OAuth {
+                client_id,
+                client_secret,
+            } => (client_id, client_secret),
+        };
+
+        if let Some((expires, token)) = &*self
+            .inner
+            .token
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+        {
+            if Instant::now() < *expires {
+                return Ok(token.clone());
+            }
+        }
+
+        let token: Token = self
+            .inner
+            .http
+            .post(self.url("oauth/token"))
+            .form(&[
+                ("client_id", client_id.as_str()),
+                ("client_secret", client_secret.expose_secret()),
+                ("grant_type", "client_credentials"),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        info!(expires_in = token.expires_in, "Got OAuth access token");
+
+        // Refresh a minute early so that the token doesn't expire mid-request
+        let expires = Instant::now() + Duration::from_secs(token.expires_in.saturating_sub(60));
+        redact::register(&token.access_token);
+        let access_token = SecretString::from(token.access_token);
+        *self
+            .inner
+            .token
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some((expires, access_token.clone()));
+        Ok(access_token)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, Report> {
+        let token = self.token().await?;
+        let url = self.url(path);
+        debug!(url, "Querying Tailscale API");
+        Ok(self
+            .inner
+            .http
+            .get(url)
+            .bearer_auth(token.expose_secret())
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<(), Report> {
+        let token = self.token().await?;
+        request
+            .bearer_auth(token.expose_secret())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/api/v2/{path}", self.inner.base_url)
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn authorize_device(&self, id: &str) -> Result<(), Report> {
+        let request = self
+            .inner
+            .http
+            .post(self.url(&format!("device/{id}/authorized")))
+            .json(&serde_json::json!({ "authorized": true }));
+        self.send(request).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn delete_device(&self, id: &str) -> Result<(), Report> {
+        let request = self.inner.http.delete(self.url(&format!("device/{id}")));
+        self.send(request).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn approve_user(&self, id: &str) -> Result<(), Report> {
+        let request = self
+            .inner
+            .http
+            .post(self.url(&format!("users/{id}/approve")));
+        self.send(request).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn delete_user(&self, id: &str) -> Result<(), Report> {
+        let request = self
+            .inner
+            .http
+            .post(self.url(&format!("users/{id}/delete")));
+        self.send(request).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn device(&self, id: &str) -> Result<Device, Report> {
+        if let Some(device) = self.inner.devices.get(id) {
+            debug!("Device is cached");
+            return Ok(device);
+        }
+        let device: Device = self.get(&format!("device/{id}?fields=all")).await?;
+        self.inner.devices.insert(id, device.clone());
+        Ok(device)
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn devices(&self) -> Result<Vec<Device>, Report> {
+        let devices: Devices = self
+            .get(&format!(
+                "tailnet/{}/devices?fields=all",
+                self.inner.tailnet
+            ))
+            .await?;
+        for device in &devices.devices {
+            self.inner.devices.insert(&device.node_id, device.clone());
+        }
+        Ok(devices.devices)
+    }
+
+    /// Looks a user up either by ID or, if it contains `@`, by login name
+    #[tracing::instrument(skip(self))]
+    pub async fn user(&self, id: &str) -> Result<User, Report> {
+        if let Some(user) = self.inner.users.get(id) {
+            debug!("User is cached");
+            return Ok(user);
+        }
+        let user = if id.contains('@') {
+            let users: Users = self
+                .get(&format!("tailnet/{}/users", self.inner.tailnet))
+                .await?;
+            users
+                .users
+                .into_iter()
+                .find(|user| user.login_name == id)
+                .ok_or_else(|| eyre!("No user with login name {id}"))?
+        } else {
+            self.get(&format!("users/{id}")).await?
+        };
+        self.inner.users.insert(id, user.clone());
+        Ok(user)
+    }
+
+    /// Adds details of the device and user referenced in the event data
+    #[tracing::instrument(skip(self))]
+    pub async fn enrich(&self, event: Event) -> Notification {
+        let field = |name: &str| {
+            event
+                .data
+                .as_ref()
+                .and_then(|data| data.get(name))
+                .and_then(Value::as_str)
+                .map(ToOwned::to_owned)
+        };
+        let node_id = field("nodeID");
+        let user_id = field("userID").or_else(|| field("user"));
+
+        let device = match node_id {
+            Some(node_id) => self
+                .device(&node_id)
+                .await
+                .map_err(|error| warn!(?error, node_id, "Failed to look up device"))
+                .ok(),
+            None => None,
+        };
+        let user = match user_id {
+            Some(user_id) => self
+                .user(&user_id)
+                .await
+                .map_err(|error| warn!(?error, user_id, "Failed to look up user"))
+                .ok(),
+            None => None,
+        };
+
+        Notification {
+            event,
+            device,
+            user,
+        }
+    }
+}
+
+#[tracing::instrument(skip(events))]
+pub async fn enrich(client: Option<&Client>, events: Vec<Event>) -> Vec<Notification> {
+    let Some(client) = client else {
+        return events.into_iter().map(Notification::from).collect();
+    };
+    let mut notifications = Vec::with_capacity(events.len());
+    for event in events {
+        notifications.push(client.enrich(event).await);
+    }
+    notifications
+}
+
+#[derive(Debug)]
+struct Cache<T> {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, T)>>,
+}
+
+impl<T: Clone> Cache<T> {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<T> {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        match entries.get(key) {
+            Some((inserted, value)) if inserted.elapsed() < self.ttl => Some(value.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Stores a value, dropping expired ones so keys never read again don't pile up
+    fn insert(&self, key: &str, value: T) {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.retain(|_, (inserted, _)| inserted.elapsed() < self.ttl);
+        entries.insert(key.to_owned(), (Instant::now(), value));
+        drop(entries);
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use axum::extract::State;
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use chrono::Utc;
+    use serde_json::json;
+    use std::future::IntoFuture;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::net::TcpListener;
+
+    async fn mock_api(hits: Arc<AtomicUsize>) -> String {
+        let app = Router::new()
+            .route(
+                "/api/v2/oauth/token",
+                post(|| async { Json(json!({"access_token": "token", "expires_in": 3600})) }),
+            )
+            .route(
+                "/api/v2/device/:id",
+                get(|State(hits): State<Arc<AtomicUsize>>| async move {
+                    hits.fetch_add(1, Ordering::SeqCst);
+                    Json(json!({
+                        "id": "1",
+                        "nodeId": "n1",
+                        "hostname": "laptop",
+                        "os": "linux",
+                        "user": "alice@example.com",
+                        "tags": ["tag:server"],
+                        "addresses": ["100.64.0.1"],
+                        "lastSeen": "2024-01-01T00:00:00Z"
+                    }))
+                }),
+            )
+            .route(
+                "/api/v2/tailnet/-/users",
+                get(|| async {
+                    Json(json!({"users": [{
+                        "id": "u1",
+                        "loginName": "alice@example.com",
+                        "displayName": "Alice",
+                        "role": "admin"
+                    }]}))
+                }),
+            )
+            .with_state(hits);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, app).into_future());
+        format!("http://{addr}")
+    }
+
+    fn client(base_url: String, oauth_client_id: Option<String>) -> Client {
+        let config = TailscaleApi {
+            base_url,
+            oauth_client_id,
+            ..TailscaleApi::default()
+        };
+        Client::new(&config, "secret".to_owned().into())
+    }
+
+    #[tokio::test]
+    async fn enriches_event() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let client = client(mock_api(hits).await, Some("id".to_owned()));
+        let event = Event {
+            timestamp: Utc::now(),
+            version: 1,
+            r#type: "nodeCreated".to_owned(),
+            tailnet: "example.com".to_owned(),
+            message: "Node created".to_owned(),
+            data: Some(json!({"nodeID": "n1", "user": "alice@example.com"})),
+        };
+
+        let notification = client.enrich(event).await;
+
+        assert_eq!(notification.device.unwrap().hostname, "laptop");
+        assert_eq!(notification.user.unwrap().display_name, "Alice");
+    }
+
+    #[tokio::test]
+    async fn caches_devices() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let client = client(mock_api(hits.clone()).await, None);
+
+        client.device("n1").await.unwrap();
+        client.device("n1").await.unwrap();
+
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn prunes_expired_entries() {
+        let cache = Cache::new(Duration::ZERO);
+
+        cache.insert("n1", 1);
+        cache.insert("n2", 2);
+
+        let keys: Vec<_> = cache.entries.lock().unwrap().keys().cloned().collect();
+        assert_eq!(keys, ["n2"]);
+    }
+}
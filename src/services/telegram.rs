@@ -1,29 +1,197 @@
-use crate::models::{event::Event, message::Message};
-use color_eyre::Report;
-use secrecy::{ExposeSecret, SecretString};
-use tracing::{debug, info};
+use crate::config::Endpoint;
+use crate::models::approval::{Approval, Decision, Subject, MAX_CALLBACK_DATA};
+use crate::models::telegram::{InlineKeyboardButton, InlineKeyboardMarkup, Response};
+use crate::models::{message::Message, Notification};
+use color_eyre::{eyre::eyre, Report};
+use secrecy::ExposeSecret;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use tracing::{debug, info, warn};
 
 pub async fn post(
-    events: Vec<Event>,
+    notifications: Vec<Notification>,
     client: reqwest::Client,
-    secret: SecretString,
-    chat_id: i64,
+    endpoint: &Endpoint,
 ) -> Result<(), Report> {
-    let text = events.iter().map(|event| Message {
+    let chat_id = endpoint
+        .telegram
+        .chat_id
+        .ok_or_else(|| eyre!("Chat id can't be read"))?;
+    let text = notifications.iter().map(|notification| Message {
         chat_id,
-        text: format!("{event:?}"),
+        text: notification.to_string(),
+        reply_markup: keyboard(notification, endpoint),
     });
     info!("Mapped events to text");
 
-    let url = format!(
-        "https://api.telegram.org/bot{}/sendMessage",
-        secret.expose_secret()
-    );
-
     for message in text {
         debug!(contents = ?message, "Sending message");
-        client.post(&url).json(&message).send().await?;
+        call::<Value>(&client, endpoint, "sendMessage", &message).await?;
         info!(contents = ?message, "Sent message");
     }
     Ok(())
 }
+
+/// Calls a Telegram Bot API method, failing unless Telegram reports success
+pub async fn call<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    endpoint: &Endpoint,
+    method: &str,
+    body: &(impl Serialize + Sync),
+) -> Result<T, Report> {
+    let response: Response<T> = client
+        .post(method_url(endpoint, method))
+        .json(body)
+        .send()
+        .await
+        .map_err(reqwest::Error::without_url)?
+        .json()
+        .await
+        .map_err(reqwest::Error::without_url)?;
+    match response {
+        Response {
+            ok: true,
+            result: Some(result),
+            ..
+        } => Ok(result),
+        Response { description, .. } => Err(eyre!(
+            "Telegram method {method} failed: {}",
+            description.unwrap_or_default()
+        )),
+    }
+}
+
+fn method_url(endpoint: &Endpoint, method: &str) -> String {
+    format!(
+        "{}/bot{}/{method}",
+        endpoint.telegram.api_url.trim_end_matches('/'),
+        endpoint.telegram_secret.expose_secret()
+    )
+}
+
+/// Approval buttons for events that wait for an admin to act on them
+fn keyboard(notification: &Notification, endpoint: &Endpoint) -> Option<InlineKeyboardMarkup> {
+    let event = &notification.event;
+    let field = |name: &str| {
+        event
+            .data
+            .as_ref()
+            .and_then(|data| data.get(name))
+            .and_then(Value::as_str)
+    };
+    let target = match event.r#type.as_str() {
+        "nodeNeedsApproval" => field("nodeID").map(|id| (Subject::Node, id)),
+        "userNeedsApproval" => notification
+            .user
+            .as_ref()
+            .map(|user| user.id.as_str())
+            .or_else(|| field("userID"))
+            .map(|id| (Subject::User, id)),
+        _ => return None,
+    };
+
+    let mut inline_keyboard = Vec::new();
+    if let (Some((subject, id)), Some(_)) = (target, &endpoint.api) {
+        let buttons: Vec<_> = [("Approve", Decision::Approve), ("Reject", Decision::Reject)]
+            .into_iter()
+            .map(|(text, decision)| InlineKeyboardButton {
+                text: text.to_owned(),
+                callback_data: Some(
+                    Approval {
+                        decision,
+                        subject,
+                        id: id.to_owned(),
+                        tailnet: endpoint.reference(),
+                    }
+                    .to_string(),
+                ),
+                url: None,
+            })
+            .collect();
+        // Telegram refuses the whole message if any callback data is too long
+        if buttons.iter().all(|button| {
+            button
+                .callback_data
+                .as_ref()
+                .is_some_and(|data| data.len() <= MAX_CALLBACK_DATA)
+        }) {
+            inline_keyboard.push(buttons);
+        } else {
+            warn!(id, "ID is too long for approval buttons, sending none");
+        }
+    }
+    if let Some(url) = field("url") {
+        inline_keyboard.push(vec![InlineKeyboardButton {
+            text: "Open in console".to_owned(),
+            callback_data: None,
+            url: Some(url.to_owned()),
+        }]);
+    }
+
+    (!inline_keyboard.is_empty()).then_some(InlineKeyboardMarkup { inline_keyboard })
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::config::new_config_with_secrets;
+    use crate::models::Event;
+    use crate::services::tailscale_api;
+    use chrono::Utc;
+    use serde_json::json;
+    use tailforward_cfg::config::TailscaleApi;
+    use test_case::test_case;
+
+    #[test_case("nodeNeedsApproval", true, "n1" => vec!["Approve", "Reject", "Open in console"]; "when approvable")]
+    #[test_case("nodeNeedsApproval", false, "n1" => vec!["Open in console"]; "when no api")]
+    #[test_case("nodeCreated", true, "n1" => Vec::<String>::new(); "when not approvable")]
+    #[test_case("nodeNeedsApproval", true, &"n".repeat(64) => vec!["Open in console"]; "when id too long")]
+    fn keyboard_buttons(r#type: &str, api: bool, id: &str) -> Vec<String> {
+        let mut endpoint =
+            new_config_with_secrets("tail".to_owned().into(), "tele".to_owned().into())
+                .unwrap()
+                .endpoint;
+        if api {
+            endpoint.api = Some(tailscale_api::Client::new(
+                &TailscaleApi::default(),
+                "key".to_owned().into(),
+            ));
+        }
+        let notification = Notification::from(Event {
+            timestamp: Utc::now(),
+            version: 1,
+            r#type: r#type.to_owned(),
+            tailnet: "example.com".to_owned(),
+            message: "Node needs approval".to_owned(),
+            data: Some(json!({"nodeID": id, "url": "https://login.tailscale.com/admin/machines"})),
+        });
+
+        keyboard(&notification, &endpoint)
+            .map(|keyboard| {
+                keyboard
+                    .inline_keyboard
+                    .into_iter()
+                    .flatten()
+                    .map(|button| button.text)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn errors_do_not_contain_token() {
+        let mut endpoint =
+            new_config_with_secrets("tail".to_owned().into(), "123:secret".to_owned().into())
+                .unwrap()
+                .endpoint;
+        // Nothing listens on port 9 on the loopback interface
+        endpoint.telegram.api_url = "http://127.0.0.1:9".to_owned();
+
+        let error = call::<Value>(&reqwest::Client::new(), &endpoint, "getMe", &json!({}))
+            .await
+            .unwrap_err();
+
+        assert!(!format!("{error:?}").contains("123:secret"), "{error:?}");
+    }
+}
@@ -0,0 +1,575 @@
+use crate::config::Endpoint;
+use crate::models::approval::{Approval, Decision, Subject};
+use crate::models::command::Command;
+use crate::models::error::BotCommand;
+use crate::models::telegram::{CallbackQuery, IncomingMessage, Update};
+use crate::runtime::MUTE_ALL;
+use crate::services::telegram::call;
+use crate::State;
+use chrono::{Duration as ChronoDuration, Utc};
+use color_eyre::{eyre::eyre, Report};
+use secrecy::ExposeSecret;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tracing::{error, info, warn};
+
+/// Seconds Telegram holds a `getUpdates` request open waiting for updates
+const POLL_TIMEOUT: u64 = 30;
+
+/// Most events `/recent` is allowed to show
+const MAX_RECENT: usize = 20;
+
+const USAGE: &str = "Commands:
+/status - uptime, last webhook, queue depth and sink health
+/mute <type|all> <duration> - stop notifying, e.g. /mute nodeCreated 2h
+/unmute - lift every mute of this chat's tailnets
+/recent [n] - last n events";
+
+/// Long-polls every bot that has admins configured, running bot commands and
+/// acting on pressed inline buttons
+#[tracing::instrument(skip(state))]
+pub async fn receive_updates(state: State) {
+    let mut bots = HashSet::new();
+    // Dropping the set, e.g. when the configuration is reloaded, stops every poller with it
+    let mut tasks = JoinSet::new();
+    for endpoint in state.settings().endpoints() {
+        if endpoint.telegram.admins.is_empty()
+            || !bots.insert(endpoint.telegram_secret.expose_secret().clone())
+        {
+            continue;
+        }
+        info!(name = endpoint.name, "Receiving Telegram updates");
+        tasks.spawn(poll(state.clone(), endpoint.clone()));
+    }
+    while let Some(result) = tasks.join_next().await {
+        if let Err(error) = result {
+            error!(?error, "Telegram updates receiver stopped");
+        }
+    }
+}
+
+async fn poll(state: State, bot: Endpoint) {
+    // The bot ID is the part of the token before the colon, and isn't secret
+    let bot_id = bot
+        .telegram_secret
+        .expose_secret()
+        .split(':')
+        .next()
+        .unwrap_or_default()
+        .to_owned();
+    let mut offset = state.runtime.update_offset(&bot_id);
+    loop {
+        let request = json!({
+            "offset": offset,
+            "timeout": POLL_TIMEOUT,
+            "allowed_updates": ["message", "callback_query"],
+        });
+        let updates: Vec<Update> =
+            match call(&state.reqwest_client, &bot, "getUpdates", &request).await {
+                Ok(updates) => updates,
+                Err(error) => {
+                    warn!(?error, "Failed to get Telegram updates, retrying");
+                    state.metrics.record_retry("getUpdates");
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    continue;
+                }
+            };
+        for update in updates {
+            offset = offset.max(update.update_id + 1);
+            state.runtime.record_update_offset(&bot_id, offset);
+            if let Some(message) = update.message {
+                handle_message(&state, &bot, message).await;
+            }
+            if let Some(query) = update.callback_query {
+                handle_callback(&state, &bot, query).await;
+            }
+        }
+    }
+}
+
+/// Runs a bot command sent by an admin and replies with its outcome
+#[tracing::instrument(skip(state, bot))]
+pub async fn handle_message(state: &State, bot: &Endpoint, message: IncomingMessage) {
+    let Some(text) = message.text.as_deref().filter(|text| text.starts_with('/')) else {
+        return;
+    };
+    let Some(from) = &message.from else {
+        return;
+    };
+    let endpoints = scope(state, bot, message.chat.id, from.id);
+    if endpoints.is_empty() {
+        warn!(user = from.id, "User is not allowed to run commands");
+        return;
+    }
+
+    let reply = match text.parse() {
+        Ok(command) => run(state, &endpoints, command),
+        Err(error) => format!("{error}\n\n{USAGE}"),
+    };
+    let request = json!({ "chat_id": message.chat.id, "text": reply });
+    if let Err(error) = call::<Value>(&state.reqwest_client, bot, "sendMessage", &request).await {
+        warn!(?error, "Failed to reply to command");
+    }
+}
+
+/// Names of the endpoints a command applies to: those served by the same bot
+/// that the sender is an admin of, narrowed down to the one whose chat it was
+/// sent in if there is one
+fn scope(state: &State, bot: &Endpoint, chat: i64, user: i64) -> Vec<String> {
+    let settings = state.settings();
+    let administered: Vec<_> = settings
+        .endpoints()
+        .filter(|endpoint| {
+            endpoint.telegram_secret.expose_secret() == bot.telegram_secret.expose_secret()
+                && endpoint.telegram.admins.contains(&user)
+        })
+        .collect();
+    let in_chat: Vec<_> = administered
+        .iter()
+        .copied()
+        .filter(|endpoint| endpoint.telegram.chat_id == Some(chat))
+        .collect();
+    let scoped = if in_chat.is_empty() {
+        administered
+    } else {
+        in_chat
+    };
+    scoped
+        .iter()
+        .map(|endpoint| endpoint.name.clone())
+        .collect()
+}
+
+/// Endpoint names as shown in replies
+fn describe(endpoints: &[String]) -> String {
+    endpoints
+        .iter()
+        .map(|name| if name.is_empty() { "default" } else { name })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn run(state: &State, endpoints: &[String], command: Command) -> String {
+    let runtime = &state.runtime;
+    match command {
+        Command::Status => status(state, endpoints),
+        Command::Mute { target, duration } => {
+            let Some(until) = Utc::now().checked_add_signed(duration) else {
+                return format!(
+                    "{}\n\n{USAGE}",
+                    BotCommand::InvalidArgument(duration.to_string())
+                );
+            };
+            for endpoint in endpoints {
+                runtime.mute(endpoint, &target, until);
+            }
+            info!(target, %duration, ?endpoints, "Muted events");
+            let what = if target == MUTE_ALL {
+                "all events".to_owned()
+            } else {
+                format!("{target} events")
+            };
+            format!(
+                "Muted {what} until {} for {}",
+                format_time(until),
+                describe(endpoints)
+            )
+        }
+        Command::Unmute => {
+            for endpoint in endpoints {
+                runtime.unmute(endpoint);
+            }
+            info!(?endpoints, "Unmuted events");
+            format!("Unmuted all events for {}", describe(endpoints))
+        }
+        Command::Recent(n) => {
+            let events = runtime.recent(endpoints, n.min(MAX_RECENT));
+            if events.is_empty() {
+                return "No events received yet".to_owned();
+            }
+            events
+                .iter()
+                .map(|event| {
+                    format!(
+                        "{} {} ({}): {}",
+                        format_time(event.timestamp),
+                        event.r#type,
+                        event.tailnet,
+                        event.message
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+    }
+}
+
+fn status(state: &State, endpoints: &[String]) -> String {
+    let runtime = &state.runtime;
+    let mut text = format!("Uptime: {}", format_duration(Utc::now() - runtime.started));
+    let last_webhook = runtime
+        .last_webhook()
+        .map_or_else(|| "never".to_owned(), format_time);
+    let _ = write!(text, "\nLast webhook: {last_webhook}");
+    let _ = write!(text, "\nQueue depth: {}", runtime.in_flight());
+    for endpoint in endpoints {
+        for (target, until) in runtime.mutes(endpoint) {
+            let _ = write!(
+                text,
+                "\nMuted: {target} until {} for {}",
+                format_time(until),
+                describe(std::slice::from_ref(endpoint))
+            );
+        }
+    }
+    // Shared sinks are left to the health checks, as are those of other endpoints
+    let sinks: Vec<_> = runtime
+        .sinks()
+        .into_iter()
+        .filter(|(_, health)| {
+            health
+                .endpoint
+                .as_ref()
+                .is_some_and(|endpoint| endpoints.contains(endpoint))
+        })
+        .collect();
+    if sinks.is_empty() {
+        text.push_str("\nSinks: nothing delivered yet");
+    }
+    for (sink, health) in sinks {
+        let healthy = match (&health.last_success, &health.last_failure) {
+            (_, None) => true,
+            (Some(success), Some((failure, _))) => success > failure,
+            (None, Some(_)) => false,
+        };
+        let _ = write!(text, "\n{sink}: {}", if healthy { "ok" } else { "failing" });
+        if let Some(success) = health.last_success {
+            let _ = write!(text, ", last delivered {}", format_time(success));
+        }
+        if let Some((failure, error)) = health.last_failure {
+            let _ = write!(text, ", last failed {} ({error})", format_time(failure));
+        }
+    }
+    text
+}
+
+fn format_time(time: chrono::DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+}
+
+fn format_duration(duration: ChronoDuration) -> String {
+    let days = duration.num_days();
+    let hours = duration.num_hours() % 24;
+    let minutes = duration.num_minutes() % 60;
+    format!("{days}d {hours}h {minutes}m")
+}
+
+/// Acts on a pressed approval button and answers the callback query
+#[tracing::instrument(skip(state, bot))]
+pub async fn handle_callback(state: &State, bot: &Endpoint, query: CallbackQuery) {
+    let answer = match act(state, bot, &query).await {
+        Ok(text) => text,
+        Err(error) => {
+            warn!(?error, "Failed to act on callback query");
+            format!("Failed: {error}")
+        }
+    };
+    let request = json!({ "callback_query_id": query.id, "text": answer });
+    if let Err(error) =
+        call::<Value>(&state.reqwest_client, bot, "answerCallbackQuery", &request).await
+    {
+        warn!(?error, "Failed to answer callback query");
+    }
+}
+
+async fn act(state: &State, bot: &Endpoint, query: &CallbackQuery) -> Result<String, Report> {
+    let approval: Approval = query
+        .data
+        .as_deref()
+        .ok_or_else(|| eyre!("Callback query has no data"))?
+        .parse()?;
+    let settings = state.settings();
+    let endpoint = settings
+        .endpoint_by_reference(&approval.tailnet)
+        .ok_or_else(|| eyre!("Tailnet {} is not configured", approval.tailnet))?;
+    if !endpoint.telegram.admins.contains(&query.from.id) {
+        warn!(
+            user = query.from.id,
+            "User is not allowed to act on approvals"
+        );
+        return Ok("You are not allowed to do this".to_owned());
+    }
+    let api = endpoint
+        .api
+        .as_ref()
+        .ok_or_else(|| eyre!("Tailscale API is not configured"))?;
+
+    match (approval.decision, approval.subject) {
+        (Decision::Approve, Subject::Node) => api.authorize_device(&approval.id).await?,
+        (Decision::Reject, Subject::Node) => api.delete_device(&approval.id).await?,
+        (Decision::Approve, Subject::User) => api.approve_user(&approval.id).await?,
+        (Decision::Reject, Subject::User) => api.delete_user(&approval.id).await?,
+    }
+    let verdict = match approval.decision {
+        Decision::Approve => "Approved",
+        Decision::Reject => "Rejected",
+    };
+    let actor = query
+        .from
+        .username
+        .as_ref()
+        .map_or_else(|| query.from.first_name.clone(), |name| format!("@{name}"));
+    info!(%approval, actor, "Acted on approval");
+
+    if let Some(message) = &query.message {
+        let text = format!(
+            "{}\n\n{verdict} by {actor} at {}",
+            message.text.as_deref().unwrap_or_default(),
+            format_time(Utc::now())
+        );
+        // Not passing a reply markup removes the buttons
+        let request = json!({
+            "chat_id": message.chat.id,
+            "message_id": message.message_id,
+            "text": text,
+        });
+        call::<Value>(&state.reqwest_client, bot, "editMessageText", &request).await?;
+    }
+    Ok(verdict.to_owned())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::config::new_config_with_secrets;
+    use crate::models::telegram::{Chat, User};
+    use crate::models::Event;
+    use crate::services::tailscale_api;
+    use axum::extract::State as AxumState;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use std::future::IntoFuture;
+    use std::sync::{Arc, Mutex};
+    use tailforward_cfg::config::TailscaleApi;
+    use tokio::net::TcpListener;
+
+    type Calls = Arc<Mutex<Vec<Value>>>;
+
+    async fn mock(calls: Calls) -> String {
+        let record = |name: &'static str| {
+            move |AxumState(calls): AxumState<Calls>, body: Option<Json<Value>>| async move {
+                let body = body.map_or(Value::Null, |Json(body)| body);
+                calls
+                    .lock()
+                    .unwrap()
+                    .push(json!({ "method": name, "body": body }));
+                Json(json!({"ok": true, "result": true}))
+            }
+        };
+        let app = Router::new()
+            .route("/api/v2/device/:id/authorized", post(record("authorize")))
+            .route("/bottele/editMessageText", post(record("edit")))
+            .route("/bottele/answerCallbackQuery", post(record("answer")))
+            .route("/bottele/sendMessage", post(record("send")))
+            .with_state(calls);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, app).into_future());
+        format!("http://{addr}")
+    }
+
+    async fn state(calls: Calls) -> State {
+        let url = mock(calls).await;
+        let mut settings =
+            new_config_with_secrets("tail".to_owned().into(), "tele".to_owned().into()).unwrap();
+        settings.endpoint.telegram.api_url.clone_from(&url);
+        settings.endpoint.telegram.admins = vec![1];
+        settings.endpoint.api = Some(tailscale_api::Client::new(
+            &TailscaleApi {
+                base_url: url,
+                ..TailscaleApi::default()
+            },
+            "key".to_owned().into(),
+        ));
+        State::new(settings)
+    }
+
+    fn user(id: i64) -> User {
+        User {
+            id,
+            first_name: "Alice".to_owned(),
+            username: None,
+        }
+    }
+
+    fn methods(calls: &Calls) -> Vec<String> {
+        calls
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|call| call["method"].as_str().unwrap().to_owned())
+            .collect()
+    }
+
+    async fn press(from: i64) -> Vec<String> {
+        let calls = Calls::default();
+        let state = state(calls.clone()).await;
+        let query = CallbackQuery {
+            id: "q1".to_owned(),
+            from: user(from),
+            message: Some(IncomingMessage {
+                message_id: 1,
+                from: None,
+                chat: Chat { id: -1 },
+                text: Some("Node needs approval".to_owned()),
+            }),
+            data: Some("approve:node::n1".to_owned()),
+        };
+
+        handle_callback(&state, &state.settings().endpoint, query).await;
+
+        methods(&calls)
+    }
+
+    #[tokio::test]
+    async fn admin_approves_node() {
+        assert_eq!(press(1).await, vec!["authorize", "edit", "answer"]);
+    }
+
+    #[tokio::test]
+    async fn stranger_cannot_approve_node() {
+        assert_eq!(press(2).await, vec!["answer"]);
+    }
+
+    async fn send(state: &State, calls: &Calls, from: i64, text: &str) -> Option<String> {
+        let message = IncomingMessage {
+            message_id: 1,
+            from: Some(user(from)),
+            chat: Chat { id: -1 },
+            text: Some(text.to_owned()),
+        };
+        handle_message(state, &state.settings().endpoint, message).await;
+        let reply = calls.lock().unwrap().pop()?;
+        Some(reply["body"]["text"].as_str()?.to_owned())
+    }
+
+    #[tokio::test]
+    async fn admin_mutes_and_unmutes() {
+        let calls = Calls::default();
+        let state = state(calls.clone()).await;
+
+        let reply = send(&state, &calls, 1, "/mute nodeCreated 1h")
+            .await
+            .unwrap();
+        assert!(reply.starts_with("Muted nodeCreated events until"));
+        assert!(reply.ends_with("for default"));
+        assert!(state.runtime.is_muted("", "nodeCreated"));
+
+        send(&state, &calls, 1, "/unmute").await.unwrap();
+        assert!(!state.runtime.is_muted("", "nodeCreated"));
+    }
+
+    #[tokio::test]
+    async fn admin_cannot_mute_for_ever() {
+        let calls = Calls::default();
+        let state = state(calls.clone()).await;
+
+        let reply = send(&state, &calls, 1, "/mute all 100000000d")
+            .await
+            .unwrap();
+
+        assert!(reply.starts_with("invalid argument"));
+        assert!(!state.runtime.is_muted("", "nodeCreated"));
+    }
+
+    #[tokio::test]
+    async fn admin_mutes_own_tailnet_only() {
+        let calls = Calls::default();
+        let mut settings = (*state(calls.clone()).await.settings()).clone();
+        let mut corp = settings.endpoint.clone();
+        corp.name = "corp".to_owned();
+        corp.telegram.admins = vec![3];
+        corp.telegram.chat_id = Some(-1);
+        settings.tailnets.insert("corp".to_owned(), corp);
+        let state = State::new(settings);
+
+        let reply = send(&state, &calls, 3, "/mute all 1h").await.unwrap();
+
+        assert!(reply.ends_with("for corp"));
+        assert!(state.runtime.is_muted("corp", "nodeCreated"));
+        assert!(!state.runtime.is_muted("", "nodeCreated"));
+    }
+
+    #[tokio::test]
+    async fn admin_gets_status_and_recent() {
+        let calls = Calls::default();
+        let state = state(calls.clone()).await;
+        state.runtime.record_events(
+            "",
+            &[Event {
+                timestamp: Utc::now(),
+                version: 1,
+                r#type: "nodeCreated".to_owned(),
+                tailnet: "example.com".to_owned(),
+                message: "Node created".to_owned(),
+                data: None,
+            }],
+        );
+
+        let status = send(&state, &calls, 1, "/status").await.unwrap();
+        assert!(status.contains("Last webhook: never"));
+        assert!(status.contains("Queue depth: 0"));
+
+        let recent = send(&state, &calls, 1, "/recent").await.unwrap();
+        assert!(recent.ends_with("nodeCreated (example.com): Node created"));
+    }
+
+    #[tokio::test]
+    async fn admin_sees_own_tailnet_only() {
+        let calls = Calls::default();
+        let mut settings = (*state(calls.clone()).await.settings()).clone();
+        let mut corp = settings.endpoint.clone();
+        corp.name = "corp".to_owned();
+        corp.telegram.admins = vec![3];
+        corp.telegram.chat_id = Some(-1);
+        settings.tailnets.insert("corp".to_owned(), corp);
+        let state = State::new(settings);
+        let event = |message: &str| Event {
+            timestamp: Utc::now(),
+            version: 1,
+            r#type: "nodeCreated".to_owned(),
+            tailnet: "example.com".to_owned(),
+            message: message.to_owned(),
+            data: None,
+        };
+        state.runtime.record_events("", &[event("Default node")]);
+        state.runtime.record_events("corp", &[event("Corp node")]);
+        state
+            .runtime
+            .record_delivery(Some(""), "telegram", &Err(eyre!("default")));
+        state
+            .runtime
+            .record_delivery(Some("corp"), "telegram/corp", &Ok(()));
+
+        let recent = send(&state, &calls, 3, "/recent").await.unwrap();
+        let status = send(&state, &calls, 3, "/status").await.unwrap();
+
+        assert!(recent.ends_with("Corp node"));
+        assert!(!recent.contains("Default node"));
+        assert!(status.contains("telegram/corp: ok"));
+        assert!(!status.contains("default"));
+    }
+
+    #[tokio::test]
+    async fn stranger_cannot_run_commands() {
+        let calls = Calls::default();
+        let state = state(calls.clone()).await;
+
+        assert_eq!(send(&state, &calls, 2, "/mute all 1h").await, None);
+        assert!(!state.runtime.is_muted("", "nodeCreated"));
+    }
+}
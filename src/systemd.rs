@@ -0,0 +1,146 @@
+use crate::listen::Socket;
+use crate::services::health::{readiness, Status};
+use crate::State;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tracing::{debug, info, warn};
+
+/// Sockets passed by systemd socket activation, in the order of the `.socket` unit
+///
+/// # Errors
+/// If `LISTEN_FDS` is invalid or a socket can't be used
+#[cfg(unix)]
+pub fn listeners() -> std::io::Result<Vec<Socket>> {
+    use nix::sys::socket::{getsockname, AddressFamily, SockaddrLike, SockaddrStorage};
+    use std::os::fd::FromRawFd;
+
+    sd_notify::listen_fds()?
+        .map(|fd| {
+            let family = getsockname::<SockaddrStorage>(fd)?.family();
+            info!(fd, ?family, "Using socket from systemd");
+            // SAFETY: systemd hands these descriptors over to this process, and
+            // `listen_fds` unsets the variables so that they're only taken once
+            if family == Some(AddressFamily::Unix) {
+                let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+                listener.set_nonblocking(true)?;
+                tokio::net::UnixListener::from_std(listener).map(Socket::Unix)
+            } else {
+                let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+                listener.set_nonblocking(true)?;
+                TcpListener::from_std(listener).map(Socket::Tcp)
+            }
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+pub fn listeners() -> std::io::Result<Vec<Socket>> {
+    Ok(Vec::new())
+}
+
+/// Tells systemd that startup finished, or that a reload did
+pub fn ready() {
+    #[cfg(unix)]
+    notify(&[sd_notify::NotifyState::Ready]);
+}
+
+/// Tells systemd that the configuration is being reloaded, for `Type=notify-reload`
+pub fn reloading() {
+    #[cfg(unix)]
+    match sd_notify::NotifyState::monotonic_usec_now() {
+        Ok(now) => notify(&[sd_notify::NotifyState::Reloading, now]),
+        Err(error) => debug!(?error, "Can't read the monotonic clock"),
+    }
+}
+
+/// Tells systemd that shutdown started
+pub fn stopping() {
+    #[cfg(unix)]
+    notify(&[sd_notify::NotifyState::Stopping]);
+}
+
+#[cfg(unix)]
+fn notify(states: &[sd_notify::NotifyState]) {
+    // Does nothing unless started by systemd with `NOTIFY_SOCKET`
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(error) = notify_at(std::path::Path::new(&socket), states) {
+        debug!(?error, "Failed to notify systemd");
+    }
+}
+
+/// Sends states to the notification socket at `path`
+#[cfg(unix)]
+fn notify_at(path: &std::path::Path, states: &[sd_notify::NotifyState]) -> std::io::Result<()> {
+    use std::fmt::Write;
+
+    let mut message = String::new();
+    for state in states {
+        let _ = writeln!(message, "{state}");
+    }
+    let socket = std::os::unix::net::UnixDatagram::unbound()?;
+    socket.send_to(message.as_bytes(), path).map(drop)
+}
+
+/// Pings the systemd watchdog at half its interval for as long as the service is
+/// ready, so that systemd restarts it once it can't process webhooks anymore
+#[tracing::instrument(skip(state))]
+pub async fn watchdog(state: State) {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    info!(?interval, "Pinging the systemd watchdog");
+    loop {
+        tokio::time::sleep(interval / 2).await;
+        // Without probing sinks, which could take longer than the watchdog waits
+        let readiness = readiness(&state, false).await;
+        if readiness.status == Status::Unavailable {
+            warn!(?readiness, "Not ready, skipping the watchdog ping");
+            continue;
+        }
+        #[cfg(unix)]
+        notify(&[sd_notify::NotifyState::Watchdog]);
+    }
+}
+
+#[cfg(unix)]
+fn watchdog_interval() -> Option<Duration> {
+    let mut usec = 0;
+    sd_notify::watchdog_enabled(false, &mut usec).then(|| Duration::from_micros(usec))
+}
+
+#[cfg(not(unix))]
+const fn watchdog_interval() -> Option<Duration> {
+    None
+}
+
+#[cfg(all(test, unix))]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+
+    #[test]
+    fn notifies_notify_socket() {
+        let path = std::env::temp_dir().join(format!("tailforward-notify-{}", std::process::id()));
+        let socket = UnixDatagram::bind(&path).unwrap();
+        let now = sd_notify::NotifyState::monotonic_usec_now().unwrap();
+
+        notify_at(&path, &[sd_notify::NotifyState::Ready]).unwrap();
+        notify_at(&path, &[sd_notify::NotifyState::Reloading, now]).unwrap();
+        notify_at(&path, &[sd_notify::NotifyState::Stopping]).unwrap();
+
+        let mut buffer = [0; 128];
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            let length = socket.recv(&mut buffer).unwrap();
+            received.push(String::from_utf8_lossy(&buffer[..length]).into_owned());
+        }
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(received[0], "READY=1\n");
+        assert!(received[1].starts_with("RELOADING=1\nMONOTONIC_USEC="));
+        assert_eq!(received[2], "STOPPING=1\n");
+    }
+}
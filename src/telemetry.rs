@@ -0,0 +1,113 @@
+use color_eyre::Result;
+use tailforward_cfg::config::{Exporter, Telemetry};
+
+/// Exporter to use, falling back to gRPC if the standard endpoint variable is set
+#[must_use]
+pub fn exporter(config: &Telemetry) -> Exporter {
+    config.exporter.unwrap_or_else(|| {
+        if std::env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").is_some() {
+            Exporter::OtlpGrpc
+        } else {
+            Exporter::Disabled
+        }
+    })
+}
+
+/// Tracing layer exporting spans as configured, if any
+///
+/// # Errors
+/// If the exporter can't be set up or isn't compiled in
+#[cfg(feature = "opentelemetry")]
+pub fn layer<S>(config: &Telemetry) -> Result<Option<impl tracing_subscriber::Layer<S>>>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    use color_eyre::eyre::eyre;
+    use opentelemetry::{trace::TracerProvider, KeyValue};
+    use opentelemetry_otlp::{SpanExporterBuilder, WithExportConfig};
+    use opentelemetry_sdk::trace::{self, Sampler};
+    use opentelemetry_sdk::Resource;
+
+    let exporter: SpanExporterBuilder = match exporter(config) {
+        Exporter::Disabled => return Ok(None),
+        #[cfg(feature = "otlp-grpc")]
+        Exporter::OtlpGrpc => {
+            let mut builder = opentelemetry_otlp::new_exporter().tonic();
+            if let Some(endpoint) = &config.endpoint {
+                builder = builder.with_endpoint(endpoint);
+            }
+            if !config.headers.is_empty() {
+                let mut headers = reqwest::header::HeaderMap::new();
+                for (name, value) in &config.headers {
+                    headers.insert(
+                        reqwest::header::HeaderName::try_from(name)?,
+                        value.try_into()?,
+                    );
+                }
+                builder =
+                    builder.with_metadata(tonic::metadata::MetadataMap::from_headers(headers));
+            }
+            builder.into()
+        }
+        #[cfg(feature = "otlp-http")]
+        Exporter::OtlpHttp => {
+            let mut builder = opentelemetry_otlp::new_exporter()
+                .http()
+                .with_headers(config.headers.clone().into_iter().collect());
+            if let Some(endpoint) = &config.endpoint {
+                builder = builder.with_endpoint(endpoint);
+            }
+            builder.into()
+        }
+        #[allow(unreachable_patterns)]
+        other => return Err(eyre!("The {other:?} exporter is not compiled in")),
+    };
+
+    let mut attributes = vec![KeyValue::new("service.name", config.service_name.clone())];
+    if let Some(id) = &config.service_instance_id {
+        attributes.push(KeyValue::new("service.instance.id", id.clone()));
+    }
+    attributes.extend(
+        config
+            .resource_attributes
+            .iter()
+            .map(|(key, value)| KeyValue::new(key.clone(), value.clone())),
+    );
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio)));
+
+    let provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .with_trace_config(
+            trace::Config::default()
+                .with_sampler(sampler)
+                .with_resource(Resource::default().merge(&Resource::new(attributes))),
+        )
+        .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+    let tracer = provider.tracer("tailforward");
+    opentelemetry::global::set_tracer_provider(provider);
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+/// Without OpenTelemetry support, only complains if an exporter is configured
+///
+/// # Errors
+/// If an exporter is configured
+#[cfg(not(feature = "opentelemetry"))]
+pub fn layer(config: &Telemetry) -> Result<Option<tracing_subscriber::layer::Identity>> {
+    match exporter(config) {
+        Exporter::Disabled => Ok(None),
+        other => Err(color_eyre::eyre::eyre!(
+            "The {other:?} exporter is not compiled in"
+        )),
+    }
+}
+
+/// Flushes spans that haven't been exported yet
+#[cfg(feature = "opentelemetry")]
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+#[cfg(not(feature = "opentelemetry"))]
+pub const fn shutdown() {}
@@ -0,0 +1,110 @@
+use axum_server::tls_rustls::RustlsConfig;
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tailforward_cfg::config::{Tls, TlsVersion};
+use tracing::{error, info};
+
+/// Builds the rustls configuration from the certificate files
+///
+/// # Errors
+/// If a file can't be read or doesn't contain what it should
+pub fn server_config(tls: &Tls) -> Result<Arc<ServerConfig>> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let versions: &[_] = match tls.min_version {
+        TlsVersion::Tls12 => &[&rustls::version::TLS13, &rustls::version::TLS12],
+        TlsVersion::Tls13 => &[&rustls::version::TLS13],
+    };
+    let builder =
+        ServerConfig::builder_with_provider(provider.clone()).with_protocol_versions(versions)?;
+    let builder = match &tls.client_ca_file {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in certificates(path)? {
+                roots
+                    .add(certificate)
+                    .wrap_err_with(|| format!("Invalid CA certificate in {path}"))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .wrap_err_with(|| format!("Can't verify clients with {path}"))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder
+        .with_single_cert(certificates(&tls.cert_file)?, private_key(&tls.key_file)?)
+        .wrap_err("Certificate doesn't match the private key")?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+fn certificates(path: &Utf8Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certificates = rustls_pemfile::certs(&mut reader(path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .wrap_err_with(|| format!("Invalid PEM in {path}"))?;
+    if certificates.is_empty() {
+        return Err(eyre!("No certificates in {path}"));
+    }
+    Ok(certificates)
+}
+
+fn private_key(path: &Utf8Path) -> Result<PrivateKeyDer<'static>> {
+    rustls_pemfile::private_key(&mut reader(path)?)
+        .wrap_err_with(|| format!("Invalid PEM in {path}"))?
+        .ok_or_else(|| eyre!("No private key in {path}"))
+}
+
+fn reader(path: &Utf8Path) -> Result<BufReader<File>> {
+    Ok(BufReader::new(
+        File::open(path).wrap_err_with(|| format!("Can't read {path}"))?,
+    ))
+}
+
+/// Reloads the certificates whenever one of the files changes, keeping the
+/// current ones if the new files are invalid
+#[tracing::instrument(skip(config))]
+pub async fn watch(config: RustlsConfig, tls: Tls) {
+    let mut fingerprint = fingerprint(&tls);
+    loop {
+        tokio::time::sleep(Duration::from_secs(tls.reload_interval)).await;
+        let current = self::fingerprint(&tls);
+        if current == fingerprint {
+            continue;
+        }
+        fingerprint = current;
+        match server_config(&tls) {
+            Ok(server_config) => {
+                config.reload_from_config(server_config);
+                info!("Reloaded TLS certificates");
+            }
+            Err(error) => error!(
+                ?error,
+                "Failed to reload TLS certificates, keeping the current ones"
+            ),
+        }
+    }
+}
+
+fn fingerprint(tls: &Tls) -> Vec<(Utf8PathBuf, Option<SystemTime>)> {
+    [
+        Some(&tls.cert_file),
+        Some(&tls.key_file),
+        tls.client_ca_file.as_ref(),
+    ]
+    .into_iter()
+    .flatten()
+    .map(|path| {
+        let modified = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        (path.clone(), modified)
+    })
+    .collect()
+}
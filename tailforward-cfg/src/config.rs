@@ -1,15 +1,39 @@
 #![allow(clippy::expect_used)]
+use crate::{ListenAddress, SecretSource};
 use camino::Utf8PathBuf;
+use ipnet::IpNet;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::{net::SocketAddr, str::FromStr};
+use std::{collections::BTreeMap, net::SocketAddr, str::FromStr};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(default)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
+    /// Log at debug level with source locations and span timings
     pub debug: bool,
     pub tailscale: Tailscale,
     pub telegram: Telegram,
+    /// Events whose `tailnet` field differs from this one are rejected on
+    /// `/tailscale-webhook`
+    pub tailnet: Option<String>,
     pub address: SocketAddr,
+    /// Sockets to serve on instead of `address` and `metrics.address`, applied on startup only
+    pub listeners: Vec<Listener>,
+    pub tailnets: Vec<Tailnet>,
+    /// Seconds between checks of the configuration and secret files for changes,
+    /// unset to only reload on SIGHUP
+    pub watch_interval: Option<u64>,
+    pub telemetry: Telemetry,
+    pub log: Log,
+    pub metrics: Metrics,
+    pub health: Health,
+    /// Serve HTTPS on `address` instead of plain HTTP
+    pub tls: Option<Tls>,
+    pub requests: Requests,
+    pub history: History,
+    pub api: Api,
+    pub dashboard: Dashboard,
+    pub archive: Archive,
 }
 
 impl Default for Config {
@@ -18,29 +42,512 @@ impl Default for Config {
             debug: false,
             tailscale: Tailscale::default(),
             telegram: Telegram::default(),
+            tailnet: None,
             address: SocketAddr::from_str("0.0.0.0:33010")
                 .expect("Default value for config should never panic!"),
+            listeners: Vec::new(),
+            tailnets: Vec::new(),
+            watch_interval: None,
+            telemetry: Telemetry::default(),
+            log: Log::default(),
+            metrics: Metrics::default(),
+            health: Health::default(),
+            tls: None,
+            requests: Requests::default(),
+            history: History::default(),
+            api: Api::default(),
+            dashboard: Dashboard::default(),
+            archive: Archive::default(),
         }
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
-#[serde(default)]
+impl Config {
+    /// Configuration with placeholder secret paths and chat ID, used as the example configuration
+    #[must_use]
+    pub fn example() -> Self {
+        Self {
+            tailscale: Tailscale {
+                secret_file: Some("/etc/tailforward/tailforward.toml".into()),
+                ..Default::default()
+            },
+            telegram: Telegram {
+                secret_file: Some("/secrets/telegram".into()),
+                file_format: Format::Plain,
+                chat_id: Some(-123),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+}
+
+/// Additional tailnet, served on `/tailscale-webhook/<name>`
+#[derive(Clone, Debug, Serialize, Deserialize, Default, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tailnet {
+    pub name: String,
+    /// Events whose `tailnet` field differs from this one are rejected
+    pub tailnet: Option<String>,
+    pub tailscale: Tailscale,
+    pub telegram: Telegram,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Default, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
 pub struct Tailscale {
+    pub secret: Option<SecretSource>,
+    /// Same as `secret = "file:<path>"`
+    #[schemars(with = "Option<String>")]
     pub secret_file: Option<Utf8PathBuf>,
+    pub file_format: Format,
+    /// Key or field of the secret in `Env`, `Json` and `Yaml` files
+    pub secret_key: Option<String>,
+    /// Enrich notifications with device and user details from the Tailscale API
+    pub api: Option<TailscaleApi>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct TailscaleApi {
+    pub base_url: String,
+    /// Tailnet to look users up in, `-` is the default tailnet of the credentials
+    pub tailnet: String,
+    /// API key, or OAuth client secret if `oauth_client_id` is set
+    pub secret: Option<SecretSource>,
+    /// Same as `secret = "file:<path>"`
+    #[schemars(with = "Option<String>")]
+    pub secret_file: Option<Utf8PathBuf>,
+    pub file_format: Format,
+    /// Key or field of the secret in `Env`, `Json` and `Yaml` files
+    pub secret_key: Option<String>,
+    pub oauth_client_id: Option<String>,
+    /// Seconds to keep looked up devices and users for
+    pub cache_ttl: u64,
+    /// Days before a device key expires to send reminders at, e.g. `[14, 7, 1]`
+    pub key_expiry_reminders: Vec<u32>,
+    /// Seconds between checks of device key expiry
+    pub key_expiry_interval: u64,
+}
+
+impl Default for TailscaleApi {
+    fn default() -> Self {
+        Self {
+            base_url: "https://api.tailscale.com".to_owned(),
+            tailnet: "-".to_owned(),
+            secret: None,
+            secret_file: None,
+            file_format: Format::default(),
+            secret_key: None,
+            oauth_client_id: None,
+            cache_ttl: 300,
+            key_expiry_reminders: Vec::new(),
+            key_expiry_interval: 3600,
+        }
+    }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
-#[serde(default)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
 pub struct Telegram {
+    pub secret: Option<SecretSource>,
+    /// Same as `secret = "file:<path>"`
+    #[schemars(with = "Option<String>")]
     pub secret_file: Option<Utf8PathBuf>,
     pub file_format: Format,
+    /// Key or field of the secret in `Env`, `Json` and `Yaml` files
+    pub secret_key: Option<String>,
     pub chat_id: Option<i64>,
+    pub api_url: String,
+    /// Telegram user IDs allowed to act on approval buttons
+    pub admins: Vec<i64>,
+}
+
+impl Default for Telegram {
+    fn default() -> Self {
+        Self {
+            secret: None,
+            secret_file: None,
+            file_format: Format::default(),
+            secret_key: None,
+            chat_id: None,
+            api_url: "https://api.telegram.org".to_owned(),
+            admins: Vec::new(),
+        }
+    }
+}
+
+/// Export of traces over OpenTelemetry, applied on startup only
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Telemetry {
+    /// Unset to use `OtlpGrpc` if `OTEL_EXPORTER_OTLP_ENDPOINT` is set, and `Disabled` otherwise
+    pub exporter: Option<Exporter>,
+    /// Collector URL, the exporter's default if unset
+    pub endpoint: Option<String>,
+    /// Sent with every export, e.g. for authentication
+    pub headers: BTreeMap<String, String>,
+    /// Fraction of traces to export, from 0 to 1
+    pub sample_ratio: f64,
+    pub service_name: String,
+    /// `service.instance.id` resource attribute, to tell replicas apart
+    pub service_instance_id: Option<String>,
+    /// Additional resource attributes, e.g. `{ "deployment.environment" = "production" }`
+    pub resource_attributes: BTreeMap<String, String>,
+}
+
+impl Default for Telemetry {
+    fn default() -> Self {
+        Self {
+            exporter: None,
+            endpoint: None,
+            headers: BTreeMap::new(),
+            sample_ratio: 1.0,
+            service_name: "tailforward".to_owned(),
+            service_instance_id: None,
+            resource_attributes: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+pub enum Exporter {
+    Disabled,
+    /// OTLP over gRPC, by default to `http://localhost:4317`
+    OtlpGrpc,
+    /// OTLP over HTTP with protobuf, by default to `http://localhost:4318/v1/traces`
+    OtlpHttp,
+}
+
+/// Log output, applied on startup only
+#[derive(Clone, Debug, Serialize, Deserialize, Default, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Log {
+    pub format: LogFormat,
+    /// `RUST_LOG`-style directives, e.g. `info,tailforward=debug`; `RUST_LOG` overrides them
+    pub filter: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Default, PartialEq, Eq, JsonSchema)]
+pub enum LogFormat {
+    /// Indented tree of spans, for reading in a terminal
+    #[default]
+    Hierarchical,
+    /// One line per event, with the span context in front
+    Compact,
+    /// One JSON object per line, for log shippers
+    Json,
+    /// Native journald fields, sent to the journal socket
+    Journald,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Listener {
+    pub address: ListenAddress,
+    /// Routes served on this socket
+    pub services: Vec<Service>,
+    /// Whether to use `[tls]`, if set, on a TCP socket
+    pub tls: bool,
+    /// Permissions of a Unix socket in octal, e.g. `"660"`
+    pub mode: Option<String>,
+    /// User owning a Unix socket, by name or ID
+    pub owner: Option<String>,
+    /// Group owning a Unix socket, by name or ID
+    pub group: Option<String>,
+}
+
+impl Default for Listener {
+    fn default() -> Self {
+        Self {
+            address: ListenAddress::Tcp(
+                SocketAddr::from_str("0.0.0.0:33010")
+                    .expect("Default value for config should never panic!"),
+            ),
+            services: Service::ALL.to_vec(),
+            tls: true,
+            mode: None,
+            owner: None,
+            group: None,
+        }
+    }
+}
+
+/// Group of routes that can be served on a listener
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+pub enum Service {
+    /// `/tailscale-webhook` and `/ping`
+    Webhooks,
+    /// `/healthz` and `/readyz`
+    Health,
+    /// `/metrics`
+    Metrics,
+    /// `/api/events`
+    Api,
+    /// `/dashboard`
+    Dashboard,
+}
+
+impl Service {
+    pub const ALL: &'static [Self] = &[
+        Self::Webhooks,
+        Self::Health,
+        Self::Metrics,
+        Self::Api,
+        Self::Dashboard,
+    ];
+}
+
+/// Limits on incoming requests; `concurrency_limit` is applied on startup only
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Requests {
+    /// Largest webhook body accepted, in bytes
+    pub max_body_size: usize,
+    /// Seconds a request may take before it's answered with 408
+    pub timeout: u64,
+    /// Reject webhooks not sent as `Content-Type: application/json`
+    pub require_json: bool,
+    /// Requests handled at once, further ones are answered with 503
+    pub concurrency_limit: Option<usize>,
+    /// Addresses or CIDR ranges allowed to send webhooks, any if empty
+    #[schemars(with = "Vec<String>")]
+    pub allow: Vec<IpNet>,
+    /// Reverse proxies whose `X-Forwarded-For` header is believed; requests over
+    /// Unix sockets always come from a trusted proxy
+    #[schemars(with = "Vec<String>")]
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+impl Default for Requests {
+    fn default() -> Self {
+        Self {
+            max_body_size: 1024 * 1024,
+            timeout: 30,
+            require_json: true,
+            concurrency_limit: None,
+            allow: Vec::new(),
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+/// Verified events kept in SQLite, along with their deliveries; `enabled` and
+/// `path` are applied on startup only
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct History {
+    pub enabled: bool,
+    /// SQLite database, created if missing
+    #[schemars(with = "String")]
+    pub path: Utf8PathBuf,
+    /// Days to keep events for, forever if unset
+    pub retention_days: Option<u32>,
+    /// Most events to keep, the oldest ones being removed first
+    pub max_events: Option<u64>,
+    /// Seconds between removals of old events
+    pub prune_interval: u64,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: "/var/lib/tailforward/history.db".into(),
+            retention_days: None,
+            max_events: None,
+            prune_interval: 3600,
+        }
+    }
+}
+
+/// Queries of the event history on `/api`
+#[derive(Clone, Debug, Serialize, Deserialize, Default, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Api {
+    /// Bearer token clients must send, every request being refused if unset
+    pub token: Option<SecretSource>,
+}
+
+/// HTML pages on `/dashboard` showing the history, sinks and configuration,
+/// and replaying failed deliveries; `enabled` is applied on startup only
+#[derive(Clone, Debug, Serialize, Deserialize, Default, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Dashboard {
+    pub enabled: bool,
+    /// Required when enabled
+    pub auth: Option<Auth>,
+}
+
+/// How clients prove who they are
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub enum Auth {
+    /// HTTP basic authentication, which browsers prompt for
+    Basic {
+        username: String,
+        password: SecretSource,
+    },
+    /// `Authorization: Bearer <token>`, e.g. added by a reverse proxy
+    Bearer { token: SecretSource },
+}
+
+/// Every verified event appended to JSON Lines files, e.g. for a log shipper;
+/// `enabled` and `directory` are applied on startup only
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Archive {
+    pub enabled: bool,
+    /// Directory of the files, created if missing; events are appended to
+    /// `events.jsonl`, which is renamed after the day or time it covers once rotated
+    #[schemars(with = "String")]
+    pub directory: Utf8PathBuf,
+    pub rotation: Rotation,
+    /// Bytes past which a file is rotated with `rotation = "Size"`
+    pub max_size: u64,
+    /// Applied to rotated files
+    pub compression: Compression,
+    pub fsync: Fsync,
+}
+
+impl Default for Archive {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: "/var/lib/tailforward/archive".into(),
+            rotation: Rotation::default(),
+            max_size: 100 * 1024 * 1024,
+            compression: Compression::default(),
+            fsync: Fsync::default(),
+        }
+    }
+}
+
+/// When the archive starts a new file
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Default, PartialEq, Eq, JsonSchema)]
+pub enum Rotation {
+    /// At the first event of each day, in UTC
+    #[default]
+    Daily,
+    /// Once the file has grown past `max_size`
+    Size,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Default, PartialEq, Eq, JsonSchema)]
+pub enum Compression {
+    #[default]
+    None,
+    /// `.gz`
+    Gzip,
+    /// `.zst`
+    Zstd,
+}
+
+/// When archived events are flushed to disk
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Default, PartialEq, Eq, JsonSchema)]
+pub enum Fsync {
+    /// After every webhook, before it's answered
+    Always,
+    /// When a file is rotated
+    #[default]
+    Rotation,
+    /// Whenever the operating system sees fit
+    Never,
+}
+
+/// Prometheus metrics on `/metrics`, applied on startup only
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Metrics {
+    pub enabled: bool,
+    /// Serve metrics on this address only instead of alongside the webhooks,
+    /// unless `listeners` are set
+    pub address: Option<SocketAddr>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            address: None,
+        }
+    }
+}
+
+/// Checks behind `/readyz`
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Health {
+    /// Call Telegram `getMe` for every bot on readiness checks
+    pub probe_sinks: bool,
+    /// Seconds to wait for a probe before considering it failed
+    pub probe_timeout: u64,
+    /// Seconds the result of a probe is reused for, so that frequent checks
+    /// don't call Telegram every time
+    pub probe_interval: u64,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            probe_sinks: false,
+            probe_timeout: 5,
+            probe_interval: 60,
+        }
+    }
+}
+
+/// HTTPS with rustls; the certificate and key are reloaded whenever they change
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tls {
+    /// PEM certificate chain, leaf first
+    #[schemars(with = "String")]
+    pub cert_file: Utf8PathBuf,
+    /// PEM private key
+    #[schemars(with = "String")]
+    pub key_file: Utf8PathBuf,
+    pub min_version: TlsVersion,
+    /// PEM CA certificates; when set, clients must present a certificate signed by one of them
+    #[schemars(with = "Option<String>")]
+    pub client_ca_file: Option<Utf8PathBuf>,
+    /// Seconds between checks of the certificate files for changes
+    pub reload_interval: u64,
+}
+
+impl Default for Tls {
+    fn default() -> Self {
+        Self {
+            cert_file: Utf8PathBuf::new(),
+            key_file: Utf8PathBuf::new(),
+            min_version: TlsVersion::default(),
+            client_ca_file: None,
+            reload_interval: 60,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Default, PartialEq, Eq, JsonSchema)]
+pub enum TlsVersion {
+    #[default]
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+/// Format of the contents of a secret
+#[derive(Clone, Debug, Serialize, Deserialize, Default, PartialEq, Eq, JsonSchema)]
 pub enum Format {
+    /// The whole contents are the secret
     #[default]
     Plain,
+    /// Same as `Env`, kept for compatibility
     Alertmanager,
+    /// `KEY=value` lines, like systemd `EnvironmentFile=`
+    Env,
+    /// A JSON document, `secret_key` being a dot-separated path to the field
+    Json,
+    /// A YAML document, `secret_key` being a dot-separated path to the field
+    Yaml,
 }
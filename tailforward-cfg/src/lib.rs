@@ -1,2 +1,11 @@
 pub mod config;
 pub use config::Config;
+
+pub mod listen;
+pub use listen::ListenAddress;
+
+pub mod secret;
+pub use secret::SecretSource;
+
+pub mod validate;
+pub use validate::Problem;
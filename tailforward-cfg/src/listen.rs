@@ -0,0 +1,99 @@
+use camino::Utf8PathBuf;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Schema, SchemaObject, StringValidation};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+/// Where to listen, written as `<ip>:<port>`, `[<ipv6>]:<port>` or `unix:<path>`
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ListenAddress {
+    Tcp(SocketAddr),
+    Unix(Utf8PathBuf),
+}
+
+impl FromStr for ListenAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err("unix: must be followed by the socket path".to_owned());
+            }
+            return Ok(Self::Unix(path.into()));
+        }
+        s.parse()
+            .map(Self::Tcp)
+            .map_err(|_| format!("{s:?} must be <ip>:<port>, [<ipv6>]:<port> or unix:<path>"))
+    }
+}
+
+impl JsonSchema for ListenAddress {
+    fn schema_name() -> String {
+        "ListenAddress".to_owned()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            string: Some(Box::new(StringValidation {
+                pattern: Some(r"^(unix:.+|\[[0-9A-Fa-f:.]+\]:\d+|[0-9.]+:\d+)$".to_owned()),
+                ..StringValidation::default()
+            })),
+            ..SchemaObject::default()
+        }
+        .into()
+    }
+}
+
+impl TryFrom<String> for ListenAddress {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ListenAddress> for String {
+    fn from(address: ListenAddress) -> Self {
+        address.to_string()
+    }
+}
+
+impl Display for ListenAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(address) => write!(f, "{address}"),
+            Self::Unix(path) => write!(f, "unix:{path}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        for address in [
+            "127.0.0.1:33010",
+            "[::1]:33010",
+            "unix:/run/tailforward.sock",
+        ] {
+            assert_eq!(
+                address.parse::<ListenAddress>().map(|a| a.to_string()),
+                Ok(address.to_owned())
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid() {
+        for address in ["localhost:80", "unix:", "33010"] {
+            assert!(address.parse::<ListenAddress>().is_err(), "{address}");
+        }
+    }
+}
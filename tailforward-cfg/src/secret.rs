@@ -0,0 +1,96 @@
+use camino::Utf8PathBuf;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Schema, SchemaObject, StringValidation};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::str::FromStr;
+
+/// Where to read a secret from, written as `<kind>:<value>`
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum SecretSource {
+    /// `credential:<name>`, a systemd credential in `$CREDENTIALS_DIRECTORY`
+    Credential(String),
+    /// `env:<VAR>`, an environment variable
+    Env(String),
+    /// `file:<path>`
+    File(Utf8PathBuf),
+    /// `literal:<value>`, the secret itself, for development only
+    Literal(String),
+}
+
+impl FromStr for SecretSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            "secret source must be one of credential:<name>, env:<VAR>, file:<path> or literal:<value>"
+                .to_owned()
+        };
+        let (kind, value) = s.split_once(':').ok_or_else(invalid)?;
+        if value.is_empty() {
+            return Err(invalid());
+        }
+        match kind {
+            "credential" => Ok(Self::Credential(value.to_owned())),
+            "env" => Ok(Self::Env(value.to_owned())),
+            "file" => Ok(Self::File(value.into())),
+            "literal" => Ok(Self::Literal(value.to_owned())),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl JsonSchema for SecretSource {
+    fn schema_name() -> String {
+        "SecretSource".to_owned()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            string: Some(Box::new(StringValidation {
+                pattern: Some("^(credential|env|file|literal):.+$".to_owned()),
+                ..StringValidation::default()
+            })),
+            ..SchemaObject::default()
+        }
+        .into()
+    }
+}
+
+impl TryFrom<String> for SecretSource {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<SecretSource> for String {
+    fn from(source: SecretSource) -> Self {
+        source.to_string()
+    }
+}
+
+impl Display for SecretSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Credential(name) => write!(f, "credential:{name}"),
+            Self::Env(var) => write!(f, "env:{var}"),
+            Self::File(path) => write!(f, "file:{path}"),
+            Self::Literal(value) => write!(f, "literal:{value}"),
+        }
+    }
+}
+
+/// Never shows literal secrets, unlike `Display` which has to round-trip
+impl Debug for SecretSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Literal(_) => write!(f, "literal:[REDACTED]"),
+            other => write!(f, "{other}"),
+        }
+    }
+}
@@ -0,0 +1,588 @@
+use crate::config::{Auth, Format, Tailscale, TailscaleApi, Telegram, Telemetry, Tls};
+use crate::{Config, ListenAddress, SecretSource};
+use camino::Utf8PathBuf;
+use schemars::schema::{RootSchema, Schema, SchemaObject, SingleOrVec};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+
+/// Something wrong with the configuration, at a dot-separated key path
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Problem {
+    pub path: String,
+    pub message: String,
+}
+
+impl Problem {
+    fn new(path: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+/// JSON Schema of the configuration file
+#[must_use]
+pub fn schema() -> RootSchema {
+    schemars::schema_for!(Config)
+}
+
+/// Keys of a configuration document that aren't in the schema, suggesting
+/// the closest known key for likely typos
+#[must_use]
+pub fn unknown_keys(document: &Value) -> Vec<Problem> {
+    let root = schema();
+    let mut problems = Vec::new();
+    walk(document, &root.schema, &root.definitions, "", &mut problems);
+    problems
+}
+
+fn walk(
+    value: &Value,
+    schema: &SchemaObject,
+    definitions: &BTreeMap<String, Schema>,
+    path: &str,
+    problems: &mut Vec<Problem>,
+) {
+    let Some(schema) = resolve(schema, definitions) else {
+        return;
+    };
+    match value {
+        Value::Object(map) => {
+            let Some(object) = &schema.object else {
+                return;
+            };
+            for (key, value) in map {
+                let child = join(path, key);
+                match object.properties.get(key) {
+                    Some(Schema::Object(property)) => {
+                        walk(value, property, definitions, &child, problems);
+                    }
+                    Some(Schema::Bool(_)) => {}
+                    None => {
+                        let known = object.properties.keys().map(String::as_str);
+                        let message = match suggest(key, known) {
+                            Some(known) => format!("unknown key, did you mean `{known}`?"),
+                            None => "unknown key".to_owned(),
+                        };
+                        problems.push(Problem::new(child, message));
+                    }
+                }
+            }
+        }
+        Value::Array(items) => {
+            let Some(SingleOrVec::Single(item)) =
+                schema.array.as_ref().and_then(|array| array.items.as_ref())
+            else {
+                return;
+            };
+            let Schema::Object(item) = item.as_ref() else {
+                return;
+            };
+            for (index, value) in items.iter().enumerate() {
+                walk(
+                    value,
+                    item,
+                    definitions,
+                    &format!("{path}[{index}]"),
+                    problems,
+                );
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Follows references and `Option`/default wrappers down to the schema describing the value
+fn resolve<'a>(
+    schema: &'a SchemaObject,
+    definitions: &'a BTreeMap<String, Schema>,
+) -> Option<&'a SchemaObject> {
+    if let Some(reference) = &schema.reference {
+        let name = reference.trim_start_matches("#/definitions/");
+        return match definitions.get(name)? {
+            Schema::Object(schema) => resolve(schema, definitions),
+            Schema::Bool(_) => None,
+        };
+    }
+    if schema.object.is_some() || schema.array.is_some() {
+        return Some(schema);
+    }
+    let subschemas = schema.subschemas.as_ref()?;
+    subschemas
+        .all_of
+        .iter()
+        .chain(&subschemas.any_of)
+        .flatten()
+        .find_map(|schema| match schema {
+            Schema::Object(schema) => resolve(schema, definitions),
+            Schema::Bool(_) => None,
+        })
+}
+
+fn suggest<'a>(key: &str, known: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    known
+        .map(|candidate| (strsim::jaro_winkler(key, candidate), candidate))
+        .filter(|(similarity, _)| *similarity > 0.8)
+        .max_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(_, candidate)| candidate)
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+impl Config {
+    /// Checks everything that can be checked without reading secrets, returning
+    /// every problem found
+    ///
+    /// # Errors
+    /// If there is at least one problem
+    pub fn validate(&self) -> Result<(), Vec<Problem>> {
+        let mut problems = Vec::new();
+        if self.watch_interval == Some(0) {
+            problems.push(Problem::new("watch_interval", "must be positive"));
+        }
+        check_tailscale(&self.tailscale, "tailscale", &mut problems);
+        check_telegram(&self.telegram, "telegram", &mut problems);
+        check_telemetry(&self.telemetry, &mut problems);
+        if self.health.probe_timeout == 0 {
+            problems.push(Problem::new("health.probe_timeout", "must be positive"));
+        }
+        if let Some(tls) = &self.tls {
+            check_tls(tls, &mut problems);
+        }
+        check_listeners(self, &mut problems);
+        for (key, zero) in [
+            ("max_body_size", self.requests.max_body_size == 0),
+            ("timeout", self.requests.timeout == 0),
+            (
+                "concurrency_limit",
+                self.requests.concurrency_limit == Some(0),
+            ),
+        ] {
+            if zero {
+                problems.push(Problem::new(join("requests", key), "must be positive"));
+            }
+        }
+        check_history(self, &mut problems);
+        if self.archive.directory.as_str().is_empty() {
+            problems.push(Problem::new("archive.directory", "is required"));
+        }
+        if self.archive.max_size == 0 {
+            problems.push(Problem::new("archive.max_size", "must be positive"));
+        }
+        if self.metrics.address == Some(self.address) {
+            problems.push(Problem::new(
+                "metrics.address",
+                "must differ from address, unset it to serve metrics alongside the webhooks",
+            ));
+        }
+
+        let mut names = HashSet::new();
+        for (index, tailnet) in self.tailnets.iter().enumerate() {
+            let path = format!("tailnets[{index}]");
+            if tailnet.name.is_empty() || tailnet.name.contains('/') {
+                problems.push(Problem::new(
+                    join(&path, "name"),
+                    format!("{:?} is not a valid path segment", tailnet.name),
+                ));
+            } else if !names.insert(tailnet.name.as_str()) {
+                problems.push(Problem::new(
+                    join(&path, "name"),
+                    format!("tailnet {} is specified more than once", tailnet.name),
+                ));
+            }
+            check_tailscale(&tailnet.tailscale, &join(&path, "tailscale"), &mut problems);
+            check_telegram(&tailnet.telegram, &join(&path, "telegram"), &mut problems);
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+}
+
+fn check_tailscale(tailscale: &Tailscale, path: &str, problems: &mut Vec<Problem>) {
+    check_secret(
+        tailscale.secret.as_ref(),
+        tailscale.secret_file.as_ref(),
+        &tailscale.file_format,
+        tailscale.secret_key.as_ref(),
+        path,
+        problems,
+    );
+    if let Some(api) = &tailscale.api {
+        check_api(api, &join(path, "api"), problems);
+    }
+}
+
+fn check_api(api: &TailscaleApi, path: &str, problems: &mut Vec<Problem>) {
+    check_secret(
+        api.secret.as_ref(),
+        api.secret_file.as_ref(),
+        &api.file_format,
+        api.secret_key.as_ref(),
+        path,
+        problems,
+    );
+    check_url(&api.base_url, &join(path, "base_url"), problems);
+    if api.key_expiry_interval == 0 {
+        problems.push(Problem::new(
+            join(path, "key_expiry_interval"),
+            "must be positive",
+        ));
+    }
+}
+
+fn check_telegram(telegram: &Telegram, path: &str, problems: &mut Vec<Problem>) {
+    check_secret(
+        telegram.secret.as_ref(),
+        telegram.secret_file.as_ref(),
+        &telegram.file_format,
+        telegram.secret_key.as_ref(),
+        path,
+        problems,
+    );
+    if telegram.chat_id.is_none() {
+        problems.push(Problem::new(join(path, "chat_id"), "is required"));
+    }
+    check_url(&telegram.api_url, &join(path, "api_url"), problems);
+}
+
+fn check_telemetry(telemetry: &Telemetry, problems: &mut Vec<Problem>) {
+    if !(0.0..=1.0).contains(&telemetry.sample_ratio) {
+        problems.push(Problem::new(
+            "telemetry.sample_ratio",
+            "must be between 0 and 1",
+        ));
+    }
+    if let Some(endpoint) = &telemetry.endpoint {
+        check_url(endpoint, "telemetry.endpoint", problems);
+    }
+}
+
+fn check_listeners(config: &Config, problems: &mut Vec<Problem>) {
+    if !config.listeners.is_empty() && config.metrics.address.is_some() {
+        problems.push(Problem::new(
+            "metrics.address",
+            "is ignored with listeners, add a listener with services = [\"Metrics\"] instead",
+        ));
+    }
+    let mut addresses = HashSet::new();
+    for (index, listener) in config.listeners.iter().enumerate() {
+        let path = format!("listeners[{index}]");
+        if !addresses.insert(&listener.address) {
+            problems.push(Problem::new(
+                join(&path, "address"),
+                format!("{} is specified more than once", listener.address),
+            ));
+        }
+        if listener.services.is_empty() {
+            problems.push(Problem::new(join(&path, "services"), "must not be empty"));
+        }
+        let unix = matches!(listener.address, ListenAddress::Unix(_));
+        for (key, value) in [
+            ("mode", &listener.mode),
+            ("owner", &listener.owner),
+            ("group", &listener.group),
+        ] {
+            if value.is_some() && !unix {
+                problems.push(Problem::new(
+                    join(&path, key),
+                    "is only used with unix: addresses",
+                ));
+            }
+        }
+        if let Some(mode) = &listener.mode {
+            if u32::from_str_radix(mode, 8).map_or(true, |mode| mode > 0o777) {
+                problems.push(Problem::new(
+                    join(&path, "mode"),
+                    format!("{mode:?} is not an octal mode like \"660\""),
+                ));
+            }
+        }
+    }
+}
+
+fn check_tls(tls: &Tls, problems: &mut Vec<Problem>) {
+    if tls.cert_file.as_str().is_empty() {
+        problems.push(Problem::new("tls.cert_file", "is required"));
+    }
+    if tls.key_file.as_str().is_empty() {
+        problems.push(Problem::new("tls.key_file", "is required"));
+    }
+    if tls.reload_interval == 0 {
+        problems.push(Problem::new("tls.reload_interval", "must be positive"));
+    }
+}
+
+fn check_history(config: &Config, problems: &mut Vec<Problem>) {
+    let history = &config.history;
+    if history.path.as_str().is_empty() {
+        problems.push(Problem::new("history.path", "is required"));
+    }
+    if history.retention_days == Some(0) {
+        problems.push(Problem::new("history.retention_days", "must be positive"));
+    }
+    if history.max_events == Some(0) {
+        problems.push(Problem::new("history.max_events", "must be positive"));
+    }
+    if history.prune_interval == 0 {
+        problems.push(Problem::new("history.prune_interval", "must be positive"));
+    }
+    let dashboard = &config.dashboard;
+    if dashboard.enabled && !history.enabled {
+        problems.push(Problem::new(
+            "dashboard.enabled",
+            "requires history.enabled, as the dashboard shows the history",
+        ));
+    }
+    if dashboard.enabled && dashboard.auth.is_none() {
+        problems.push(Problem::new("dashboard.auth", "is required"));
+    }
+    if let Some(Auth::Basic { username, .. }) = &dashboard.auth {
+        if username.is_empty() || username.contains(':') {
+            problems.push(Problem::new(
+                "dashboard.auth.Basic.username",
+                "must be non-empty and without `:`",
+            ));
+        }
+    }
+    if config.api.token.is_some() && !history.enabled {
+        problems.push(Problem::new(
+            "api.token",
+            "is unused as the API serves the history, which is disabled",
+        ));
+    }
+}
+
+fn check_secret(
+    secret: Option<&SecretSource>,
+    secret_file: Option<&Utf8PathBuf>,
+    format: &Format,
+    key: Option<&String>,
+    path: &str,
+    problems: &mut Vec<Problem>,
+) {
+    match (secret, secret_file) {
+        (None, None) => problems.push(Problem::new(
+            path,
+            "one of secret or secret_file is required",
+        )),
+        (Some(_), Some(_)) => problems.push(Problem::new(
+            path,
+            "secret and secret_file are mutually exclusive",
+        )),
+        _ => {}
+    }
+    if key.is_some() && *format == Format::Plain {
+        problems.push(Problem::new(
+            join(path, "secret_key"),
+            "is only used with the Env, Json and Yaml file formats",
+        ));
+    }
+}
+
+fn check_url(url: &str, path: &str, problems: &mut Vec<Problem>) {
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        problems.push(Problem::new(path, "must be an http:// or https:// URL"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Listener, Tailnet};
+    use serde_json::json;
+
+    #[test]
+    fn suggests_known_keys() {
+        let document = json!({
+            "telegram": {"chat_idd": -1, "bogus": true},
+            "tailnets": [{"name": "corp", "tailscale": {"api": {"cache_tll": 1}}}],
+        });
+
+        let problems: Vec<_> = unknown_keys(&document)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        assert_eq!(
+            problems,
+            vec![
+                "tailnets[0].tailscale.api.cache_tll: unknown key, did you mean `cache_ttl`?",
+                "telegram.bogus: unknown key",
+                "telegram.chat_idd: unknown key, did you mean `chat_id`?",
+            ]
+        );
+    }
+
+    #[test]
+    fn accepts_known_keys() {
+        let document = serde_json::to_value(Config::example()).unwrap_or_default();
+        assert_eq!(unknown_keys(&document), Vec::new());
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let mut config = Config::example();
+        config.telegram.chat_id = None;
+        config.tailscale.secret = Some(SecretSource::Env("TAILSCALE".to_owned()));
+        config.tailnets = vec![
+            Tailnet {
+                name: "a/b".to_owned(),
+                ..Tailnet::default()
+            },
+            Tailnet {
+                name: "corp".to_owned(),
+                ..Config::example().tailnet("corp")
+            },
+            Tailnet {
+                name: "corp".to_owned(),
+                ..Config::example().tailnet("corp")
+            },
+        ];
+
+        let problems: Vec<_> = config
+            .validate()
+            .unwrap_err()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        assert_eq!(
+            problems,
+            vec![
+                "tailscale: secret and secret_file are mutually exclusive",
+                "telegram.chat_id: is required",
+                "tailnets[0].name: \"a/b\" is not a valid path segment",
+                "tailnets[0].tailscale: one of secret or secret_file is required",
+                "tailnets[0].telegram: one of secret or secret_file is required",
+                "tailnets[0].telegram.chat_id: is required",
+                "tailnets[2].name: tailnet corp is specified more than once",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_telemetry() {
+        let mut config = Config::example();
+        config.telemetry.sample_ratio = 1.5;
+        config.telemetry.endpoint = Some("localhost:4317".to_owned());
+
+        let problems: Vec<_> = config
+            .validate()
+            .unwrap_err()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        assert_eq!(
+            problems,
+            vec![
+                "telemetry.sample_ratio: must be between 0 and 1",
+                "telemetry.endpoint: must be an http:// or https:// URL",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_listeners() {
+        let mut config = Config::example();
+        config.metrics.address = Some("127.0.0.1:9090".parse().unwrap());
+        let listener = Listener {
+            address: "unix:/run/tailforward.sock".parse().unwrap(),
+            mode: Some("999".to_owned()),
+            ..Listener::default()
+        };
+        config.listeners = vec![
+            listener.clone(),
+            listener,
+            Listener {
+                owner: Some("nginx".to_owned()),
+                services: Vec::new(),
+                ..Listener::default()
+            },
+        ];
+
+        let problems: Vec<_> = config
+            .validate()
+            .unwrap_err()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        assert_eq!(
+            problems,
+            vec![
+                "metrics.address: is ignored with listeners, add a listener with services = [\"Metrics\"] instead",
+                "listeners[0].mode: \"999\" is not an octal mode like \"660\"",
+                "listeners[1].address: unix:/run/tailforward.sock is specified more than once",
+                "listeners[1].mode: \"999\" is not an octal mode like \"660\"",
+                "listeners[2].services: must not be empty",
+                "listeners[2].owner: is only used with unix: addresses",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_history() {
+        let mut config = Config::example();
+        config.history.retention_days = Some(0);
+        config.api.token = Some(SecretSource::Env("TOKEN".to_owned()));
+        config.dashboard.enabled = true;
+        config.archive.max_size = 0;
+
+        let problems: Vec<_> = config
+            .validate()
+            .unwrap_err()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        assert_eq!(
+            problems,
+            vec![
+                "history.retention_days: must be positive",
+                "dashboard.enabled: requires history.enabled, as the dashboard shows the history",
+                "dashboard.auth: is required",
+                "api.token: is unused as the API serves the history, which is disabled",
+                "archive.max_size: must be positive",
+            ]
+        );
+    }
+
+    #[test]
+    fn accepts_example() {
+        assert_eq!(Config::example().validate(), Ok(()));
+    }
+
+    impl Config {
+        fn tailnet(self, name: &str) -> Tailnet {
+            Tailnet {
+                name: name.to_owned(),
+                tailnet: None,
+                tailscale: self.tailscale,
+                telegram: self.telegram,
+            }
+        }
+    }
+}
@@ -0,0 +1,183 @@
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+mod common;
+
+use chrono::Utc;
+use common::{signed, spawn_app};
+use serde_json::Value;
+use tailforward::config::Application;
+use tailforward::history::Store;
+use tailforward::State;
+
+fn config() -> Application {
+    let mut config = common::config();
+    config.api_token = Some("token".to_owned().into());
+    config
+}
+
+#[tokio::test]
+async fn stores_and_queries_events() {
+    // Arrange
+    let addr = spawn_app(State::new(config()).with_history(Store::in_memory().unwrap())).await;
+    let client = reqwest::Client::new();
+    let body = format!(
+        r#"[{{"timestamp":"{0}","version":1,"type":"nodeKeyExpired","tailnet":"example.com","message":"Node key of laptop expired"}},
+            {{"timestamp":"{0}","version":1,"type":"nodeCreated","tailnet":"example.com","message":"Node phone created"}}]"#,
+        Utc::now().to_rfc3339()
+    );
+    client
+        .post(format!("http://{addr}/tailscale-webhook"))
+        .header("Content-Type", "application/json")
+        .header("Tailscale-Webhook-Signature", signed("tail", &body))
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Act
+    let response = client
+        .get(format!(
+            "http://{addr}/api/events?type=nodeKeyExpired&q=laptop"
+        ))
+        .bearer_auth("token")
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert!(response.status().is_success());
+    let body: Value = response.json().await.unwrap();
+    let events = body["events"].as_array().unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["event"]["type"], "nodeKeyExpired");
+    assert_eq!(events[0]["endpoint"], "");
+    assert_eq!(events[0]["deliveries"][0]["sink"], "telegram");
+    // No chat ID is configured
+    assert_eq!(events[0]["deliveries"][0]["outcome"], "failed");
+}
+
+#[tokio::test]
+async fn exports_events() {
+    // Arrange
+    let addr = spawn_app(State::new(config()).with_history(Store::in_memory().unwrap())).await;
+    let client = reqwest::Client::new();
+    let body = format!(
+        r#"[{{"timestamp":"{0}","version":1,"type":"nodeCreated","tailnet":"example.com","message":"Node phone created","data":{{"nodeID":"n1","deviceName":"phone"}}}},
+            {{"timestamp":"{0}","version":1,"type":"nodeCreated","tailnet":"example.com","message":"Node tablet created"}}]"#,
+        Utc::now().to_rfc3339()
+    );
+    client
+        .post(format!("http://{addr}/tailscale-webhook"))
+        .header("Content-Type", "application/json")
+        .header("Tailscale-Webhook-Signature", signed("tail", &body))
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Act
+    let csv = client
+        .get(format!("http://{addr}/api/export/csv?type=nodeCreated"))
+        .bearer_auth("token")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let jsonl = client
+        .get(format!("http://{addr}/api/export/jsonl?q=tablet"))
+        .bearer_auth("token")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let unknown = client
+        .get(format!("http://{addr}/api/export/xml"))
+        .bearer_auth("token")
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert!(csv.status().is_success());
+    assert_eq!(csv.headers()["Content-Type"], "text/csv; charset=utf-8");
+    let csv = csv.text().await.unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("id,received,"));
+    assert!(lines[0]
+        .ends_with(",data.nodeID,data.deviceName,data.managedBy,data.actor,data.url,data.other"));
+    assert!(lines[1].contains(",Node phone created,failed,n1,phone,,,,"));
+    assert!(lines[2].contains(",Node tablet created,"));
+    assert!(jsonl.status().is_success());
+    let jsonl = jsonl.text().await.unwrap();
+    let lines: Vec<Value> = jsonl
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["message"], "Node tablet created");
+    assert_eq!(lines[0]["data.nodeID"], Value::Null);
+    assert_eq!(unknown.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn requires_token() {
+    // Arrange
+    let addr = spawn_app(State::new(config()).with_history(Store::in_memory().unwrap())).await;
+    let client = reqwest::Client::new();
+
+    // Act
+    let anonymous = client
+        .get(format!("http://{addr}/api/events"))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let wrong = client
+        .get(format!("http://{addr}/api/events/1"))
+        .bearer_auth("nekot")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let missing = client
+        .get(format!("http://{addr}/api/events/1"))
+        .bearer_auth("token")
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_eq!(anonymous.status(), reqwest::StatusCode::UNAUTHORIZED);
+    assert_eq!(wrong.status(), reqwest::StatusCode::UNAUTHORIZED);
+    assert_eq!(missing.status(), reqwest::StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn reads_token_from_documented_variable() {
+    // Arrange
+    let file = std::env::temp_dir().join(format!("tailforward-api-{}.toml", std::process::id()));
+    std::fs::write(
+        &file,
+        "[tailscale]\nsecret = \"literal:tail\"\n\
+         [telegram]\nsecret = \"literal:tele\"\nchat_id = -1\n\
+         [history]\nenabled = true\n\
+         [api]\ntoken = \"env:TAILFORWARD_API_TOKEN\"\n",
+    )
+    .unwrap();
+
+    // Act
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_tailforward"))
+        .arg("--config")
+        .arg(&file)
+        .arg("check-config")
+        .env("TAILFORWARD_API_TOKEN", "s3cr3t")
+        .output()
+        .expect("Failed to run tailforward");
+    std::fs::remove_file(&file).unwrap();
+
+    // Assert
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.starts_with("Configuration is valid"));
+    assert!(!stdout.contains("TAILFORWARD_API_TOKEN"));
+}
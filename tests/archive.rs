@@ -0,0 +1,125 @@
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+mod common;
+
+use camino::Utf8PathBuf;
+use chrono::Utc;
+use common::{signed, spawn_app};
+use serde_json::Value;
+use tailforward::archive::{Archive, CURRENT};
+use tailforward::config::Application;
+use tailforward::history::{Outcome, Query, Store};
+use tailforward::State;
+
+fn config() -> Application {
+    let mut config = common::config();
+    config.base.archive.enabled = true;
+    // Lets `/readyz` detail the archive sink
+    config.api_token = Some("token".to_owned().into());
+    config
+}
+
+#[tokio::test]
+async fn archives_verified_events() {
+    // Arrange
+    let directory = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+        .unwrap()
+        .join(format!("tailforward-archive-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    let archive = Archive::open(&directory).unwrap();
+    let addr = spawn_app(State::new(config()).with_archive(archive)).await;
+    let client = reqwest::Client::new();
+    let body = format!(
+        r#"[{{"timestamp":"{}","version":1,"type":"nodeKeyExpired","tailnet":"example.com","message":"Node key of laptop expired"}}]"#,
+        Utc::now().to_rfc3339()
+    );
+
+    // Act
+    client
+        .post(format!("http://{addr}/tailscale-webhook"))
+        .header("Content-Type", "application/json")
+        .header("Tailscale-Webhook-Signature", signed("tail", &body))
+        .body(body.clone())
+        .send()
+        .await
+        .expect("Failed to execute request");
+    client
+        .post(format!("http://{addr}/tailscale-webhook"))
+        .header("Content-Type", "application/json")
+        .header("Tailscale-Webhook-Signature", signed("wrong", &body))
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let readiness: Value = client
+        .get(format!("http://{addr}/readyz"))
+        .bearer_auth("token")
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    let archived = std::fs::read_to_string(directory.join(CURRENT)).unwrap();
+    let lines: Vec<Value> = archived
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["event"]["message"], "Node key of laptop expired");
+    assert_eq!(lines[0]["endpoint"], "");
+    assert_eq!(readiness["sinks"]["archive"]["status"], "ok");
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[tokio::test]
+async fn keeps_events_sent_again_once() {
+    // Arrange
+    let directory = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+        .unwrap()
+        .join(format!("tailforward-archive-again-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    let archive = Archive::open(&directory).unwrap();
+    let mut config = config();
+    // Nothing listens there, so every delivery fails
+    config.endpoint.telegram.api_url = "http://127.0.0.1:1".to_owned();
+    let state = State::new(config)
+        .with_archive(archive)
+        .with_history(Store::in_memory().unwrap());
+    let history = state.history.clone().unwrap();
+    let addr = spawn_app(state).await;
+    let client = reqwest::Client::new();
+    let body = format!(
+        r#"[{{"timestamp":"{}","version":1,"type":"nodeKeyExpired","tailnet":"example.com","message":"Node key of laptop expired"}}]"#,
+        Utc::now().to_rfc3339()
+    );
+
+    // Act
+    let mut statuses = Vec::new();
+    for _ in 0..2 {
+        let response = client
+            .post(format!("http://{addr}/tailscale-webhook"))
+            .header("Content-Type", "application/json")
+            .header("Tailscale-Webhook-Signature", signed("tail", &body))
+            .body(body.clone())
+            .send()
+            .await
+            .expect("Failed to execute request");
+        statuses.push(response.status());
+    }
+
+    // Assert
+    assert!(statuses.iter().all(reqwest::StatusCode::is_server_error));
+    let archived = std::fs::read_to_string(directory.join(CURRENT)).unwrap();
+    assert_eq!(archived.lines().count(), 1);
+    let records = history.query(&Query::default()).unwrap();
+    assert_eq!(records.len(), 1);
+    let outcomes: Vec<_> = records[0]
+        .deliveries
+        .iter()
+        .map(|delivery| delivery.outcome)
+        .collect();
+    assert_eq!(outcomes, [Outcome::Failed, Outcome::Failed]);
+    std::fs::remove_dir_all(directory).unwrap();
+}
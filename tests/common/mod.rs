@@ -0,0 +1,38 @@
+//! Fixtures shared by the integration tests
+#![allow(dead_code, clippy::unwrap_used)]
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::future::IntoFuture;
+use std::net::SocketAddr;
+use tailforward::config::{new_config_with_secrets, Application};
+use tailforward::State;
+use tokio::net::TcpListener;
+
+/// Default endpoint with `tail` as webhook secret and `tele` as bot token
+pub fn config() -> Application {
+    new_config_with_secrets("tail".to_owned().into(), "tele".to_owned().into()).unwrap()
+}
+
+/// `Tailscale-Webhook-Signature` header of a body signed now
+pub fn signed(secret: &str, body: &str) -> String {
+    let timestamp = Utc::now().timestamp();
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("{timestamp}.{body}").as_bytes());
+    let signature = hex::encode(mac.finalize().into_bytes());
+    format!("t={timestamp},v1={signature}")
+}
+
+/// Serves every route of the app on a free local port
+pub async fn spawn_app(state: State) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = tailforward::setup_app(state).unwrap();
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .into_future();
+    tokio::spawn(server);
+    addr
+}
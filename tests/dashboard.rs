@@ -0,0 +1,118 @@
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+mod common;
+
+use chrono::Utc;
+use common::spawn_app;
+use tailforward::config::{Application, Credentials};
+use tailforward::history::{Outcome, Store};
+use tailforward::models::Event;
+use tailforward::State;
+
+fn config() -> Application {
+    let mut config = common::config();
+    config.base.history.enabled = true;
+    config.base.dashboard.enabled = true;
+    config.dashboard_auth = Some(Credentials::Basic {
+        username: "admin".to_owned(),
+        password: "hunter2".to_owned().into(),
+    });
+    config
+}
+
+fn store() -> Store {
+    let store = Store::in_memory().unwrap();
+    let event = Event {
+        timestamp: Utc::now(),
+        version: 1,
+        r#type: "nodeKeyExpired".to_owned(),
+        tailnet: "example.com".to_owned(),
+        message: "Node key of <laptop> expired".to_owned(),
+        data: None,
+    };
+    let ids = store.insert(Utc::now(), "", "k1", &[event]).unwrap();
+    store
+        .record_delivery(&ids, "telegram", Outcome::Failed, Some("boom"))
+        .unwrap();
+    store
+}
+
+#[tokio::test]
+async fn requires_credentials() {
+    // Arrange
+    let addr = spawn_app(State::new(config()).with_history(store())).await;
+    let client = reqwest::Client::new();
+
+    // Act
+    let anonymous = client
+        .get(format!("http://{addr}/dashboard"))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let wrong = client
+        .get(format!("http://{addr}/dashboard"))
+        .basic_auth("admin", Some("hunter3"))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_eq!(anonymous.status(), reqwest::StatusCode::UNAUTHORIZED);
+    assert!(anonymous.headers()["WWW-Authenticate"]
+        .to_str()
+        .unwrap()
+        .starts_with("Basic"));
+    assert_eq!(wrong.status(), reqwest::StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn shows_dead_letters_and_replays_them() {
+    // Arrange
+    let addr = spawn_app(State::new(config()).with_history(store())).await;
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    // Act
+    let page = client
+        .get(format!("http://{addr}/dashboard"))
+        .basic_auth("admin", Some("hunter2"))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let replay = client
+        .post(format!("http://{addr}/dashboard/replay/1"))
+        .basic_auth("admin", Some("hunter2"))
+        .header("Origin", "https://evil.example")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let unknown = client
+        .post(format!("http://{addr}/dashboard/replay/1"))
+        .basic_auth("admin", Some("hunter2"))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let replayed = client
+        .post(format!("http://{addr}/dashboard/replay/1"))
+        .basic_auth("admin", Some("hunter2"))
+        .header("Origin", format!("http://{addr}"))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert!(page.status().is_success());
+    let html = page.text().await.unwrap();
+    assert!(html.contains("Node key of &lt;laptop&gt; expired"));
+    assert!(html.contains("Events whose last delivery failed: 1"));
+    assert!(html.contains("action=\"/dashboard/replay/1\""));
+    assert_eq!(replay.status(), reqwest::StatusCode::FORBIDDEN);
+    assert_eq!(unknown.status(), reqwest::StatusCode::FORBIDDEN);
+    assert_eq!(replayed.status(), reqwest::StatusCode::SEE_OTHER);
+    // No chat ID is configured, so the delivery fails again
+    assert_eq!(
+        replayed.headers()["Location"],
+        "/dashboard?replayed=1&delivered=false"
+    );
+}
@@ -0,0 +1,93 @@
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+use std::sync::LazyLock;
+use tailforward::config::{new_config_with_secrets, Application};
+use tailforward::listen::{serve, Socket};
+use tailforward_cfg::config::{Listener, Service};
+use tokio::sync::watch;
+
+static GLOBAL_CONFIG: LazyLock<Application> = LazyLock::new(|| {
+    new_config_with_secrets("tail".to_owned().into(), "tele".to_owned().into()).unwrap()
+});
+
+#[cfg(unix)]
+#[tokio::test]
+async fn serves_on_unix_socket() {
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Arrange
+    let path = std::env::temp_dir().join(format!("tailforward-{}.sock", std::process::id()));
+    let listener = Listener {
+        address: format!("unix:{}", path.display()).parse().unwrap(),
+        services: vec![Service::Webhooks],
+        mode: Some("600".to_owned()),
+        ..Listener::default()
+    };
+    let socket = Socket::bind(&listener).await.unwrap();
+    let state = tailforward::State::new(GLOBAL_CONFIG.to_owned());
+    let (stop, stopped) = watch::channel(false);
+    let server = tokio::spawn(serve(
+        socket,
+        tailforward::router(state, &listener.services),
+        None,
+        stopped,
+    ));
+
+    // Act
+    let mut responses = Vec::new();
+    for route in ["/ping", "/metrics"] {
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(
+                format!("GET {route} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        responses.push(response);
+    }
+    stop.send_replace(true);
+
+    // Assert
+    assert!(responses[0].starts_with("HTTP/1.1 200"), "{}", responses[0]);
+    assert!(responses[1].starts_with("HTTP/1.1 404"), "{}", responses[1]);
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert!(!path.with_extension("sock.tmp").exists());
+    server.await.unwrap().unwrap();
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn serves_only_listed_services() {
+    // Arrange
+    let listener = Listener {
+        address: "127.0.0.1:0".parse().unwrap(),
+        services: vec![Service::Metrics],
+        ..Listener::default()
+    };
+    let Socket::Tcp(tcp) = Socket::bind(&listener).await.unwrap() else {
+        unreachable!("TCP address")
+    };
+    let addr = tcp.local_addr().unwrap();
+    let state = tailforward::State::new(GLOBAL_CONFIG.to_owned());
+    let (_stop, stopped) = watch::channel(false);
+    tokio::spawn(serve(
+        Socket::Tcp(tcp),
+        tailforward::router(state, &listener.services),
+        None,
+        stopped,
+    ));
+
+    // Act
+    let metrics = reqwest::get(format!("http://{addr}/metrics"))
+        .await
+        .unwrap();
+    let ping = reqwest::get(format!("http://{addr}/ping")).await.unwrap();
+
+    // Assert
+    assert!(metrics.status().is_success());
+    assert_eq!(ping.status(), reqwest::StatusCode::NOT_FOUND);
+}
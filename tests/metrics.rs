@@ -0,0 +1,82 @@
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+mod common;
+
+use common::{config, signed, spawn_app};
+use tailforward::State;
+
+#[tokio::test]
+async fn counts_signature_failures() {
+    // Arrange
+    let addr = spawn_app(State::new(config())).await;
+    let client = reqwest::Client::new();
+    client
+        .post(format!("http://{addr}/tailscale-webhook"))
+        .header("Content-Type", "application/json")
+        .header("Tailscale-Webhook-Signature", "t=1,v1=00")
+        .body("[]")
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Act
+    let response = client
+        .get(format!("http://{addr}/metrics"))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert!(response.status().is_success());
+    let text = response.text().await.unwrap();
+    assert!(text.contains("tailforward_webhooks_total 1"));
+    assert!(
+        text.contains("tailforward_signature_failures_total{reason=\"timestamp_difference\"} 1")
+    );
+}
+
+#[tokio::test]
+async fn counts_malformed_bodies_apart_from_signature_failures() {
+    // Arrange
+    let addr = spawn_app(State::new(config())).await;
+    let client = reqwest::Client::new();
+    let body = r#"{"not":"a list"}"#;
+    for signature in [Some(signed("tail", body)), None] {
+        let mut request = client
+            .post(format!("http://{addr}/tailscale-webhook"))
+            .header("Content-Type", "application/json")
+            .body(body);
+        if let Some(signature) = signature {
+            request = request.header("Tailscale-Webhook-Signature", signature);
+        }
+        request.send().await.expect("Failed to execute request");
+    }
+
+    // Act
+    let text = reqwest::get(format!("http://{addr}/metrics"))
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(text.contains("tailforward_invalid_bodies_total 1"));
+    assert!(text.contains("tailforward_signature_failures_total{reason=\"missing_header\"} 1"));
+    assert!(!text.contains("reason=\"invalid_signature\""));
+}
+
+#[tokio::test]
+async fn is_not_served_alongside_webhooks_with_separate_address() {
+    // Arrange
+    let mut config = config();
+    config.base.metrics.address = Some("127.0.0.1:0".parse().unwrap());
+    let addr = spawn_app(State::new(config)).await;
+
+    // Act
+    let response = reqwest::get(format!("http://{addr}/metrics"))
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+}
@@ -1,9 +1,11 @@
-use once_cell::sync::Lazy;
+#![allow(clippy::unwrap_used, clippy::expect_used)]
 use std::future::IntoFuture;
+use std::sync::LazyLock;
 use tailforward::config::{new_config_with_secrets, Application};
+use tailforward_cfg::config::Service;
 use tokio::net::TcpListener;
 
-static GLOBAL_CONFIG: Lazy<Application> = Lazy::new(|| {
+static GLOBAL_CONFIG: LazyLock<Application> = LazyLock::new(|| {
     new_config_with_secrets("tail".to_owned().into(), "tele=gram".to_owned().into()).unwrap()
 });
 
@@ -27,8 +29,95 @@ async fn ping_works() {
     assert_eq!(Some(0), response.content_length());
 }
 
+#[tokio::test]
+async fn readyz_reports_only_counts_next_to_webhooks() {
+    // Arrange
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    spawn_app(GLOBAL_CONFIG.to_owned(), listener);
+
+    // Act
+    let response = reqwest::get(format!("http://{addr}/readyz"))
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert!(response.status().is_success());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!({ "status": "ok", "queue": { "depth": 0 }, "sinks": { "ok": 1 } })
+    );
+}
+
+#[tokio::test]
+async fn readyz_reports_checks_with_api_token() {
+    // Arrange
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut config = GLOBAL_CONFIG.to_owned();
+    config.api_token = Some("token".to_owned().into());
+    spawn_app(config, listener);
+    let client = reqwest::Client::new();
+
+    // Act
+    let response = client
+        .get(format!("http://{addr}/readyz"))
+        .bearer_auth("token")
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert!(response.status().is_success());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "ok");
+    assert_eq!(body["secrets"]["status"], "ok");
+    assert_eq!(body["queue"], serde_json::json!({ "depth": 0 }));
+    assert_eq!(body["sinks"]["telegram"]["status"], "ok");
+}
+
+#[tokio::test]
+async fn readyz_reports_checks_on_dedicated_listener() {
+    // Arrange
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let state = tailforward::State::new(GLOBAL_CONFIG.to_owned());
+    let app = tailforward::router(state, &[Service::Health, Service::Metrics]);
+    tokio::spawn(axum::serve(listener, app.into_make_service()).into_future());
+
+    // Act
+    let response = reqwest::get(format!("http://{addr}/readyz"))
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert!(response.status().is_success());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["secrets"]["status"], "ok");
+    assert_eq!(body["sinks"]["telegram"]["status"], "ok");
+}
+
+#[tokio::test]
+async fn healthz_works() {
+    // Arrange
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    spawn_app(GLOBAL_CONFIG.to_owned(), listener);
+
+    // Act
+    let response = reqwest::get(format!("http://{addr}/healthz"))
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert!(response.status().is_success());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "ok");
+}
+
 fn spawn_app(config: Application, listener: TcpListener) {
-    let app = tailforward::setup_app(config).unwrap();
+    let app = tailforward::setup_app(tailforward::State::new(config)).unwrap();
     let server = axum::serve(listener, app.into_make_service()).into_future();
-    let _ = tokio::spawn(server);
+    tokio::spawn(server);
 }
@@ -0,0 +1,86 @@
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+mod common;
+
+use common::{config, spawn_app};
+use reqwest::StatusCode;
+use std::net::SocketAddr;
+use tailforward::State;
+
+async fn post(addr: SocketAddr, content_type: &str, body: String) -> StatusCode {
+    reqwest::Client::new()
+        .post(format!("http://{addr}/tailscale-webhook"))
+        .header("Content-Type", content_type)
+        .header("X-Forwarded-For", "10.1.2.3")
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .status()
+}
+
+#[tokio::test]
+async fn rejects_oversized_body() {
+    // Arrange
+    let mut config = config();
+    config.base.requests.max_body_size = 16;
+    let addr = spawn_app(State::new(config)).await;
+
+    // Act
+    let status = post(addr, "application/json", "[]".repeat(16)).await;
+
+    // Assert
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn accepts_body_over_default_limit() {
+    // Arrange
+    let mut config = config();
+    config.base.requests.max_body_size = 4 * 1024 * 1024;
+    let addr = spawn_app(State::new(config)).await;
+
+    // Act
+    let status = post(addr, "application/json", "[]".repeat(1536 * 1024)).await;
+
+    // Assert
+    // Let through, to fail on the missing signature
+    assert_ne!(status, StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn requires_json() {
+    // Arrange
+    let addr = spawn_app(State::new(config())).await;
+
+    // Act
+    let status = post(addr, "text/plain", "[]".to_owned()).await;
+
+    // Assert
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
+
+#[tokio::test]
+async fn allows_listed_addresses_behind_trusted_proxies() {
+    // Arrange
+    let mut config = config();
+    config.base.requests.allow = vec!["10.0.0.0/8".parse().unwrap()];
+    let direct = spawn_app(State::new(config.clone())).await;
+    config.base.requests.trusted_proxies = vec!["127.0.0.1/32".parse().unwrap()];
+    let proxied = spawn_app(State::new(config)).await;
+
+    // Act
+    let forbidden = post(direct, "application/json", "[]".to_owned()).await;
+    let allowed = post(proxied, "application/json", "[]".to_owned()).await;
+    let metrics = reqwest::get(format!("http://{direct}/metrics"))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(forbidden, StatusCode::FORBIDDEN);
+    // Let through, to fail on the missing signature
+    assert_ne!(allowed, StatusCode::FORBIDDEN);
+    assert!(metrics.contains("tailforward_rejections_total{reason=\"forbidden\"} 1"));
+}
@@ -0,0 +1,121 @@
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+mod common;
+
+use chrono::Utc;
+use common::{signed, spawn_app};
+use tailforward::config::{Application, Endpoint};
+use tailforward::State;
+use tailforward_cfg::config::{Tailscale, Telegram};
+
+fn config() -> Application {
+    let mut config = common::config();
+    config.tailnets.insert(
+        "corp".to_owned(),
+        Endpoint {
+            name: "corp".to_owned(),
+            tailnet: Some("corp.example.com".to_owned()),
+            tailscale_secret: "corp-secret".to_owned().into(),
+            telegram_secret: "tele".to_owned().into(),
+            tailscale: Tailscale::default(),
+            telegram: Telegram {
+                chat_id: Some(-1),
+                ..Telegram::default()
+            },
+            api: None,
+        },
+    );
+    config
+}
+
+#[tokio::test]
+async fn unknown_tailnet_is_not_found() {
+    // Arrange
+    let addr = spawn_app(State::new(config())).await;
+    let client = reqwest::Client::new();
+
+    // Act
+    let response = client
+        .post(format!("http://{addr}/tailscale-webhook/unknown"))
+        .header("Content-Type", "application/json")
+        .body("[]")
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn foreign_tailnet_is_rejected() {
+    // Arrange
+    let addr = spawn_app(State::new(config())).await;
+    let client = reqwest::Client::new();
+    let body = format!(
+        r#"[{{"timestamp":"{}","version":1,"type":"test","tailnet":"other.example.com","message":"test"}}]"#,
+        Utc::now().to_rfc3339()
+    );
+
+    // Act
+    let response = client
+        .post(format!("http://{addr}/tailscale-webhook/corp"))
+        .header("Content-Type", "application/json")
+        .header("Tailscale-Webhook-Signature", signed("corp-secret", &body))
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn default_endpoint_checks_tailnet() {
+    // Arrange
+    let mut config = config();
+    config.endpoint.tailnet = Some("example.com".to_owned());
+    let addr = spawn_app(State::new(config)).await;
+    let client = reqwest::Client::new();
+    let body = format!(
+        r#"[{{"timestamp":"{}","version":1,"type":"test","tailnet":"other.example.com","message":"test"}}]"#,
+        Utc::now().to_rfc3339()
+    );
+
+    // Act
+    let response = client
+        .post(format!("http://{addr}/tailscale-webhook"))
+        .header("Content-Type", "application/json")
+        .header("Tailscale-Webhook-Signature", signed("tail", &body))
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn default_secret_is_not_accepted_by_tailnet() {
+    // Arrange
+    let addr = spawn_app(State::new(config())).await;
+    let client = reqwest::Client::new();
+    let body = "[]";
+
+    // Act
+    let response = client
+        .post(format!("http://{addr}/tailscale-webhook/corp"))
+        .header("Content-Type", "application/json")
+        .header("Tailscale-Webhook-Signature", signed("tail", body))
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_eq!(
+        response.status(),
+        reqwest::StatusCode::INTERNAL_SERVER_ERROR
+    );
+}
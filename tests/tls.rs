@@ -0,0 +1,156 @@
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+use axum_server::tls_rustls::RustlsConfig;
+use camino::Utf8PathBuf;
+use rcgen::{BasicConstraints, CertificateParams, CertifiedKey, IsCa, KeyPair};
+use std::sync::LazyLock;
+use tailforward::config::{new_config_with_secrets, Application};
+use tailforward_cfg::config::{Tls, TlsVersion};
+
+static GLOBAL_CONFIG: LazyLock<Application> = LazyLock::new(|| {
+    new_config_with_secrets("tail".to_owned().into(), "tele".to_owned().into()).unwrap()
+});
+
+struct Certificates {
+    server: CertifiedKey,
+    ca: CertifiedKey,
+    client: CertifiedKey,
+}
+
+fn certificates() -> Certificates {
+    let server = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+
+    let mut params = CertificateParams::new(Vec::new()).unwrap();
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let key_pair = KeyPair::generate().unwrap();
+    let ca = CertifiedKey {
+        cert: params.self_signed(&key_pair).unwrap(),
+        key_pair,
+    };
+
+    let key_pair = KeyPair::generate().unwrap();
+    let client = CertifiedKey {
+        cert: CertificateParams::new(vec!["client".to_owned()])
+            .unwrap()
+            .signed_by(&key_pair, &ca.cert, &ca.key_pair)
+            .unwrap(),
+        key_pair,
+    };
+    Certificates { server, ca, client }
+}
+
+fn write(name: &str, contents: &str) -> Utf8PathBuf {
+    let path = std::env::temp_dir().join(format!("tailforward-tls-{}-{name}", std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    Utf8PathBuf::try_from(path).unwrap()
+}
+
+fn tls(name: &str, certificates: &Certificates) -> Tls {
+    Tls {
+        cert_file: write(&format!("{name}-cert"), &certificates.server.cert.pem()),
+        key_file: write(
+            &format!("{name}-key"),
+            &certificates.server.key_pair.serialize_pem(),
+        ),
+        ..Tls::default()
+    }
+}
+
+fn spawn_app(tls: &Tls) -> u16 {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let config = RustlsConfig::from_config(tailforward::tls::server_config(tls).unwrap());
+    let app = tailforward::setup_app(tailforward::State::new(GLOBAL_CONFIG.to_owned())).unwrap();
+    tokio::spawn(axum_server::from_tcp_rustls(listener, config).serve(app.into_make_service()));
+    port
+}
+
+fn client(certificates: &Certificates) -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        .use_rustls_tls()
+        .tls_built_in_root_certs(false)
+        .add_root_certificate(
+            reqwest::Certificate::from_pem(certificates.server.cert.pem().as_bytes()).unwrap(),
+        )
+}
+
+#[tokio::test]
+async fn serves_https() {
+    // Arrange
+    let certificates = certificates();
+    let port = spawn_app(&tls("https", &certificates));
+
+    // Act
+    let response = client(&certificates)
+        .build()
+        .unwrap()
+        .get(format!("https://localhost:{port}/ping"))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert!(response.status().is_success());
+}
+
+#[tokio::test]
+async fn enforces_min_version() {
+    // Arrange
+    let certificates = certificates();
+    let tls = Tls {
+        min_version: TlsVersion::Tls13,
+        ..tls("version", &certificates)
+    };
+    let port = spawn_app(&tls);
+
+    // Act
+    let result = client(&certificates)
+        .max_tls_version(reqwest::tls::Version::TLS_1_2)
+        .build()
+        .unwrap()
+        .get(format!("https://localhost:{port}/ping"))
+        .send()
+        .await;
+
+    // Assert
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn requires_client_certificate() {
+    // Arrange
+    let certificates = certificates();
+    let tls = Tls {
+        client_ca_file: Some(write("ca", &certificates.ca.cert.pem())),
+        ..tls("mtls", &certificates)
+    };
+    let port = spawn_app(&tls);
+    let url = format!("https://localhost:{port}/ping");
+    let identity = reqwest::Identity::from_pem(
+        format!(
+            "{}{}",
+            certificates.client.cert.pem(),
+            certificates.client.key_pair.serialize_pem()
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+
+    // Act
+    let anonymous = client(&certificates)
+        .build()
+        .unwrap()
+        .get(&url)
+        .send()
+        .await;
+    let authenticated = client(&certificates)
+        .identity(identity)
+        .build()
+        .unwrap()
+        .get(&url)
+        .send()
+        .await;
+
+    // Assert
+    assert!(anonymous.is_err());
+    assert!(authenticated.unwrap().status().is_success());
+}